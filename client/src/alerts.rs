@@ -0,0 +1,63 @@
+//! Alerts raised by the running program
+//!
+//! Bells and notifications are raised as soon as their message arrives
+//! instead of on the next render: browsers stop animation frames in hidden
+//! tabs, which is exactly when a notification is worth showing.
+
+use crate::bell::{Bell, BellOptions};
+use crate::notification;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+/// Shared between the terminal and the connection callbacks.
+pub struct Alerts {
+    bell: RefCell<Bell>,
+    /// Whether program notifications (OSC 9 / OSC 777) are shown.
+    notifications: Cell<bool>,
+    /// Window title, which names notifications that bring no title.
+    title: RefCell<Option<String>>,
+}
+
+impl Alerts {
+    pub fn new() -> Rc<Self> {
+        Rc::new(Alerts {
+            bell: RefCell::new(Bell::new()),
+            notifications: Cell::new(true),
+            title: RefCell::new(None),
+        })
+    }
+
+    pub fn set_bell(&self, options: BellOptions) {
+        self.bell.borrow_mut().set_options(options);
+    }
+
+    pub fn set_notifications(&self, enabled: bool) {
+        self.notifications.set(enabled);
+    }
+
+    pub fn set_title(&self, title: Option<String>) {
+        *self.title.borrow_mut() = title;
+    }
+
+    /// Ring the bell, named after the window title if it notifies.
+    pub fn ring(&self) {
+        self.bell.borrow_mut().ring(js_sys::Date::now(), self.title.borrow().as_deref());
+    }
+
+    /// Strength of the bell's visual flash at `now_ms`.
+    pub fn flash(&self, now_ms: f64) -> f32 {
+        self.bell.borrow().flash(now_ms)
+    }
+
+    /// Show a notification from the program (OSC 9 / OSC 777).
+    pub fn notify(&self, title: Option<&str>, body: &str) {
+        if !self.notifications.get() {
+            return;
+        }
+        let fallback = self.title.borrow();
+        let title = title.or(fallback.as_deref()).unwrap_or(notification::DEFAULT_TITLE);
+        if let Err(e) = notification::show(title, body, None) {
+            tracing::warn!("Notification failed: {:?}", e);
+        }
+    }
+}
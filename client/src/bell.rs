@@ -0,0 +1,117 @@
+//! Terminal bell
+//!
+//! A bell can flash the terminal, play a short beep, vibrate the device and,
+//! while the page is in the background, raise a system notification. Each
+//! effect is switched on separately; bells closer together than the rate
+//! limit are dropped so a runaway program cannot flood the user.
+
+use crate::notification;
+use wasm_bindgen::prelude::*;
+use web_sys::AudioContext;
+
+/// Bells closer together than this are dropped.
+const MIN_INTERVAL_MS: f64 = 200.0;
+/// Length of the visual flash.
+const FLASH_MS: f64 = 150.0;
+const BEEP_HZ: f32 = 880.0;
+const BEEP_SECONDS: f64 = 0.08;
+const BEEP_VOLUME: f32 = 0.1;
+const VIBRATE_MS: u32 = 50;
+
+/// Which effects a bell triggers.
+#[derive(Clone, Copy, Debug)]
+pub struct BellOptions {
+    pub visual: bool,
+    pub audio: bool,
+    pub vibrate: bool,
+    /// System notification, only while the page is in the background.
+    pub notify: bool,
+}
+
+impl Default for BellOptions {
+    fn default() -> Self {
+        BellOptions {
+            visual: true,
+            audio: false,
+            vibrate: false,
+            notify: false,
+        }
+    }
+}
+
+pub struct Bell {
+    options: BellOptions,
+    last_rung: Option<f64>,
+    /// Created on the first beep; browsers only allow audio after a user
+    /// gesture, which typing the command that rang has provided.
+    audio: Option<AudioContext>,
+}
+
+impl Bell {
+    pub fn new() -> Self {
+        Bell {
+            options: BellOptions::default(),
+            last_rung: None,
+            audio: None,
+        }
+    }
+
+    pub fn set_options(&mut self, options: BellOptions) {
+        self.options = options;
+    }
+
+    /// Ring unless rate limited. `title` names the notification.
+    pub fn ring(&mut self, now_ms: f64, title: Option<&str>) {
+        if self.last_rung.is_some_and(|last| now_ms - last < MIN_INTERVAL_MS) {
+            return;
+        }
+        self.last_rung = Some(now_ms);
+
+        if self.options.audio {
+            if let Err(e) = self.beep() {
+                tracing::warn!("Bell beep failed: {:?}", e);
+            }
+        }
+        if self.options.vibrate {
+            if let Some(window) = web_sys::window() {
+                window.navigator().vibrate_with_duration(VIBRATE_MS);
+            }
+        }
+        if self.options.notify {
+            // Replace rather than stack bell notifications
+            let title = title.unwrap_or(notification::DEFAULT_TITLE);
+            if let Err(e) = notification::show(title, "Bell", Some("noirtty-bell")) {
+                tracing::warn!("Bell notification failed: {:?}", e);
+            }
+        }
+    }
+
+    /// Strength of the visual flash at `now_ms`, fading from 1 to 0.
+    pub fn flash(&self, now_ms: f64) -> f32 {
+        if !self.options.visual {
+            return 0.0;
+        }
+        match self.last_rung {
+            Some(rung) if now_ms - rung < FLASH_MS => (1.0 - (now_ms - rung) / FLASH_MS) as f32,
+            _ => 0.0,
+        }
+    }
+
+    fn beep(&mut self) -> Result<(), JsValue> {
+        if self.audio.is_none() {
+            self.audio = Some(AudioContext::new()?);
+        }
+        let Some(ctx) = self.audio.as_ref() else {
+            return Ok(());
+        };
+        let oscillator = ctx.create_oscillator()?;
+        let gain = ctx.create_gain()?;
+        oscillator.frequency().set_value(BEEP_HZ);
+        gain.gain().set_value(BEEP_VOLUME);
+        oscillator.connect_with_audio_node(&gain)?;
+        gain.connect_with_audio_node(&ctx.destination())?;
+        oscillator.start()?;
+        oscillator.stop_with_when(ctx.current_time() + BEEP_SECONDS)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+//! Inflate side of the server's per-connection frame compression.
+//!
+//! The server keeps one raw deflate stream per connection, primed with the
+//! shared frame dictionary (a server frame in bincode and in JSON, generated by
+//! a server test) and sync-flushed after each message. We mirror its
+//! window by feeding the dictionary to the inflater as an uncompressed
+//! (stored) block before the first real message arrives.
+
+use flate2::{Decompress, FlushDecompress, Status};
+
+/// Dictionary shared with the server. Both builds must embed the same bytes.
+static FRAME_DICTIONARY: &[u8] = include_bytes!("../../static/frame.dict");
+
+/// Per-connection inflate stream.
+pub struct FrameDecompressor {
+    stream: Decompress,
+}
+
+impl FrameDecompressor {
+    pub fn new() -> Self {
+        let mut decompressor = FrameDecompressor {
+            stream: Decompress::new(false),
+        };
+        for chunk in FRAME_DICTIONARY.chunks(u16::MAX as usize) {
+            // Non-final stored block: header byte, LEN, NLEN, raw bytes.
+            let len = chunk.len() as u16;
+            let mut block = Vec::with_capacity(chunk.len() + 5);
+            block.push(0);
+            block.extend_from_slice(&len.to_le_bytes());
+            block.extend_from_slice(&(!len).to_le_bytes());
+            block.extend_from_slice(chunk);
+            let _ = decompressor.decompress(&block);
+        }
+        decompressor
+    }
+
+    /// Inflate one sync-flushed message.
+    pub fn decompress(&mut self, input: &[u8]) -> Option<Vec<u8>> {
+        let mut out = Vec::with_capacity(input.len() * 4 + 1024);
+        let mut consumed = 0;
+        loop {
+            if out.len() == out.capacity() {
+                out.reserve(out.capacity());
+            }
+            let before_in = self.stream.total_in();
+            let before_out = out.len();
+            let status = self
+                .stream
+                .decompress_vec(&input[consumed..], &mut out, FlushDecompress::Sync)
+                .ok()?;
+            consumed += (self.stream.total_in() - before_in) as usize;
+            let stalled = self.stream.total_in() == before_in && out.len() == before_out;
+            match status {
+                Status::StreamEnd => break,
+                Status::Ok | Status::BufError => {
+                    if consumed == input.len() && out.len() < out.capacity() {
+                        break;
+                    }
+                    if stalled && out.len() < out.capacity() {
+                        return None;
+                    }
+                }
+            }
+        }
+        Some(out)
+    }
+}
+
+impl Default for FrameDecompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -6,6 +6,8 @@
 pub struct InputHandler {
     /// Application cursor keys mode (DECCKM)
     application_cursor_keys: bool,
+    /// Application keypad mode (DECKPAM)
+    application_keypad: bool,
 }
 
 impl InputHandler {
@@ -13,6 +15,7 @@ impl InputHandler {
     pub fn new() -> Self {
         InputHandler {
             application_cursor_keys: false,
+            application_keypad: false,
         }
     }
 
@@ -21,6 +24,11 @@ impl InputHandler {
         self.application_cursor_keys = enabled;
     }
 
+    /// Set application keypad mode
+    pub fn set_application_keypad(&mut self, enabled: bool) {
+        self.application_keypad = enabled;
+    }
+
     /// Process a key event and return the bytes to send to the terminal
     pub fn process_key(
         &self,
@@ -38,6 +46,12 @@ impl InputHandler {
             }
         }
 
+        if self.application_keypad && !ctrl && !alt && !meta {
+            if let Some(seq) = self.keypad_key(code, key) {
+                return Some(seq);
+            }
+        }
+
         // Handle special keys
         match code {
             // Arrow keys
@@ -116,6 +130,27 @@ impl InputHandler {
         }
     }
 
+    /// Application keypad sequence (ESC O x). Like xterm, digits and the
+    /// decimal point are left alone while NumLock is on.
+    fn keypad_key(&self, code: &str, key: &str) -> Option<String> {
+        let numlock = key.chars().count() == 1;
+        let final_char = match code {
+            "NumpadEnter" => 'M',
+            "NumpadAdd" => 'k',
+            "NumpadSubtract" => 'm',
+            "NumpadMultiply" => 'j',
+            "NumpadDivide" => 'o',
+            "NumpadEqual" => 'X',
+            "NumpadDecimal" if !numlock => 'n',
+            _ if !numlock => {
+                let digit = code.strip_prefix("Numpad")?.parse::<u8>().ok().filter(|d| *d <= 9)?;
+                (b'p' + digit) as char
+            }
+            _ => return None,
+        };
+        Some(format!("\x1bO{}", final_char))
+    }
+
     /// Generate arrow key sequence
     fn arrow_key(&self, direction: char, ctrl: bool, alt: bool, shift: bool) -> String {
         let modifier = self.modifier_code(ctrl, alt, shift);
@@ -132,6 +167,14 @@ impl InputHandler {
         }
     }
 
+    /// Arrow keys standing in for scrolling `lines` (positive = up), for
+    /// alternate scroll mode
+    pub fn scroll_keys(&self, lines: i32) -> String {
+        let direction = if lines > 0 { 'A' } else { 'B' };
+        self.arrow_key(direction, false, false, false)
+            .repeat(lines.unsigned_abs() as usize)
+    }
+
     /// Generate special key sequence (Home, End, etc.)
     fn special_key(&self, key: char, ctrl: bool, alt: bool, shift: bool) -> String {
         let modifier = self.modifier_code(ctrl, alt, shift);
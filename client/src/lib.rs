@@ -2,16 +2,30 @@
 //!
 //! Modern terminal client using WebGPU for rendering and WebTransport for I/O.
 
+use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 
+mod alerts;
+mod bell;
+mod compression;
+mod quality;
 mod renderer;
 mod terminal;
 mod transport;
 mod input;
+mod mouse;
+mod notification;
 
 pub use renderer::Renderer;
 pub use terminal::Terminal;
 pub use transport::Transport;
+
+use alerts::Alerts;
+use bell::BellOptions;
+use mouse::{Modifiers, MouseReporter, Tracking};
+use quality::{AdaptiveQuality, LinkSample};
+use terminal::CursorShape;
+use transport::WebTransportEndpoint;
 pub use input::InputHandler;
 
 /// Initialize panic hook for better WASM debugging
@@ -28,12 +42,35 @@ pub struct NoirTTYWeb {
     renderer: Option<Renderer>,
     transport: Option<Transport>,
     input: InputHandler,
+    mouse: MouseReporter,
     pending_render_config: Option<RenderConfig>,
     pending_max_frames: Option<usize>,
     pending_min_interval_ms: Option<u32>,
+    compression: bool,
+    webtransport: Option<WebTransportEndpoint>,
+    quality: AdaptiveQuality,
+    /// Whether the page has focus; an unfocused cursor is drawn hollow.
+    focused: bool,
+    /// Blink phase origin, reset on input so the cursor stays solid while
+    /// typing.
+    blink_epoch: f64,
+    /// Cursor as last drawn, to redraw when only the blink phase changed.
+    drawn_cursor: Option<CursorShape>,
+    /// URI schemes hyperlinks may use to be opened, lowercase.
+    link_schemes: Vec<String>,
+    alerts: Rc<Alerts>,
+    /// Bell flash strength as last drawn, to redraw while it fades.
+    drawn_flash: f32,
     frame_count: u64,
 }
 
+/// Render rate suggested before a performance profile is set.
+const DEFAULT_MAX_FPS: u32 = 60;
+/// Half period of a blinking cursor.
+const CURSOR_BLINK_MS: f64 = 600.0;
+/// Hyperlink schemes opened unless the page configures its own list.
+const DEFAULT_LINK_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
 struct RenderConfig {
     font_size: f64,
     font_stack: String,
@@ -56,9 +93,19 @@ impl NoirTTYWeb {
             renderer: None,
             transport: None,
             input,
+            mouse: MouseReporter::new(),
             pending_render_config: None,
             pending_max_frames: None,
             pending_min_interval_ms: None,
+            compression: false,
+            webtransport: None,
+            quality: AdaptiveQuality::new(),
+            focused: true,
+            blink_epoch: 0.0,
+            drawn_cursor: None,
+            link_schemes: DEFAULT_LINK_SCHEMES.map(String::from).to_vec(),
+            alerts: Alerts::new(),
+            drawn_flash: 0.0,
             frame_count: 0,
         })
     }
@@ -116,42 +163,143 @@ impl NoirTTYWeb {
         Ok(())
     }
 
-    /// Connect to WebTransport server
+    /// Connect to the server, trying WebTransport first when configured and
+    /// falling back to the given WebSocket URL
     #[wasm_bindgen]
     pub async fn connect(&mut self, url: &str) -> Result<(), JsValue> {
-        let transport = Transport::connect(url).await?;
-        if let Some(max_frames) = self.pending_max_frames.take() {
-            transport.set_max_frames(max_frames);
-        }
-        if let Some(min_interval_ms) = self.pending_min_interval_ms.take() {
-            transport.send_quality(min_interval_ms)?;
+        let settings = self.quality.settings();
+        let max_frames = self
+            .pending_max_frames
+            .take()
+            .or(settings.map(|s| s.max_frames))
+            .unwrap_or(transport::DEFAULT_MAX_FRAMES);
+        // Endpoint URLs carry a one-time ticket, so they are used only once.
+        let webtransport = self.webtransport.take();
+        let transport = Transport::connect(
+            url,
+            webtransport.as_ref(),
+            max_frames,
+            self.compression,
+            self.alerts.clone(),
+        )
+        .await?;
+        let min_interval_ms = self
+            .pending_min_interval_ms
+            .take()
+            .or(settings.map(|s| s.min_interval_ms));
+        if let Some(min_interval_ms) = min_interval_ms {
+            transport.send_quality(min_interval_ms, settings.map(|s| s.compress))?;
         }
+        self.quality.reset_link();
         self.transport = Some(transport);
         Ok(())
     }
 
-    /// Limit the number of frames kept in the client queue (0 = unlimited).
+    /// Limit the number of unacknowledged frames the server may send (0 = unlimited).
     #[wasm_bindgen]
-    pub fn set_max_frames_in_queue(&mut self, max_frames: u32) {
+    pub fn set_max_frames_in_queue(&mut self, max_frames: u32) -> Result<(), JsValue> {
         let max_frames = max_frames as usize;
         if let Some(ref transport) = self.transport {
-            transport.set_max_frames(max_frames);
+            transport.set_max_frames(max_frames)?;
         } else {
             self.pending_max_frames = Some(max_frames);
         }
+        Ok(())
+    }
+
+    /// Offer a WebTransport endpoint for the next connect.
+    ///
+    /// `cert_hash` is the hex SHA-256 of the server certificate, for servers
+    /// using a short-lived self-signed certificate.
+    #[wasm_bindgen]
+    pub fn set_webtransport(&mut self, url: &str, cert_hash: Option<String>) {
+        let cert_hash = cert_hash.and_then(|hex| {
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+                .collect::<Option<Vec<u8>>>()
+        });
+        self.webtransport = Some(WebTransportEndpoint {
+            url: url.to_string(),
+            cert_hash,
+        });
+    }
+
+    /// Active transport ("webtransport", "websocket" or "disconnected").
+    #[wasm_bindgen]
+    pub fn transport_kind(&self) -> String {
+        self.transport
+            .as_ref()
+            .map(|t| t.kind())
+            .unwrap_or("disconnected")
+            .to_string()
+    }
+
+    /// Request deflate-compressed frames on the next connect.
+    #[wasm_bindgen]
+    pub fn set_compression(&mut self, enabled: bool) {
+        self.compression = enabled;
     }
 
     /// Throttle server frame rate (0 = no throttle).
     #[wasm_bindgen]
     pub fn set_frame_throttle_ms(&mut self, min_interval_ms: u32) -> Result<(), JsValue> {
         if let Some(ref transport) = self.transport {
-            transport.send_quality(min_interval_ms)?;
+            transport.send_quality(min_interval_ms, None)?;
         } else {
             self.pending_min_interval_ms = Some(min_interval_ms);
         }
         Ok(())
     }
 
+    /// Set the performance profile limits that adaptive quality stays
+    /// within. `max_frames` of undefined keeps the default credit window
+    /// (0 = unlimited). Returns the frame rate the render loop should use.
+    #[wasm_bindgen]
+    pub fn set_quality_ceiling(
+        &mut self,
+        max_fps: u32,
+        max_frames: Option<u32>,
+        min_interval_ms: u32,
+    ) -> Result<u32, JsValue> {
+        let max_frames = max_frames
+            .map(|n| n as usize)
+            .unwrap_or(transport::DEFAULT_MAX_FRAMES);
+        self.quality.set_ceiling(max_fps, max_frames, min_interval_ms);
+        self.apply_quality()
+    }
+
+    /// Enable or disable adaptation below the ceiling (on by default).
+    #[wasm_bindgen]
+    pub fn set_adaptive_quality(&mut self, enabled: bool) -> Result<u32, JsValue> {
+        self.quality.set_enabled(enabled);
+        self.apply_quality()
+    }
+
+    /// Measure the link and adjust quality; call periodically (e.g. every 2s)
+    /// while connected. Returns the frame rate the render loop should use.
+    #[wasm_bindgen]
+    pub fn adapt_quality(&mut self) -> Result<u32, JsValue> {
+        if let Some(ref transport) = self.transport {
+            let sample = LinkSample {
+                rtt_ms: transport.rtt_ms(),
+                jitter_ms: transport.jitter_ms(),
+                bytes_received: transport.bytes_received(),
+            };
+            let now = js_sys::Date::now();
+            if self.quality.tick(now, sample).is_some() {
+                return self.apply_quality();
+            }
+        }
+        Ok(self.quality.settings().map(|s| s.max_fps).unwrap_or(DEFAULT_MAX_FPS))
+    }
+
+    /// Current adaptive quality level (0 = profile ceiling).
+    #[wasm_bindgen]
+    pub fn quality_level(&self) -> u32 {
+        self.quality.level() as u32
+    }
+
     /// WebSocket connection state (0=connecting,1=open,2=closing,3=closed)
     #[wasm_bindgen]
     pub fn connection_state(&self) -> u16 {
@@ -179,6 +327,15 @@ impl NoirTTYWeb {
             .unwrap_or(0)
     }
 
+    /// Total bytes received by transport after decompression.
+    #[wasm_bindgen]
+    pub fn transport_bytes_decoded(&self) -> u64 {
+        self.transport
+            .as_ref()
+            .map(|t| t.bytes_decoded())
+            .unwrap_or(0)
+    }
+
     /// Total messages received by transport.
     #[wasm_bindgen]
     pub fn transport_messages_received(&self) -> u64 {
@@ -196,6 +353,39 @@ impl NoirTTYWeb {
         }
     }
 
+    /// Send a heartbeat ping; call periodically (e.g. every 2s).
+    #[wasm_bindgen]
+    pub fn heartbeat(&self) -> Result<(), JsValue> {
+        if let Some(ref transport) = self.transport {
+            transport.ping()?;
+        }
+        Ok(())
+    }
+
+    /// Smoothed round-trip time in ms (undefined until the first pong).
+    #[wasm_bindgen]
+    pub fn rtt_ms(&self) -> Option<f64> {
+        self.transport.as_ref().and_then(|t| t.rtt_ms())
+    }
+
+    /// Round-trip time jitter (mean deviation) in ms.
+    #[wasm_bindgen]
+    pub fn rtt_jitter_ms(&self) -> f64 {
+        self.transport.as_ref().map(|t| t.jitter_ms()).unwrap_or(0.0)
+    }
+
+    /// Time since the last frame arrived, in ms (undefined when disconnected).
+    #[wasm_bindgen]
+    pub fn last_frame_age_ms(&self) -> Option<f64> {
+        self.transport.as_ref().map(|t| t.last_frame_age_ms())
+    }
+
+    /// Time since the server was last heard from (frame or heartbeat), in ms.
+    #[wasm_bindgen]
+    pub fn heartbeat_age_ms(&self) -> Option<f64> {
+        self.transport.as_ref().map(|t| t.last_message_age_ms())
+    }
+
     /// Maximum surface dimension supported by the active renderer.
     #[wasm_bindgen]
     pub fn max_surface_dim(&self) -> u32 {
@@ -214,13 +404,30 @@ impl NoirTTYWeb {
         Ok(())
     }
 
+    /// Send raw bytes to the terminal unchanged (e.g. non-UTF-8 data).
+    #[wasm_bindgen]
+    pub fn send_input_bytes(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        if let Some(ref transport) = self.transport {
+            transport.send(data)?;
+        }
+        Ok(())
+    }
+
     /// Handle keyboard event
     #[wasm_bindgen]
     pub fn on_key(&mut self, code: &str, key: &str, ctrl: bool, alt: bool, meta: bool, shift: bool) -> Result<(), JsValue> {
+        self.blink_epoch = js_sys::Date::now();
+        let modes = self.terminal.modes();
+        self.input.set_application_cursor_keys(modes.app_cursor);
+        self.input.set_application_keypad(modes.app_keypad);
         if let Some(data) = self.input.process_key(code, key, ctrl, alt, meta, shift) {
             // LOCAL ECHO: Try to predict simple printable characters
-            // Only predict single-byte printable ASCII (no modifiers except shift)
-            if data.len() == 1 && !ctrl && !alt && !meta {
+            // Only predict single-byte printable ASCII (no modifiers except shift).
+            // Full-screen programs on the alternate screen treat keys as
+            // commands, so nothing is predicted there.
+            // Nor on a raw stream, where nothing would correct a misprediction.
+            let raw = self.transport.as_ref().is_some_and(|t| t.is_raw());
+            if data.len() == 1 && !ctrl && !alt && !meta && !modes.alt_screen && !raw {
                 if let Some(c) = data.chars().next() {
                     // write_char_speculative returns true if it handled the char
                     // This provides instant visual feedback before server response
@@ -228,6 +435,8 @@ impl NoirTTYWeb {
                 }
             }
 
+            self.terminal.scroll_to_bottom();
+
             // Always send to server - server is authoritative
             if let Some(ref transport) = self.transport {
                 transport.send(data.as_bytes())?;
@@ -236,6 +445,19 @@ impl NoirTTYWeb {
         Ok(())
     }
 
+    /// Tell the terminal whether the page has focus. Programs that enabled
+    /// focus reporting (1004) get `CSI I` / `CSI O` when it changes.
+    #[wasm_bindgen]
+    pub fn set_focused(&mut self, focused: bool) -> Result<(), JsValue> {
+        let changed = focused != self.focused;
+        self.focused = focused;
+        self.blink_epoch = js_sys::Date::now();
+        if changed && self.terminal.modes().focus_reporting {
+            self.send_input_bytes(if focused { b"\x1b[I" } else { b"\x1b[O" })?;
+        }
+        Ok(())
+    }
+
     /// Resize terminal
     #[wasm_bindgen]
     pub fn resize(&mut self, cols: u16, rows: u16) -> Result<(), JsValue> {
@@ -245,7 +467,8 @@ impl NoirTTYWeb {
         self.terminal.resize(cols, rows);
 
         if let Some(ref mut transport) = self.transport {
-            transport.send_resize(cols, rows)?;
+            let cell = self.renderer.as_ref().map(|renderer| renderer.cell_pixels());
+            transport.send_resize(cols, rows, cell)?;
         }
 
         if let Some(ref mut renderer) = self.renderer {
@@ -255,11 +478,32 @@ impl NoirTTYWeb {
         Ok(())
     }
 
-    /// Scroll terminal viewport (positive = scroll up)
+    /// Scroll terminal viewport (positive = scroll up) through scrollback
+    /// fetched from the server as needed, while the screen keeps updating.
+    /// On the alternate screen, which has no scrollback, this sends arrow
+    /// keys instead when the program left alternate scroll (1007) on and
+    /// does not track the mouse.
     #[wasm_bindgen]
     pub fn scroll(&mut self, delta: i32) -> Result<(), JsValue> {
+        let modes = self.terminal.modes();
+        if modes.alt_screen && modes.alternate_scroll && Tracking::from_modes(&modes) == Tracking::Off {
+            if delta != 0 {
+                self.input.set_application_cursor_keys(modes.app_cursor);
+                let keys = self.input.scroll_keys(delta);
+                self.send_input_bytes(keys.as_bytes())?;
+            }
+            return Ok(());
+        }
+        self.terminal.scroll_view(delta);
+        Ok(())
+    }
+
+    /// Scroll to the shell prompt `delta` prompts away (negative = earlier).
+    /// Needs shell integration (OSC 133).
+    #[wasm_bindgen]
+    pub fn jump_to_prompt(&mut self, delta: i32) -> Result<(), JsValue> {
         if let Some(ref transport) = self.transport {
-            transport.send_scroll(delta)?;
+            transport.send_jump_prompt(delta, self.terminal.view_top_line())?;
         }
         Ok(())
     }
@@ -277,47 +521,240 @@ impl NoirTTYWeb {
         self.resize(cols, rows)
     }
 
-    /// Handle mouse down
+    /// Handle mouse down (button 0 left, 1 middle, 2 right). Reported to
+    /// the program when it tracks the mouse, unless Shift is held; otherwise
+    /// starts a local selection. Returns true when reported.
     #[wasm_bindgen]
-    pub fn on_mouse_down(&mut self, x: u32, y: u32) {
-        if let Some(ref renderer) = self.renderer {
-            let (col, row) = renderer.pixel_to_cell(x, y);
-            self.terminal.start_selection(col, row);
+    pub fn on_mouse_down(
+        &mut self,
+        x: u32,
+        y: u32,
+        button: u8,
+        shift: bool,
+        alt: bool,
+        ctrl: bool,
+    ) -> Result<bool, JsValue> {
+        let Some(ref renderer) = self.renderer else {
+            return Ok(false);
+        };
+        let (col, row) = renderer.pixel_to_cell(x, y);
+        if !shift {
+            let modes = self.terminal.modes();
+            let mods = Modifiers { shift, alt, ctrl };
+            if let Some(report) = self.mouse.press(&modes, button, col, row, mods) {
+                self.terminal.clear_selection();
+                self.send_input_bytes(&report)?;
+                return Ok(true);
+            }
         }
+        self.terminal.start_selection(col, row);
+        Ok(false)
     }
 
-    /// Handle mouse move
+    /// Handle mouse move: drags and, with any-motion tracking, plain motion
+    /// go to the program; otherwise extends the local selection
     #[wasm_bindgen]
-    pub fn on_mouse_move(&mut self, x: u32, y: u32) {
-        if let Some(ref renderer) = self.renderer {
-            let (col, row) = renderer.pixel_to_cell(x, y);
+    pub fn on_mouse_move(&mut self, x: u32, y: u32, shift: bool, alt: bool, ctrl: bool) -> Result<(), JsValue> {
+        let Some(ref renderer) = self.renderer else {
+            return Ok(());
+        };
+        let (col, row) = renderer.pixel_to_cell(x, y);
+        if self.mouse.is_held() || !shift {
+            let modes = self.terminal.modes();
+            let mods = Modifiers { shift, alt, ctrl };
+            if let Some(report) = self.mouse.motion(&modes, col, row, mods) {
+                return self.send_input_bytes(&report);
+            }
+        }
+        if !self.mouse.is_held() {
             self.terminal.update_selection(col, row);
         }
+        Ok(())
     }
 
-    /// Handle mouse up
+    /// Handle mouse up: releases a reported press, else ends the selection
     #[wasm_bindgen]
-    pub fn on_mouse_up(&mut self) {
+    pub fn on_mouse_up(&mut self, x: u32, y: u32, shift: bool, alt: bool, ctrl: bool) -> Result<(), JsValue> {
         self.terminal.end_selection();
+        let Some(ref renderer) = self.renderer else {
+            return Ok(());
+        };
+        let (col, row) = renderer.pixel_to_cell(x, y);
+        let modes = self.terminal.modes();
+        let mods = Modifiers { shift, alt, ctrl };
+        if let Some(report) = self.mouse.release(&modes, col, row, mods) {
+            self.send_input_bytes(&report)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the running program tracks the mouse
+    #[wasm_bindgen]
+    pub fn mouse_tracking(&self) -> bool {
+        Tracking::from_modes(&self.terminal.modes()) != Tracking::Off
+    }
+
+    /// Wheel notches (positive = up) over a program that tracks the mouse,
+    /// unless Shift is held. Returns false when the page should scroll
+    /// instead.
+    #[wasm_bindgen]
+    pub fn on_wheel(&mut self, x: u32, y: u32, lines: i32, shift: bool, alt: bool, ctrl: bool) -> Result<bool, JsValue> {
+        let Some(ref renderer) = self.renderer else {
+            return Ok(false);
+        };
+        if shift {
+            return Ok(false);
+        }
+        let (col, row) = renderer.pixel_to_cell(x, y);
+        let modes = self.terminal.modes();
+        let mods = Modifiers { shift, alt, ctrl };
+        match self.mouse.wheel(&modes, lines, col, row, mods) {
+            Some(report) => {
+                self.send_input_bytes(&report)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Track the pointer for hyperlink hover. Returns true when it is over a
+    /// link that can be opened.
+    #[wasm_bindgen]
+    pub fn on_mouse_hover(&mut self, x: u32, y: u32) -> bool {
+        let hover = self
+            .renderer
+            .as_ref()
+            .map(|renderer| renderer.pixel_to_cell(x, y))
+            .filter(|&(col, row)| self.allowed_link(col, row).is_some());
+        self.terminal.set_hover(hover);
+        hover.is_some()
+    }
+
+    /// Pointer left the terminal
+    #[wasm_bindgen]
+    pub fn on_mouse_leave(&mut self) {
+        self.terminal.set_hover(None);
+    }
+
+    /// URI of the hyperlink at a pixel position, if its scheme is allowed.
+    /// The page opens it on Ctrl/Cmd+click.
+    #[wasm_bindgen]
+    pub fn link_at(&self, x: u32, y: u32) -> Option<String> {
+        let (col, row) = self.renderer.as_ref()?.pixel_to_cell(x, y);
+        self.allowed_link(col, row).map(str::to_string)
+    }
+
+    /// Select the output of the shell command at a pixel position and ask
+    /// the server for all of it, including lines scrolled out of view; the
+    /// text arrives through `take_clipboard`. Returns false when there is no
+    /// command with output there.
+    #[wasm_bindgen]
+    pub fn select_command_output(&mut self, x: u32, y: u32) -> Result<bool, JsValue> {
+        let Some(ref renderer) = self.renderer else {
+            return Ok(false);
+        };
+        let (_, row) = renderer.pixel_to_cell(x, y);
+        let Some(command) = self.terminal.command_at(row) else {
+            return Ok(false);
+        };
+        if command.output_end <= command.output_start {
+            return Ok(false);
+        }
+        let (start, end) = (command.output_start, command.output_end);
+        self.terminal.select_rows(start, end);
+        if let Some(ref transport) = self.transport {
+            transport.send_copy_output(row, self.terminal.view_top_line() + row as u64)?;
+        }
+        Ok(true)
+    }
+
+    /// Set the URI schemes hyperlinks may use (e.g. `["https", "mailto"]`)
+    #[wasm_bindgen]
+    pub fn set_link_schemes(&mut self, schemes: Vec<String>) {
+        self.link_schemes = schemes.iter().map(|scheme| scheme.to_ascii_lowercase()).collect();
+        self.terminal.set_hover(None);
+    }
+
+    /// Choose what a bell does: flash the screen, beep, vibrate, and/or
+    /// show a notification while the page is hidden (the page must have
+    /// been granted notification permission)
+    #[wasm_bindgen]
+    pub fn set_bell(&mut self, visual: bool, audio: bool, vibrate: bool, notify: bool) {
+        self.alerts.set_bell(BellOptions {
+            visual,
+            audio,
+            vibrate,
+            notify,
+        });
+    }
+
+    /// Show notifications from the running program (OSC 9 / OSC 777) while
+    /// the page is in the background (the page must have been granted
+    /// notification permission)
+    #[wasm_bindgen]
+    pub fn set_notifications(&mut self, enabled: bool) {
+        self.alerts.set_notifications(enabled);
     }
 
     /// Render frame - call from requestAnimationFrame
     #[wasm_bindgen]
     pub fn render(&mut self) -> Result<(), JsValue> {
         // Process incoming data from transport
+        let mut received = 0;
+        // The raw stream's own bells; the server sends its bells as well
+        if self.terminal.take_bell() {
+            self.alerts.ring();
+        }
         if let Some(ref mut transport) = self.transport {
-            while let Some(frame) = transport.try_recv() {
+            if let Some(title) = transport.take_title() {
+                self.terminal.set_title(title);
+            }
+            self.quality.observe_backlog(transport.queue_len());
+            if let Some((frame, count)) = transport.take_frame() {
                 self.terminal.apply_frame(frame);
-                self.frame_count = self.frame_count.wrapping_add(1);
+                self.frame_count = self.frame_count.wrapping_add(count as u64);
+                received = count;
+            }
+            let output = transport.take_output();
+            if !output.is_empty() {
+                self.terminal.process(&output);
+            }
+            while let Some(reply) = self.terminal.take_response() {
+                if transport.responds() {
+                    transport.send(&reply)?;
+                }
+            }
+            for page in transport.take_history() {
+                self.terminal.add_history(page);
+            }
+            if let Some(line) = transport.take_scroll_to() {
+                self.terminal.scroll_to_line(line);
+            }
+            if let Some((from_line, count)) = self.terminal.take_history_request() {
+                transport.send_fetch_history(from_line, count)?;
             }
         }
 
-        // Only render if terminal is dirty
-        if self.terminal.is_dirty() {
+        let now = js_sys::Date::now();
+
+        // Only render if terminal is dirty, the cursor blinked or a bell
+        // flash is fading
+        let cursor = self.cursor_to_draw();
+        let flash = self.alerts.flash(now);
+        if self.terminal.is_dirty() || cursor != self.drawn_cursor || flash != self.drawn_flash {
             if let Some(ref mut renderer) = self.renderer {
-                renderer.render(&self.terminal)?;
+                renderer.render(&self.terminal, cursor, flash)?;
             }
             self.terminal.mark_clean();
+            self.drawn_cursor = cursor;
+            self.drawn_flash = flash;
+        }
+
+        // Return credits only once the frames have actually been drawn.
+        if received > 0 {
+            if let Some(ref transport) = self.transport {
+                transport.ack_frames(received)?;
+            }
         }
 
         Ok(())
@@ -364,7 +801,7 @@ impl NoirTTYWeb {
         let mut out = String::with_capacity(self.terminal.cols() as usize);
         for col in 0..self.terminal.cols() {
             if let Some(cell) = self.terminal.cell(col, row) {
-                out.push(cell.c);
+                cell.push_text(&mut out);
             } else {
                 out.push(' ');
             }
@@ -390,6 +827,44 @@ impl NoirTTYWeb {
         String::new()
     }
 
+    /// Window title set by the running program (OSC 0/2), if any
+    #[wasm_bindgen]
+    pub fn title(&self) -> Option<String> {
+        self.terminal.title().map(str::to_string)
+    }
+
+    /// Whether the title changed since the last call; poll after `render`
+    #[wasm_bindgen]
+    pub fn take_title_change(&mut self) -> bool {
+        self.terminal.take_title_changed()
+    }
+
+    /// Text the running program copied (OSC 52) since the last call; the
+    /// page writes it to the system clipboard
+    #[wasm_bindgen]
+    pub fn take_clipboard(&mut self) -> Option<String> {
+        self.transport.as_ref()?.take_clipboard()
+    }
+
+    /// Whether the running program asked to read the clipboard (OSC 52)
+    /// since the last call; the page answers with `reply_clipboard`
+    #[wasm_bindgen]
+    pub fn take_clipboard_request(&mut self) -> bool {
+        self.transport
+            .as_ref()
+            .is_some_and(|transport| transport.take_clipboard_request())
+    }
+
+    /// Answer a clipboard request with its contents, or None if the user
+    /// refused
+    #[wasm_bindgen]
+    pub fn reply_clipboard(&mut self, text: Option<String>) -> Result<(), JsValue> {
+        if let Some(ref transport) = self.transport {
+            transport.send_clipboard(text)?;
+        }
+        Ok(())
+    }
+
     /// Get terminal cols
     #[wasm_bindgen]
     pub fn cols(&self) -> u16 {
@@ -411,8 +886,55 @@ impl NoirTTYWeb {
     /// Paste from clipboard
     #[wasm_bindgen]
     pub fn paste(&mut self, text: &str) -> Result<(), JsValue> {
-        // Bracket paste mode for terminal safety
+        self.terminal.scroll_to_bottom();
+        if !self.terminal.modes().bracketed_paste {
+            return self.send_input(text);
+        }
+        // Drop ESC so the pasted text cannot end the bracket early.
+        let text = text.replace('\x1b', "");
         let bracketed = format!("\x1b[200~{}\x1b[201~", text);
         self.send_input(&bracketed)
     }
 }
+
+impl NoirTTYWeb {
+    /// URI of the hyperlink at a cell if its scheme is on the allowlist.
+    fn allowed_link(&self, col: u16, row: u16) -> Option<&str> {
+        let (_, uri) = self.terminal.link_at(col, row)?;
+        let (scheme, _) = uri.split_once(':')?;
+        self.link_schemes
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(scheme))
+            .then_some(uri)
+    }
+
+    /// Cursor shape to draw now, or None while hidden or blinked off.
+    fn cursor_to_draw(&self) -> Option<CursorShape> {
+        if !self.terminal.cursor_visible() {
+            return None;
+        }
+        if !self.focused {
+            return Some(CursorShape::HollowBlock);
+        }
+        if self.terminal.cursor_blink() {
+            let phase = ((js_sys::Date::now() - self.blink_epoch) / CURSOR_BLINK_MS) as u64;
+            if !phase.is_multiple_of(2) {
+                return None;
+            }
+        }
+        Some(self.terminal.cursor_shape())
+    }
+
+    /// Push the current adaptive settings to the transport; without one they
+    /// are picked up by the next connect. Returns the render frame rate.
+    fn apply_quality(&self) -> Result<u32, JsValue> {
+        let Some(settings) = self.quality.settings() else {
+            return Ok(DEFAULT_MAX_FPS);
+        };
+        if let Some(ref transport) = self.transport {
+            transport.set_max_frames(settings.max_frames)?;
+            transport.send_quality(settings.min_interval_ms, Some(settings.compress))?;
+        }
+        Ok(settings.max_fps)
+    }
+}
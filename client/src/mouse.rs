@@ -0,0 +1,166 @@
+//! Mouse reporting
+//!
+//! Encodes pointer events for programs that turned on mouse tracking (htop,
+//! mc, vim with `mouse=a`, tmux). Coordinates go out in the encoding the
+//! program picked: SGR (1006), urxvt (1015), UTF-8 (1005) or the original
+//! X10 bytes.
+
+use crate::terminal::TermModes;
+
+/// Button code bits added to the base button.
+const SHIFT: u8 = 4;
+const ALT: u8 = 8;
+const CTRL: u8 = 16;
+const MOTION: u8 = 32;
+/// Wheel up; wheel down is one more.
+const WHEEL: u8 = 64;
+/// Button code for "no button" (motion without a press, X10 release).
+const NO_BUTTON: u8 = 3;
+
+/// Which pointer events the program asked for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tracking {
+    Off,
+    /// 9: presses only, without modifiers.
+    X10,
+    /// 1000: presses, releases and the wheel.
+    Click,
+    /// 1002: also motion while a button is held.
+    Drag,
+    /// 1003: all motion.
+    Motion,
+}
+
+impl Tracking {
+    pub fn from_modes(modes: &TermModes) -> Self {
+        if modes.x10_mouse {
+            Tracking::X10
+        } else if modes.mouse_motion {
+            Tracking::Motion
+        } else if modes.mouse_drag {
+            Tracking::Drag
+        } else if modes.mouse_click {
+            Tracking::Click
+        } else {
+            Tracking::Off
+        }
+    }
+}
+
+/// Keyboard modifiers held during a pointer event.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl Modifiers {
+    fn bits(self) -> u8 {
+        (if self.shift { SHIFT } else { 0 })
+            | (if self.alt { ALT } else { 0 })
+            | (if self.ctrl { CTRL } else { 0 })
+    }
+}
+
+/// Tracks the pressed button between events so motion and release reports
+/// carry it.
+pub struct MouseReporter {
+    /// Button (0 left, 1 middle, 2 right) of a press that was reported.
+    held: Option<u8>,
+    /// Cell of the last motion report; motion within a cell is not repeated.
+    last_cell: Option<(u16, u16)>,
+}
+
+impl MouseReporter {
+    pub fn new() -> Self {
+        MouseReporter {
+            held: None,
+            last_cell: None,
+        }
+    }
+
+    /// Whether a press is being reported, so its drag and release go to the
+    /// program rather than the local selection.
+    pub fn is_held(&self) -> bool {
+        self.held.is_some()
+    }
+
+    /// Button press (0 left, 1 middle, 2 right).
+    pub fn press(&mut self, modes: &TermModes, button: u8, col: u16, row: u16, mods: Modifiers) -> Option<Vec<u8>> {
+        let tracking = Tracking::from_modes(modes);
+        if tracking == Tracking::Off || button > 2 {
+            return None;
+        }
+        self.held = Some(button);
+        self.last_cell = Some((col, row));
+        let code = if tracking == Tracking::X10 { button } else { button | mods.bits() };
+        encode(modes, code, false, col, row)
+    }
+
+    /// Button release; only reported for a reported press.
+    pub fn release(&mut self, modes: &TermModes, col: u16, row: u16, mods: Modifiers) -> Option<Vec<u8>> {
+        let button = self.held.take()?;
+        match Tracking::from_modes(modes) {
+            Tracking::Off | Tracking::X10 => None,
+            _ => encode(modes, button | mods.bits(), true, col, row),
+        }
+    }
+
+    /// Pointer motion, reported with the held button (1002) or without one
+    /// (1003).
+    pub fn motion(&mut self, modes: &TermModes, col: u16, row: u16, mods: Modifiers) -> Option<Vec<u8>> {
+        let button = match (Tracking::from_modes(modes), self.held) {
+            (Tracking::Drag | Tracking::Motion, Some(button)) => button,
+            (Tracking::Motion, None) => NO_BUTTON,
+            _ => return None,
+        };
+        if self.last_cell == Some((col, row)) {
+            return None;
+        }
+        self.last_cell = Some((col, row));
+        encode(modes, button | MOTION | mods.bits(), false, col, row)
+    }
+
+    /// Wheel notches (positive = up), each a press of button 4 or 5.
+    pub fn wheel(&self, modes: &TermModes, lines: i32, col: u16, row: u16, mods: Modifiers) -> Option<Vec<u8>> {
+        let tracking = Tracking::from_modes(modes);
+        if tracking == Tracking::Off || lines == 0 {
+            return None;
+        }
+        let button = if lines > 0 { WHEEL } else { WHEEL + 1 };
+        let code = if tracking == Tracking::X10 { button } else { button | mods.bits() };
+        let report = encode(modes, code, false, col, row)?;
+        Some(report.repeat(lines.unsigned_abs() as usize))
+    }
+}
+
+/// Encode one report. `col` and `row` are 0-based; None when the position
+/// does not fit the encoding.
+fn encode(modes: &TermModes, code: u8, release: bool, col: u16, row: u16) -> Option<Vec<u8>> {
+    let (x, y) = (col as u32 + 1, row as u32 + 1);
+    if modes.sgr_mouse {
+        // SGR keeps the button on release and marks it with 'm'
+        let last = if release { 'm' } else { 'M' };
+        return Some(format!("\x1b[<{};{};{}{}", code, x, y, last).into_bytes());
+    }
+    // The other encodings only say that some button was released
+    let code = if release { (code & !3) | NO_BUTTON } else { code };
+    if modes.urxvt_mouse {
+        return Some(format!("\x1b[{};{};{}M", code as u32 + 32, x, y).into_bytes());
+    }
+    let mut out = b"\x1b[M".to_vec();
+    out.push(code + 32);
+    if modes.utf8_mouse {
+        for value in [x + 32, y + 32] {
+            // Two UTF-8 bytes at most
+            let c = char::from_u32(value).filter(|_| value < 0x800)?;
+            let mut buf = [0; 4];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+    } else {
+        out.push(u8::try_from(x + 32).ok()?);
+        out.push(u8::try_from(y + 32).ok()?);
+    }
+    Some(out)
+}
@@ -0,0 +1,40 @@
+//! System notifications, raised only while the page is in the background.
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{NotificationOptions, NotificationPermission};
+
+/// Title used when the program has not set one.
+pub const DEFAULT_TITLE: &str = "NoirTTY";
+
+/// Whether the page is hidden or its window does not have focus.
+pub fn in_background() -> bool {
+    web_sys::window()
+        .and_then(|w| w.document())
+        .is_some_and(|d| d.hidden() || !d.has_focus().unwrap_or(true))
+}
+
+/// Show a notification if the page is in the background and permission was
+/// granted (the page asks for it). Clicking it brings this tab, and with it
+/// the session, to the front. A `tag` replaces the previous notification
+/// with the same tag instead of stacking.
+pub fn show(title: &str, body: &str, tag: Option<&str>) -> Result<(), JsValue> {
+    if !in_background() || web_sys::Notification::permission() != NotificationPermission::Granted {
+        return Ok(());
+    }
+    let options = NotificationOptions::new();
+    options.set_body(body);
+    if let Some(tag) = tag {
+        options.set_tag(tag);
+    }
+    let notification = web_sys::Notification::new_with_options(title, &options)?;
+    let clicked = notification.clone();
+    let onclick = Closure::once_into_js(move || {
+        if let Some(window) = web_sys::window() {
+            let _ = window.focus();
+        }
+        clicked.close();
+    });
+    notification.set_onclick(Some(onclick.unchecked_ref()));
+    Ok(())
+}
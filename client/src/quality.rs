@@ -0,0 +1,429 @@
+//! Adaptive quality control
+//!
+//! The performance profile picked by the user is a ceiling: render rate,
+//! frame credit window and server frame interval never exceed what it allows.
+//! Below that, the controller steps down one level whenever the link or the
+//! renderer shows strain, and climbs back one level at a time once things
+//! have stayed calm for a while.
+
+/// Settings applied to the render loop and the connection.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct QualitySettings {
+    /// Render loop frame rate cap.
+    pub max_fps: u32,
+    /// Frame credit window (0 = unlimited).
+    pub max_frames: usize,
+    /// Minimum interval between server frames.
+    pub min_interval_ms: u32,
+    /// Whether deflating frames is worth it (only honoured when compression
+    /// was negotiated).
+    pub compress: bool,
+}
+
+/// Limits per level: (max fps, max frames, min interval ms). Level 0 leaves
+/// the ceiling untouched; the last level matches the `minimal` profile.
+const LEVELS: [(u32, usize, u32); 4] = [
+    (u32::MAX, usize::MAX, 0),
+    (30, 4, 33),
+    (20, 2, 66),
+    (12, 1, 120),
+];
+
+/// Ticks without strain before climbing one level.
+const CALM_TICKS_TO_RECOVER: u32 = 5;
+/// Ticks to wait after a change before reacting to strain again, so smoothed
+/// measurements can catch up.
+const COOLDOWN_TICKS: u32 = 2;
+/// RTT above the best seen on this connection that counts as queueing.
+const MAX_QUEUEING_DELAY_MS: f64 = 150.0;
+const MAX_RTT_MS: f64 = 400.0;
+const MAX_JITTER_MS: f64 = 100.0;
+/// Unrendered frames that count as a render backlog.
+const MAX_BACKLOG_FRAMES: usize = 3;
+/// RTT below which the link is treated as local.
+const LAN_RTT_MS: f64 = 10.0;
+/// Received bytes per second above which deflate pays off even on a LAN.
+const HEAVY_TRAFFIC_BYTES_PER_SEC: f64 = 256.0 * 1024.0;
+
+/// Link measurements taken at each tick.
+pub struct LinkSample {
+    pub rtt_ms: Option<f64>,
+    pub jitter_ms: f64,
+    /// Total bytes received by the transport.
+    pub bytes_received: u64,
+}
+
+pub struct AdaptiveQuality {
+    /// Profile limits; None until the page sets a profile.
+    ceiling: Option<QualitySettings>,
+    enabled: bool,
+    level: usize,
+    calm_ticks: u32,
+    cooldown: u32,
+    /// Lowest RTT seen on this connection, the baseline for queueing delay.
+    min_rtt_ms: Option<f64>,
+    /// Most unrendered frames seen since the last tick.
+    peak_backlog: usize,
+    last_bytes: u64,
+    last_tick_ms: Option<f64>,
+    bytes_per_sec: f64,
+}
+
+impl AdaptiveQuality {
+    pub fn new() -> Self {
+        AdaptiveQuality {
+            ceiling: None,
+            enabled: true,
+            level: 0,
+            calm_ticks: 0,
+            cooldown: 0,
+            min_rtt_ms: None,
+            peak_backlog: 0,
+            last_bytes: 0,
+            last_tick_ms: None,
+            bytes_per_sec: 0.0,
+        }
+    }
+
+    pub fn set_ceiling(&mut self, max_fps: u32, max_frames: usize, min_interval_ms: u32) {
+        self.ceiling = Some(QualitySettings {
+            max_fps: max_fps.max(1),
+            max_frames,
+            min_interval_ms,
+            compress: true,
+        });
+    }
+
+    /// Turn adaptation on or off; when off the ceiling applies as is.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.level = 0;
+        }
+    }
+
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    /// Forget per-connection measurements after a reconnect. The level is
+    /// kept, as the new connection most likely runs over the same link.
+    pub fn reset_link(&mut self) {
+        self.min_rtt_ms = None;
+        self.peak_backlog = 0;
+        self.last_bytes = 0;
+        self.last_tick_ms = None;
+        self.bytes_per_sec = 0.0;
+        self.calm_ticks = 0;
+        self.cooldown = COOLDOWN_TICKS;
+    }
+
+    /// Record the number of frames waiting to be rendered.
+    pub fn observe_backlog(&mut self, queued: usize) {
+        self.peak_backlog = self.peak_backlog.max(queued);
+    }
+
+    /// Current settings, or None before a ceiling is set.
+    pub fn settings(&self) -> Option<QualitySettings> {
+        let ceiling = self.ceiling?;
+        let (fps, frames, interval) = LEVELS[self.level];
+        let max_frames = match (ceiling.max_frames, frames) {
+            (0, usize::MAX) => 0,
+            (0, frames) => frames,
+            (ceiling, frames) => ceiling.min(frames),
+        };
+        let lan = self.min_rtt_ms.is_some_and(|rtt| rtt < LAN_RTT_MS);
+        Some(QualitySettings {
+            max_fps: ceiling.max_fps.min(fps),
+            max_frames,
+            min_interval_ms: ceiling.min_interval_ms.max(interval),
+            compress: self.level > 0 || !lan || self.bytes_per_sec > HEAVY_TRAFFIC_BYTES_PER_SEC,
+        })
+    }
+
+    /// Take a measurement and adjust the level. Returns the new settings when
+    /// they changed.
+    pub fn tick(&mut self, now_ms: f64, sample: LinkSample) -> Option<QualitySettings> {
+        let before = self.settings();
+
+        // Counters may have been reset by the debug panel.
+        let bytes = sample.bytes_received.saturating_sub(self.last_bytes);
+        if let Some(last) = self.last_tick_ms {
+            let elapsed = (now_ms - last) / 1000.0;
+            if elapsed > 0.0 {
+                self.bytes_per_sec = 0.5 * self.bytes_per_sec + 0.5 * bytes as f64 / elapsed;
+            }
+        }
+        self.last_bytes = sample.bytes_received;
+        self.last_tick_ms = Some(now_ms);
+
+        if let Some(rtt) = sample.rtt_ms {
+            self.min_rtt_ms = Some(self.min_rtt_ms.map_or(rtt, |min| min.min(rtt)));
+        }
+        let queueing = match (sample.rtt_ms, self.min_rtt_ms) {
+            (Some(rtt), Some(min)) => rtt - min > MAX_QUEUEING_DELAY_MS || rtt > MAX_RTT_MS,
+            _ => false,
+        };
+        let strained = queueing
+            || sample.jitter_ms > MAX_JITTER_MS
+            || self.peak_backlog >= MAX_BACKLOG_FRAMES;
+        self.peak_backlog = 0;
+
+        self.calm_ticks = if strained { 0 } else { self.calm_ticks + 1 };
+        if self.enabled {
+            self.step(strained);
+        }
+
+        let after = self.settings();
+        (after != before).then_some(after).flatten()
+    }
+
+    fn step(&mut self, strained: bool) {
+        if self.cooldown > 0 {
+            self.cooldown -= 1;
+        } else if strained && self.level + 1 < LEVELS.len() {
+            self.level += 1;
+            self.cooldown = COOLDOWN_TICKS;
+            tracing::info!("Link strained, quality level {}", self.level);
+        } else if self.calm_ticks >= CALM_TICKS_TO_RECOVER && self.level > 0 {
+            self.level -= 1;
+            self.calm_ticks = 0;
+            self.cooldown = COOLDOWN_TICKS;
+            tracing::info!("Link recovered, quality level {}", self.level);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tick's measurements with steady traffic.
+    fn sample(rtt_ms: f64) -> LinkSample {
+        LinkSample {
+            rtt_ms: Some(rtt_ms),
+            jitter_ms: 0.0,
+            bytes_received: 0,
+        }
+    }
+
+    /// A controller under a 60 fps, unlimited frames, no interval ceiling,
+    /// after one calm tick on a 50ms link.
+    fn wan() -> AdaptiveQuality {
+        let mut quality = AdaptiveQuality::new();
+        quality.set_ceiling(60, 0, 0);
+        quality.tick(0.0, sample(50.0));
+        quality
+    }
+
+    /// Current (max fps, max frames, min interval ms).
+    fn limits(quality: &AdaptiveQuality) -> (u32, usize, u32) {
+        let settings = quality.settings().unwrap();
+        (
+            settings.max_fps,
+            settings.max_frames,
+            settings.min_interval_ms,
+        )
+    }
+
+    #[test]
+    fn no_settings_before_a_ceiling() {
+        let mut quality = AdaptiveQuality::new();
+        assert_eq!(quality.settings(), None);
+        assert_eq!(quality.tick(0.0, sample(50.0)), None);
+        quality.set_ceiling(0, 8, 16);
+        assert_eq!(limits(&quality), (1, 8, 16));
+    }
+
+    #[test]
+    fn steps_down_on_backlog() {
+        let mut quality = wan();
+        assert_eq!(limits(&quality), (60, 0, 0));
+        quality.observe_backlog(MAX_BACKLOG_FRAMES);
+        quality.observe_backlog(0);
+        let changed = quality.tick(1000.0, sample(50.0)).unwrap();
+        assert_eq!(quality.level(), 1);
+        assert_eq!(
+            (changed.max_fps, changed.max_frames, changed.min_interval_ms),
+            (30, 4, 33)
+        );
+        // The peak is consumed by the tick
+        quality.tick(2000.0, sample(50.0));
+        quality.tick(3000.0, sample(50.0));
+        quality.tick(4000.0, sample(50.0));
+        assert_eq!(quality.level(), 1);
+    }
+
+    #[test]
+    fn steps_down_on_rtt_and_jitter() {
+        let mut quality = wan();
+        // Queueing delay over the best RTT seen
+        quality.tick(1000.0, sample(50.0 + MAX_QUEUEING_DELAY_MS + 1.0));
+        assert_eq!(quality.level(), 1);
+
+        let mut quality = AdaptiveQuality::new();
+        quality.set_ceiling(60, 0, 0);
+        // A slow link from the first sample
+        quality.tick(0.0, sample(MAX_RTT_MS + 1.0));
+        assert_eq!(quality.level(), 1);
+
+        let mut quality = wan();
+        quality.tick(
+            1000.0,
+            LinkSample {
+                jitter_ms: MAX_JITTER_MS + 1.0,
+                ..sample(50.0)
+            },
+        );
+        assert_eq!(quality.level(), 1);
+    }
+
+    #[test]
+    fn bottom_level_matches_minimal() {
+        let mut quality = wan();
+        for tick in 1..20 {
+            quality.observe_backlog(MAX_BACKLOG_FRAMES);
+            quality.tick(tick as f64 * 1000.0, sample(50.0));
+        }
+        assert_eq!(quality.level(), LEVELS.len() - 1);
+        assert_eq!(limits(&quality), (12, 1, 120));
+    }
+
+    #[test]
+    fn never_exceeds_the_ceiling() {
+        let mut quality = AdaptiveQuality::new();
+        quality.set_ceiling(10, 1, 200);
+        quality.tick(0.0, sample(50.0));
+        assert_eq!(limits(&quality), (10, 1, 200));
+        // Level 1 is looser than this profile, so nothing changes
+        quality.observe_backlog(MAX_BACKLOG_FRAMES);
+        assert_eq!(quality.tick(1000.0, sample(50.0)), None);
+        assert_eq!(quality.level(), 1);
+        assert_eq!(limits(&quality), (10, 1, 200));
+    }
+
+    #[test]
+    fn cooldown_delays_further_steps() {
+        let mut quality = wan();
+        quality.observe_backlog(MAX_BACKLOG_FRAMES);
+        quality.tick(1000.0, sample(50.0));
+        assert_eq!(quality.level(), 1);
+        // Strain right after a change is ignored while measurements settle
+        for tick in 2..2 + COOLDOWN_TICKS {
+            quality.observe_backlog(MAX_BACKLOG_FRAMES);
+            quality.tick(tick as f64 * 1000.0, sample(50.0));
+            assert_eq!(quality.level(), 1);
+        }
+        quality.observe_backlog(MAX_BACKLOG_FRAMES);
+        quality.tick(10_000.0, sample(50.0));
+        assert_eq!(quality.level(), 2);
+    }
+
+    #[test]
+    fn recovers_one_level_after_calm_ticks() {
+        let mut quality = wan();
+        quality.observe_backlog(MAX_BACKLOG_FRAMES);
+        quality.tick(1000.0, sample(50.0));
+        // No immediate step back up
+        for tick in 1..CALM_TICKS_TO_RECOVER {
+            assert_eq!(
+                quality.tick(1000.0 + tick as f64 * 1000.0, sample(50.0)),
+                None
+            );
+            assert_eq!(quality.level(), 1);
+        }
+        let changed = quality.tick(10_000.0, sample(50.0)).unwrap();
+        assert_eq!(quality.level(), 0);
+        assert_eq!(changed.max_fps, 60);
+        // After recovering, strain waits out the cooldown too
+        quality.observe_backlog(MAX_BACKLOG_FRAMES);
+        quality.tick(11_000.0, sample(50.0));
+        assert_eq!(quality.level(), 0);
+    }
+
+    #[test]
+    fn reconnect_keeps_the_level_and_waits() {
+        let mut quality = wan();
+        quality.observe_backlog(MAX_BACKLOG_FRAMES);
+        quality.tick(1000.0, sample(50.0));
+        for tick in 2..10 {
+            quality.tick(tick as f64 * 1000.0, sample(50.0));
+        }
+        quality.observe_backlog(MAX_BACKLOG_FRAMES);
+        quality.tick(10_000.0, sample(50.0));
+        assert_eq!(quality.level(), 1);
+        quality.reset_link();
+        quality.tick(11_000.0, sample(MAX_RTT_MS + 1.0));
+        assert_eq!(quality.level(), 1);
+    }
+
+    #[test]
+    fn disabled_applies_the_ceiling() {
+        let mut quality = wan();
+        quality.observe_backlog(MAX_BACKLOG_FRAMES);
+        quality.tick(1000.0, sample(50.0));
+        quality.set_enabled(false);
+        assert_eq!(quality.level(), 0);
+        for tick in 2..10 {
+            quality.observe_backlog(MAX_BACKLOG_FRAMES);
+            quality.tick(tick as f64 * 1000.0, sample(50.0));
+        }
+        assert_eq!(limits(&quality), (60, 0, 0));
+    }
+
+    #[test]
+    fn compression_off_on_a_quiet_lan() {
+        let mut quality = AdaptiveQuality::new();
+        quality.set_ceiling(60, 0, 0);
+        // Unknown link: compress
+        assert!(quality.settings().unwrap().compress);
+        let changed = quality.tick(0.0, sample(2.0)).unwrap();
+        assert!(!changed.compress);
+        // Heavy traffic makes it worth it again
+        let heavy = 4 * HEAVY_TRAFFIC_BYTES_PER_SEC as u64;
+        let changed = quality
+            .tick(
+                1000.0,
+                LinkSample {
+                    bytes_received: heavy,
+                    ..sample(2.0)
+                },
+            )
+            .unwrap();
+        assert!(changed.compress);
+        // and the rate decays once it stops
+        quality.tick(
+            2000.0,
+            LinkSample {
+                bytes_received: heavy,
+                ..sample(2.0)
+            },
+        );
+        quality.tick(
+            3000.0,
+            LinkSample {
+                bytes_received: heavy,
+                ..sample(2.0)
+            },
+        );
+        assert!(!quality.settings().unwrap().compress);
+        // Any step down turns it on
+        quality.observe_backlog(MAX_BACKLOG_FRAMES);
+        let changed = quality
+            .tick(
+                4000.0,
+                LinkSample {
+                    bytes_received: heavy,
+                    ..sample(2.0)
+                },
+            )
+            .unwrap();
+        assert!(changed.compress);
+    }
+
+    #[test]
+    fn compression_on_over_a_wan() {
+        let quality = wan();
+        assert!(quality.settings().unwrap().compress);
+    }
+}
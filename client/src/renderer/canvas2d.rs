@@ -1,9 +1,15 @@
 //! Canvas 2D terminal renderer (fallback)
 
-use crate::terminal::Terminal;
+use crate::terminal::{Cell, CursorShape, Image, Terminal, Underline};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeBounds;
 use wasm_bindgen::prelude::*;
-use wasm_bindgen::JsCast;
-use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
+use wasm_bindgen::{Clamped, JsCast};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, ImageData};
+
+/// Peak opacity of the white bell flash overlay.
+const FLASH_ALPHA: f32 = 0.25;
 
 /// Canvas 2D renderer for terminal
 pub struct Canvas2DRenderer {
@@ -21,6 +27,8 @@ pub struct Canvas2DRenderer {
     selection: String,
     cursor: String,
     cursor_text: String,
+    /// Inline images drawn into canvases of their own, by id
+    images: HashMap<u32, HtmlCanvasElement>,
 }
 
 impl Canvas2DRenderer {
@@ -82,6 +90,7 @@ impl Canvas2DRenderer {
             selection: "#264f78".to_string(),
             cursor: "#c0c0c0".to_string(),
             cursor_text: "#1e1e1e".to_string(),
+            images: HashMap::new(),
         })
     }
 
@@ -147,6 +156,14 @@ impl Canvas2DRenderer {
         (cols.max(1), rows.max(1))
     }
 
+    /// Cell size in device pixels.
+    pub fn cell_pixels(&self) -> (u16, u16) {
+        (
+            (self.cell_width * self.dpr).round() as u16,
+            (self.cell_height * self.dpr).round() as u16,
+        )
+    }
+
     /// Maximum surface dimension for Canvas2D (no hard limit).
     pub fn max_surface_dim(&self) -> u32 {
         u32::MAX
@@ -164,7 +181,21 @@ impl Canvas2DRenderer {
     }
 
     /// Render the terminal
-    pub fn render(&mut self, terminal: &Terminal) -> Result<(), JsValue> {
+    pub fn render(
+        &mut self,
+        terminal: &Terminal,
+        cursor: Option<CursorShape>,
+        flash: f32,
+    ) -> Result<(), JsValue> {
+        // The server's palette (and OSC 11/12) win over the configured colors
+        if let Some(background) = terminal.background() {
+            self.background = css_rgb(background);
+        }
+        if let Some(cursor) = terminal.cursor_color() {
+            self.cursor = css_rgb(cursor);
+        }
+        let default_bg = terminal.default_bg();
+
         // Clear (using logical dimensions)
         self.ctx.set_fill_style_str(&self.background);
         let logical_width = self.width as f64 / self.dpr;
@@ -172,13 +203,17 @@ impl Canvas2DRenderer {
         self.ctx.fill_rect(0.0, 0.0, logical_width, logical_height);
 
         let (cursor_col, cursor_row) = terminal.cursor_position();
-        let cursor_visible = terminal.cursor_visible();
+        let hovered_link = terminal.hovered_link();
 
         // Get selection range
         let selection = terminal.selection_range();
 
         self.ctx.set_font(&self.font);
 
+        // There is no layer between the backgrounds and the text here, so
+        // images under the text go under the backgrounds too
+        self.draw_images(terminal, ..0, logical_height)?;
+
         // Render cells
         for (col, row, cell) in terminal.iter_cells() {
             let x = col as f64 * self.cell_width;
@@ -197,41 +232,183 @@ impl Canvas2DRenderer {
                 // Selection color (e.g., light blue/gray)
                 self.ctx.set_fill_style_str(&self.selection);
                 self.ctx.fill_rect(x, y, self.cell_width + 1.0, self.cell_height);
-            } else if cell.bg != [30, 30, 30] {
-                self.ctx.set_fill_style_str(&format!(
-                    "rgb({},{},{})", cell.bg[0], cell.bg[1], cell.bg[2]
-                ));
+            } else if cell.bg != default_bg {
+                self.ctx.set_fill_style_str(&css_rgb(cell.bg));
                 self.ctx.fill_rect(x, y, self.cell_width + 1.0, self.cell_height);
             }
 
+            let cell_cursor = cursor.filter(|_| col == cursor_col && row == cursor_row);
+
             // Cursor block
-            if cursor_visible && col == cursor_col && row == cursor_row {
+            if cell_cursor == Some(CursorShape::Block) {
                 self.ctx.set_fill_style_str(&self.cursor);
                 self.ctx.fill_rect(x, y, self.cell_width, self.cell_height);
                 self.ctx.set_fill_style_str(&self.cursor_text); // Text color in cursor
             } else {
-                self.ctx.set_fill_style_str(&format!(
-                    "rgb({},{},{})", cell.fg[0], cell.fg[1], cell.fg[2]
-                ));
+                self.ctx.set_fill_style_str(&css_rgb(cell.text_color()));
+            }
+
+            if !cell.hidden {
+                // Character; wide glyphs are squeezed into their two cells
+                if cell.c > ' ' && !cell.spacer {
+                    let mut text = String::new();
+                    cell.push_text(&mut text);
+                    if cell.wide {
+                        self.ctx
+                            .fill_text_with_max_width(&text, x, y + 2.0, 2.0 * self.cell_width)?;
+                    } else {
+                        self.ctx.fill_text(&text, x, y + 2.0)?;
+                    }
+                }
+                self.draw_decorations(cell, cell.underline_style(hovered_link), x, y);
             }
 
-            // Character
-            if cell.c > ' ' {
-                self.ctx.fill_text(&cell.c.to_string(), x, y + 2.0)?;
+            // Cursor drawn over the glyph
+            if let Some(shape) = cell_cursor {
+                self.draw_cursor_outline(shape, x, y);
+            }
+        }
+
+        self.draw_images(terminal, 0.., logical_height)?;
+        let placed: HashSet<u32> = terminal.images().map(|(_, _, image)| image.id).collect();
+        self.images.retain(|id, _| placed.contains(id));
+
+        // Shell integration marks beside each prompt
+        let mark_width = (self.cell_width * 0.15).round().max(1.0);
+        for command in terminal.commands() {
+            if command.prompt >= 0 && command.prompt < self.rows as i32 {
+                self.ctx.set_fill_style_str(&css_rgb(command.status().color()));
+                let y = command.prompt as f64 * self.cell_height;
+                self.ctx.fill_rect(0.0, y, mark_width, self.cell_height);
+            }
+        }
+
+        // Bell flash
+        if flash > 0.0 {
+            self.ctx
+                .set_fill_style_str(&format!("rgba(255,255,255,{})", FLASH_ALPHA * flash));
+            self.ctx.fill_rect(0.0, 0.0, logical_width, logical_height);
+        }
+
+        Ok(())
+    }
+
+    /// Draw the inline images with z in `layer` over the cells they cover.
+    fn draw_images(
+        &mut self,
+        terminal: &Terminal,
+        layer: impl RangeBounds<i32>,
+        logical_height: f64,
+    ) -> Result<(), JsValue> {
+        for (placement, row, image) in terminal.images() {
+            if !layer.contains(&placement.z) {
+                continue;
+            }
+            let x = placement.column as f64 * self.cell_width + placement.offset.0 as f64 / self.dpr;
+            let y = row as f64 * self.cell_height + placement.offset.1 as f64 / self.dpr;
+            let width = placement.width as f64 / self.dpr;
+            let height = placement.height as f64 / self.dpr;
+            if y + height <= 0.0 || y >= logical_height {
+                continue;
             }
+            let canvas = match self.images.entry(image.id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(image_canvas(image)?),
+            };
+            let [sx, sy, sw, sh] = placement.source.map(f64::from);
+            self.ctx.draw_image_with_html_canvas_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                canvas, sx, sy, sw, sh, x, y, width, height,
+            )?;
+        }
+        Ok(())
+    }
 
-            // Underline
-            if cell.underline {
-                self.ctx.set_stroke_style_str(&format!(
-                    "rgb({},{},{})", cell.fg[0], cell.fg[1], cell.fg[2]
-                ));
+    /// Draw underline and strikeout. Lines are filled rects so their
+    /// thickness tracks the cell size like the WebGPU shader.
+    fn draw_decorations(&self, cell: &Cell, underline: Underline, x: f64, y: f64) {
+        let w = self.cell_width;
+        let t = (self.cell_height * 0.06).round().max(1.0);
+        let base = y + self.cell_height * 0.92 - t / 2.0;
+
+        if underline != Underline::None {
+            self.ctx.set_fill_style_str(&css_rgb(cell.underline_color()));
+        }
+        match underline {
+            Underline::None => {}
+            Underline::Single => self.ctx.fill_rect(x, base, w, t),
+            Underline::Double => {
+                self.ctx.fill_rect(x, base, w, t);
+                self.ctx.fill_rect(x, base - 2.0 * t, w, t);
+            }
+            Underline::Curly => {
+                // One full wave per cell, so adjacent cells join up
+                self.ctx.set_stroke_style_str(&css_rgb(cell.underline_color()));
+                self.ctx.set_line_width(t);
                 self.ctx.begin_path();
-                self.ctx.move_to(x, y + self.cell_height - 2.0);
-                self.ctx.line_to(x + self.cell_width, y + self.cell_height - 2.0);
+                let mid = base + t / 2.0 - t;
+                self.ctx.move_to(x, mid);
+                self.ctx.quadratic_curve_to(x + w / 4.0, mid - 2.0 * t, x + w / 2.0, mid);
+                self.ctx.quadratic_curve_to(x + w * 0.75, mid + 2.0 * t, x + w, mid);
                 self.ctx.stroke();
+                self.ctx.set_line_width(1.0);
             }
+            Underline::Dotted => {
+                let mut dx = 0.0;
+                while dx < w {
+                    self.ctx.fill_rect(x + dx, base, t.min(w - dx), t);
+                    dx += 2.0 * t;
+                }
+            }
+            Underline::Dashed => self.ctx.fill_rect(x + w * 0.2, base, w * 0.6, t),
         }
 
-        Ok(())
+        if cell.strikeout {
+            self.ctx.set_fill_style_str(&css_rgb(cell.text_color()));
+            self.ctx.fill_rect(x, y + (self.cell_height - t) / 2.0, w, t);
+        }
     }
+
+    /// Draw the non-block cursor shapes; blocks are filled with the cell.
+    fn draw_cursor_outline(&self, shape: CursorShape, x: f64, y: f64) {
+        let thickness = (self.cell_height / 10.0).round().max(1.0);
+        match shape {
+            CursorShape::Block => {}
+            CursorShape::Underline => {
+                self.ctx.set_fill_style_str(&self.cursor);
+                self.ctx
+                    .fill_rect(x, y + self.cell_height - thickness, self.cell_width, thickness);
+            }
+            CursorShape::Beam => {
+                self.ctx.set_fill_style_str(&self.cursor);
+                self.ctx.fill_rect(x, y, thickness, self.cell_height);
+            }
+            CursorShape::HollowBlock => {
+                self.ctx.set_stroke_style_str(&self.cursor);
+                self.ctx.set_line_width(1.0);
+                self.ctx
+                    .stroke_rect(x + 0.5, y + 0.5, self.cell_width - 1.0, self.cell_height - 1.0);
+            }
+        }
+    }
+}
+
+/// An offscreen canvas holding an image's pixels.
+fn image_canvas(image: &Image) -> Result<HtmlCanvasElement, JsValue> {
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or("No document")?;
+    let canvas: HtmlCanvasElement = document.create_element("canvas")?.dyn_into()?;
+    canvas.set_width(image.width);
+    canvas.set_height(image.height);
+    let ctx: CanvasRenderingContext2d = canvas
+        .get_context("2d")?
+        .ok_or("Failed to get 2d context")?
+        .dyn_into()?;
+    let data = ImageData::new_with_u8_clamped_array_and_sh(Clamped(&image.rgba), image.width, image.height)?;
+    ctx.put_image_data(&data, 0.0, 0.0)?;
+    Ok(canvas)
+}
+
+fn css_rgb(color: [u8; 3]) -> String {
+    format!("rgb({},{},{})", color[0], color[1], color[2])
 }
@@ -8,7 +8,7 @@ pub use canvas2d::Canvas2DRenderer;
 #[cfg(web)]
 pub use webgpu::WebGpuRenderer;
 
-use crate::terminal::Terminal;
+use crate::terminal::{CursorShape, Terminal};
 use wasm_bindgen::prelude::*;
 
 /// Renderer enum supporting WebGPU with Canvas2D fallback
@@ -87,6 +87,15 @@ impl Renderer {
         }
     }
 
+    /// Cell size in device pixels, the unit of inline images.
+    pub fn cell_pixels(&self) -> (u16, u16) {
+        match self {
+            Renderer::Canvas2D(r) => r.cell_pixels(),
+            #[cfg(web)]
+            Renderer::WebGpu(r) => r.cell_pixels(),
+        }
+    }
+
     /// Maximum surface dimension supported by the active renderer.
     pub fn max_surface_dim(&self) -> u32 {
         match self {
@@ -105,12 +114,18 @@ impl Renderer {
         }
     }
 
-    /// Render the terminal
-    pub fn render(&mut self, terminal: &Terminal) -> Result<(), JsValue> {
+    /// Render the terminal with the given cursor (None = not drawn, e.g.
+    /// hidden or in the off phase of a blink) and bell flash strength (0-1)
+    pub fn render(
+        &mut self,
+        terminal: &Terminal,
+        cursor: Option<CursorShape>,
+        flash: f32,
+    ) -> Result<(), JsValue> {
         match self {
-            Renderer::Canvas2D(r) => r.render(terminal),
+            Renderer::Canvas2D(r) => r.render(terminal, cursor, flash),
             #[cfg(web)]
-            Renderer::WebGpu(r) => r.render(terminal),
+            Renderer::WebGpu(r) => r.render(terminal, cursor, flash),
         }
     }
 
@@ -132,6 +147,7 @@ impl Renderer {
         }
     }
 
+    #[cfg_attr(not(web), allow(unused_variables))]
     pub fn set_debug_text(&mut self, enabled: bool) {
         match self {
             Renderer::Canvas2D(_) => {}
@@ -10,10 +10,15 @@ pub struct CellInstance {
     pub pos: [f32; 2],
     /// Background color (RGBA normalized)
     pub bg_color: [f32; 4],
-    /// Flags: bit 0 = has_bg, bit 1 = is_cursor, bit 2 = is_selected, bit 3 = underline
+    /// Flags: bit 0 = has_bg, bit 1 = is_cursor, bit 2 = is_selected, bit 3 = underline,
+    /// bits 4-5 = cursor shape (0 block, 1 underline, 2 beam, 3 hollow block),
+    /// bits 6-8 = underline style (0 single, 1 double, 2 curly, 3 dotted, 4 dashed),
+    /// bit 9 = strikeout
     pub flags: u32,
-    /// Foreground color for underline (RGBA normalized)
+    /// Foreground color for strikeout (RGBA normalized)
     pub fg_color: [f32; 4],
+    /// Underline color (RGBA normalized)
+    pub underline_color: [f32; 4],
 }
 
 impl CellInstance {
@@ -47,6 +52,46 @@ impl CellInstance {
                     shader_location: 3,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                // underline_color
+                wgpu::VertexAttribute {
+                    offset: 44,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Instance data for one inline image
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct ImageInstance {
+    /// Position and size in pixels (x, y, width, height)
+    pub rect: [f32; 4],
+    /// Part of the texture drawn, in texture coordinates (x, y, width, height)
+    pub source: [f32; 4],
+}
+
+impl ImageInstance {
+    /// Vertex buffer layout for instanced rendering
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ImageInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                // rect
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // source
+                wgpu::VertexAttribute {
+                    offset: 16,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
@@ -62,8 +107,10 @@ pub struct GridUniforms {
     pub cell_size: [f32; 2],
     /// Grid size in cells
     pub grid_size: [f32; 2],
+    /// Bell flash strength (0-1)
+    pub flash: f32,
     /// Padding for alignment
-    pub _padding: [f32; 2],
+    pub _padding: f32,
     /// Selection highlight color
     pub selection_color: [f32; 4],
     /// Cursor color
@@ -78,7 +125,8 @@ impl Default for GridUniforms {
             canvas_size: [0.0, 0.0],
             cell_size: [0.0, 0.0],
             grid_size: [80.0, 24.0],
-            _padding: [0.0, 0.0],
+            flash: 0.0,
+            _padding: 0.0,
             selection_color: [0.15, 0.31, 0.47, 1.0],
             cursor_color: [0.75, 0.75, 0.75, 1.0],
             background_color: [0.118, 0.118, 0.118, 1.0],
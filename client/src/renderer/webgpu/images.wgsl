@@ -0,0 +1,66 @@
+// Inline image shader: one textured quad per image
+
+struct GridUniforms {
+    canvas_size: vec2<f32>,
+    cell_size: vec2<f32>,
+    grid_size: vec2<f32>,
+    flash: f32,
+    _padding: f32,
+    selection_color: vec4<f32>,
+    cursor_color: vec4<f32>,
+    background_color: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: GridUniforms;
+
+@group(1) @binding(0)
+var image_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var image_sampler: sampler;
+
+struct ImageInstance {
+    @location(0) rect: vec4<f32>,    // x, y, width, height in pixels
+    @location(1) source: vec4<f32>,  // x, y, width, height in texture coordinates
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// Quad vertices (two triangles)
+const QUAD_VERTICES: array<vec2<f32>, 6> = array<vec2<f32>, 6>(
+    vec2<f32>(0.0, 0.0),
+    vec2<f32>(1.0, 0.0),
+    vec2<f32>(0.0, 1.0),
+    vec2<f32>(0.0, 1.0),
+    vec2<f32>(1.0, 0.0),
+    vec2<f32>(1.0, 1.0),
+);
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    instance: ImageInstance,
+) -> VertexOutput {
+    var out: VertexOutput;
+
+    let local_pos = QUAD_VERTICES[vertex_index];
+    let pixel_pos = instance.rect.xy + local_pos * instance.rect.zw;
+
+    // Convert to clip space (-1 to 1)
+    let clip_x = (pixel_pos.x / uniforms.canvas_size.x) * 2.0 - 1.0;
+    let clip_y = 1.0 - (pixel_pos.y / uniforms.canvas_size.y) * 2.0;
+
+    out.position = vec4<f32>(clip_x, clip_y, 0.0, 1.0);
+    out.uv = instance.source.xy + local_pos * instance.source.zw;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(image_texture, image_sampler, in.uv);
+    // Same bell flash as the cells
+    return vec4<f32>(mix(color.rgb, vec3<f32>(1.0), uniforms.flash * 0.25), color.a);
+}
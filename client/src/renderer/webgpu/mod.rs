@@ -3,19 +3,34 @@
 mod buffers;
 mod pipeline;
 
-use crate::terminal::Terminal;
-use buffers::{CellInstance, GridUniforms};
+use crate::terminal::{Cell, CommandStatus, CursorShape, ImagePlacement, Terminal, Underline};
+use buffers::{CellInstance, GridUniforms, ImageInstance};
 use glyphon::{
     Attrs, Buffer, Cache, Color, ColorMode, Family, FontSystem, Metrics, Shaping, SwashCache,
     TextArea, TextAtlas, TextBounds, TextRenderer, Viewport, Wrap,
 };
-use pipeline::BackgroundPipeline;
+use pipeline::{BackgroundPipeline, ImagePipeline};
+use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::Arc;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::HtmlCanvasElement;
 use js_sys::Reflect;
 
+/// How far the bell flash blends toward white at full strength; the shader
+/// uses the same factor.
+const FLASH_MIX: f32 = 0.25;
+
+/// Text of one wide or combined cell
+struct ClusterBuffer {
+    buffer: Buffer,
+    col: u16,
+    row: u16,
+    text: String,
+    color: [u8; 3],
+}
+
 /// WebGPU-based terminal renderer
 pub struct WebGpuRenderer {
     device: wgpu::Device,
@@ -31,12 +46,23 @@ pub struct WebGpuRenderer {
     uniform_buffer: wgpu::Buffer,
     uniform_bind_group: wgpu::BindGroup,
 
+    // Inline images, uploaded once per id
+    image_pipeline: ImagePipeline,
+    image_buffer: wgpu::Buffer,
+    image_capacity: usize,
+    image_bind_groups: HashMap<u32, wgpu::BindGroup>,
+
     // Text rendering
     font_system: FontSystem,
     swash_cache: SwashCache,
     text_atlas: TextAtlas,
     text_renderer: TextRenderer,
     text_buffer: Buffer,
+    /// Wide and combined glyphs, each laid out in its own buffer placed at
+    /// its cell so a glyph wider than the grid cannot shift the rest of the
+    /// row. Kept across frames; only the first `cluster_count` are drawn.
+    cluster_buffers: Vec<ClusterBuffer>,
+    cluster_count: usize,
     viewport: Viewport,
 
     // Grid state
@@ -54,6 +80,8 @@ pub struct WebGpuRenderer {
     cursor_text_color: [f32; 4],
     cursor_text_color_u8: [u8; 3],
     default_fg: [u8; 3],
+    /// Bell flash strength last written to the uniforms
+    flash: f32,
     font_family: FontFamily,
     frame_counter: u64,
     last_text_runs: u32,
@@ -264,13 +292,18 @@ impl WebGpuRenderer {
 
         // Create background pipeline
         let background_pipeline = BackgroundPipeline::new(&device, surface_format);
+        let image_pipeline =
+            ImagePipeline::new(&device, surface_format, background_pipeline.bind_group_layout());
+        let image_capacity = 16;
+        let image_buffer = create_image_buffer(&device, image_capacity);
 
         // Create uniform buffer
         let uniforms = GridUniforms {
             canvas_size: [width as f32, height as f32],
             cell_size: [(cell_width * dpr) as f32, (cell_height * dpr) as f32],
             grid_size: [cols as f32, rows as f32],
-            _padding: [0.0, 0.0],
+            flash: 0.0,
+            _padding: 0.0,
             selection_color: [0.15, 0.31, 0.47, 1.0], // #264f78
             cursor_color: [0.75, 0.75, 0.75, 1.0],    // #c0c0c0
             background_color: [0.118, 0.118, 0.118, 1.0], // #1e1e1e
@@ -314,11 +347,17 @@ impl WebGpuRenderer {
             instance_capacity,
             uniform_buffer,
             uniform_bind_group,
+            image_pipeline,
+            image_buffer,
+            image_capacity,
+            image_bind_groups: HashMap::new(),
             font_system,
             swash_cache,
             text_atlas,
             text_renderer,
             text_buffer,
+            cluster_buffers: Vec::new(),
+            cluster_count: 0,
             viewport,
             cols,
             rows,
@@ -332,6 +371,7 @@ impl WebGpuRenderer {
             cursor_text_color: [0.118, 0.118, 0.118, 1.0],
             cursor_text_color_u8: [30, 30, 30],
             default_fg: [229, 229, 229],
+            flash: 0.0,
             font_family: FontFamily::Monospace,
             frame_counter: 0,
             last_text_runs: 0,
@@ -416,9 +456,11 @@ impl WebGpuRenderer {
         self.font_size = font_size;
         let line_height = (font_size * 1.2) as f32;
 
-        // Update text buffer metrics
+        // Update text buffer metrics; cluster buffers are recreated on demand
         self.text_buffer
             .set_metrics(&mut self.font_system, Metrics::new(font_size as f32, line_height));
+        self.cluster_buffers.clear();
+        self.cluster_count = 0;
 
         // Measure new cell dimensions
         self.font_family = parse_font_family(font_stack);
@@ -469,8 +511,22 @@ impl WebGpuRenderer {
     }
 
     /// Render the terminal
-    pub fn render(&mut self, terminal: &Terminal) -> Result<(), JsValue> {
+    pub fn render(
+        &mut self,
+        terminal: &Terminal,
+        cursor: Option<CursorShape>,
+        flash: f32,
+    ) -> Result<(), JsValue> {
         self.frame_counter = self.frame_counter.wrapping_add(1);
+        // The server's palette (and OSC 11/12) win over the configured colors
+        let background = terminal.background().map(color_u8_to_f32).unwrap_or(self.background_color);
+        let cursor_color = terminal.cursor_color().map(color_u8_to_f32).unwrap_or(self.cursor_color);
+        if flash != self.flash || background != self.background_color || cursor_color != self.cursor_color {
+            self.flash = flash;
+            self.background_color = background;
+            self.cursor_color = cursor_color;
+            self.update_uniforms();
+        }
         // Get surface texture
         let output = self
             .surface
@@ -482,7 +538,12 @@ impl WebGpuRenderer {
             .create_view(&wgpu::TextureViewDescriptor::default());
 
         // Build instance data for backgrounds
-        let instances = self.build_instances(terminal);
+        let instances = self.build_instances(terminal, cursor);
+        let images = self.prepare_images(terminal);
+        // Images are in z order: those under the cell backgrounds, those
+        // under the text, then those over it
+        let under_background = images.partition_point(|(_, z)| *z < ImagePlacement::UNDER_BACKGROUND);
+        let under_text = images.partition_point(|(_, z)| *z < 0);
 
         // Upload instance data
         if !instances.is_empty() {
@@ -506,19 +567,32 @@ impl WebGpuRenderer {
         if self.debug_text {
             self.update_text_buffer_debug();
         } else {
-            self.update_text_buffer(terminal);
+            self.update_text_buffer(terminal, cursor);
         }
 
-        // Create text area and prepare renderer
+        // Create text areas and prepare renderer
+        let scale = self.dpr as f32;
+        let default_color = Color::rgb(self.default_fg[0], self.default_fg[1], self.default_fg[2]);
         let text_area = TextArea {
             buffer: &self.text_buffer,
             left: 0.0,
             top: 0.0,
-            scale: self.dpr as f32,
+            scale,
             bounds: TextBounds::default(),
-            default_color: Color::rgb(self.default_fg[0], self.default_fg[1], self.default_fg[2]),
+            default_color,
             custom_glyphs: &[],
         };
+        let cluster_areas = self.cluster_buffers[..self.cluster_count].iter().map(|cluster| {
+            TextArea {
+                buffer: &cluster.buffer,
+                left: (cluster.col as f64 * self.cell_width) as f32 * scale,
+                top: (cluster.row as f64 * self.cell_height) as f32 * scale,
+                scale,
+                bounds: TextBounds::default(),
+                default_color,
+                custom_glyphs: &[],
+            }
+        });
 
         // Prepare text renderer
         self.text_renderer
@@ -528,7 +602,7 @@ impl WebGpuRenderer {
                 &mut self.font_system,
                 &mut self.text_atlas,
                 &self.viewport,
-                [text_area],
+                std::iter::once(text_area).chain(cluster_areas),
                 &mut self.swash_cache,
             )
             .map_err(|e| JsValue::from_str(&format!("Text prepare failed: {:?}", e)))?;
@@ -549,9 +623,9 @@ impl WebGpuRenderer {
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: self.background_color[0] as f64,
-                            g: self.background_color[1] as f64,
-                            b: self.background_color[2] as f64,
+                            r: self.flashed(self.background_color[0]),
+                            g: self.flashed(self.background_color[1]),
+                            b: self.flashed(self.background_color[2]),
                             a: self.background_color[3] as f64,
                         }),
                         store: wgpu::StoreOp::Store,
@@ -564,12 +638,16 @@ impl WebGpuRenderer {
                 multiview_mask: None,
             });
 
+            self.draw_images(&mut render_pass, &images, 0..under_background);
+
             if !instances.is_empty() {
                 render_pass.set_pipeline(self.background_pipeline.pipeline());
                 render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
                 render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
                 render_pass.draw(0..6, 0..instances.len() as u32);
             }
+
+            self.draw_images(&mut render_pass, &images, under_background..under_text);
         }
 
         // Render text (pass 2) to avoid Safari pipeline issues
@@ -596,6 +674,28 @@ impl WebGpuRenderer {
                 .map_err(|e| JsValue::from_str(&format!("Text render failed: {:?}", e)))?;
         }
 
+        // Images over the text (pass 3)
+        if under_text < images.len() {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("image-pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+                multiview_mask: None,
+            });
+
+            self.draw_images(&mut render_pass, &images, under_text..images.len());
+        }
+
         // Submit
         self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
@@ -608,6 +708,75 @@ impl WebGpuRenderer {
         Ok(())
     }
 
+    /// Draw the prepared images `range`, one instance each.
+    fn draw_images(&self, render_pass: &mut wgpu::RenderPass, images: &[(u32, i32)], range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(self.image_pipeline.pipeline());
+        render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.image_buffer.slice(..));
+        for i in range {
+            render_pass.set_bind_group(1, &self.image_bind_groups[&images[i].0], &[]);
+            render_pass.draw(0..6, i as u32..i as u32 + 1);
+        }
+    }
+
+    /// Upload the instances of the images on view, and the pixels of any
+    /// new ones. Returns their ids and z, in instance order.
+    fn prepare_images(&mut self, terminal: &Terminal) -> Vec<(u32, i32)> {
+        let cell_width = self.cell_width * self.dpr;
+        let cell_height = self.cell_height * self.dpr;
+        let height = self.surface_config.height as f64;
+        let mut drawn = Vec::new();
+        let mut instances = Vec::new();
+        let mut placed = Vec::new();
+        for (placement, row, image) in terminal.images() {
+            placed.push(image.id);
+            let x = placement.column as f64 * cell_width + placement.offset.0 as f64;
+            let y = row as f64 * cell_height + placement.offset.1 as f64;
+            if y + placement.height as f64 <= 0.0 || y >= height {
+                continue;
+            }
+            if !self.image_bind_groups.contains_key(&image.id) {
+                let bind_group = self.image_pipeline.create_bind_group(
+                    &self.device,
+                    &self.queue,
+                    image.width,
+                    image.height,
+                    &image.rgba,
+                );
+                self.image_bind_groups.insert(image.id, bind_group);
+            }
+            drawn.push((image.id, placement.z));
+            let [sx, sy, sw, sh] = placement.source;
+            let (width, height) = (image.width as f32, image.height as f32);
+            instances.push(ImageInstance {
+                rect: [x as f32, y as f32, placement.width as f32, placement.height as f32],
+                source: [sx as f32 / width, sy as f32 / height, sw as f32 / width, sh as f32 / height],
+            });
+        }
+        self.image_bind_groups.retain(|id, _| placed.contains(id));
+
+        if instances.len() > self.image_capacity {
+            self.image_capacity = instances.len().next_power_of_two();
+            self.image_buffer = create_image_buffer(&self.device, self.image_capacity);
+        }
+        if !instances.is_empty() {
+            self.queue
+                .write_buffer(&self.image_buffer, 0, bytemuck::cast_slice(&instances));
+        }
+        drawn
+    }
+
+    /// Cell size in device pixels.
+    pub fn cell_pixels(&self) -> (u16, u16) {
+        (
+            (self.cell_width * self.dpr).round() as u16,
+            (self.cell_height * self.dpr).round() as u16,
+        )
+    }
+
     pub fn debug_text_runs(&self) -> u32 {
         self.last_text_runs
     }
@@ -616,6 +785,11 @@ impl WebGpuRenderer {
         self.debug_text = enabled;
     }
 
+    /// Blend a color channel toward white by the bell flash strength.
+    fn flashed(&self, channel: f32) -> f64 {
+        (channel + (1.0 - channel) * self.flash * FLASH_MIX) as f64
+    }
+
     fn update_uniforms(&self) {
         let uniforms = GridUniforms {
             canvas_size: [
@@ -627,7 +801,8 @@ impl WebGpuRenderer {
                 (self.cell_height * self.dpr) as f32,
             ],
             grid_size: [self.cols as f32, self.rows as f32],
-            _padding: [0.0, 0.0],
+            flash: self.flash,
+            _padding: 0.0,
             selection_color: self.selection_color,
             cursor_color: self.cursor_color,
             background_color: self.background_color,
@@ -636,11 +811,21 @@ impl WebGpuRenderer {
             .write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
     }
 
-    fn build_instances(&self, terminal: &Terminal) -> Vec<CellInstance> {
+    fn build_instances(
+        &self,
+        terminal: &Terminal,
+        cursor: Option<CursorShape>,
+    ) -> Vec<CellInstance> {
         let (cursor_col, cursor_row) = terminal.cursor_position();
-        let cursor_visible = terminal.cursor_visible();
+        let hovered_link = terminal.hovered_link();
         let selection = terminal.selection_range();
-        let default_bg = [30, 30, 30];
+        let default_bg = terminal.default_bg();
+        let mut marks = vec![None; self.rows as usize];
+        for command in terminal.commands() {
+            if let Some(mark) = usize::try_from(command.prompt).ok().and_then(|row| marks.get_mut(row)) {
+                *mark = Some(command.status());
+            }
+        }
 
         let mut instances = Vec::with_capacity(self.cols as usize * self.rows as usize);
 
@@ -654,13 +839,15 @@ impl WebGpuRenderer {
             };
 
             // Check if cursor
-            let is_cursor = cursor_visible && col == cursor_col && row == cursor_row;
+            let cell_cursor = cursor.filter(|_| col == cursor_col && row == cursor_row);
+            let is_cursor = cell_cursor.is_some();
+            let is_block = cell_cursor == Some(CursorShape::Block);
 
             // Determine if we need to render this cell's background
-            let has_bg = is_selected || is_cursor || cell.bg != default_bg;
+            let has_bg = is_selected || is_block || cell.bg != default_bg;
 
             // Compute background color
-            let bg_color = if is_cursor {
+            let bg_color = if is_block {
                 self.cursor_color
             } else if is_selected {
                 self.selection_color
@@ -673,51 +860,88 @@ impl WebGpuRenderer {
                 ]
             };
 
-            // Flags: bit 0 = has_bg, bit 1 = is_cursor, bit 2 = is_selected, bit 3 = underline
+            // Flags: bit 0 = has_bg, bit 1 = is_cursor, bit 2 = is_selected, bit 3 = underline,
+            // bits 4-5 = cursor shape, bits 6-8 = underline style, bit 9 = strikeout,
+            // bit 10 = command mark, bits 11-12 = command status
+            let shape = match cell_cursor {
+                Some(CursorShape::Underline) => 1,
+                Some(CursorShape::Beam) => 2,
+                Some(CursorShape::HollowBlock) => 3,
+                _ => 0,
+            };
+            let underline = match cell.underline_style(hovered_link) {
+                _ if cell.hidden => None,
+                Underline::None => None,
+                Underline::Single => Some(0),
+                Underline::Double => Some(1),
+                Underline::Curly => Some(2),
+                Underline::Dotted => Some(3),
+                Underline::Dashed => Some(4),
+            };
+            let strikeout = cell.strikeout && !cell.hidden;
+            let mark = marks[row as usize].filter(|_| col == 0);
+            let status = match mark {
+                Some(CommandStatus::Succeeded) => 1,
+                Some(CommandStatus::Failed) => 2,
+                _ => 0,
+            };
             let flags = (has_bg as u32)
                 | ((is_cursor as u32) << 1)
                 | ((is_selected as u32) << 2)
-                | ((cell.underline as u32) << 3);
+                | ((underline.is_some() as u32) << 3)
+                | (shape << 4)
+                | (underline.unwrap_or(0) << 6)
+                | ((strikeout as u32) << 9)
+                | ((mark.is_some() as u32) << 10)
+                | (status << 11);
 
-            let fg_color = [
-                cell.fg[0] as f32 / 255.0,
-                cell.fg[1] as f32 / 255.0,
-                cell.fg[2] as f32 / 255.0,
-                1.0,
-            ];
+            let fg_color = color_u8_to_f32(cell.text_color());
+            let underline_color = color_u8_to_f32(cell.underline_color());
 
             instances.push(CellInstance {
                 pos: [col as f32, row as f32],
                 bg_color,
                 flags,
                 fg_color,
+                underline_color,
             });
         }
 
         instances
     }
 
-    fn update_text_buffer(&mut self, terminal: &Terminal) {
+    fn update_text_buffer(&mut self, terminal: &Terminal, cursor: Option<CursorShape>) {
         let (cursor_col, cursor_row) = terminal.cursor_position();
-        let cursor_visible = terminal.cursor_visible();
-        let base_attrs = Attrs::new().family(self.font_family.as_family());
+        let block_cursor = cursor == Some(CursorShape::Block);
+        let mut clusters = 0;
         let mut spans: Vec<(String, Option<[u8; 3]>)> = Vec::new();
         let mut current_color: Option<[u8; 3]> = None;
         let mut current_segment = String::new();
 
         for row in 0..self.rows {
             for col in 0..self.cols {
-                let cell = terminal
-                    .cell(col, row)
-                    .map(|cell| (cell.c, cell.fg))
-                    .unwrap_or((' ', self.default_fg));
-                let is_cursor = cursor_visible && col == cursor_col && row == cursor_row;
-                let fg = if is_cursor {
-                    self.cursor_text_color_u8
-                } else {
-                    cell.1
+                let is_cursor = block_cursor && col == cursor_col && row == cursor_row;
+                let (ch, fg) = match terminal.cell(col, row) {
+                    Some(cell) => {
+                        let fg = if is_cursor {
+                            self.cursor_text_color_u8
+                        } else {
+                            cell.text_color()
+                        };
+                        if cell.hidden || cell.spacer {
+                            (' ', fg)
+                        } else if cell.is_cluster() {
+                            // Leave a blank in the row and draw the glyph on its own
+                            self.set_cluster(clusters, col, row, cell, fg);
+                            clusters += 1;
+                            (' ', fg)
+                        } else {
+                            (cell.c, fg)
+                        }
+                    }
+                    None => (' ', self.default_fg),
                 };
-                let ch = if cell.0 > ' ' { cell.0 } else { ' ' };
+                let ch = if ch > ' ' { ch } else { ' ' };
 
                 if current_color != Some(fg) {
                     push_span(&mut spans, &mut current_segment, current_color);
@@ -731,6 +955,7 @@ impl WebGpuRenderer {
             current_color = None;
         }
 
+        let base_attrs = Attrs::new().family(self.font_family.as_family());
         self.text_buffer.set_rich_text(
             &mut self.font_system,
             spans.iter().map(|(s, color)| {
@@ -745,10 +970,45 @@ impl WebGpuRenderer {
             None,
         );
 
+        self.cluster_count = clusters;
         self.last_text_runs = self.text_buffer.layout_runs().count() as u32;
     }
 
+    /// Lay out a wide or combined cell in the pooled buffer at `index`,
+    /// reshaping only when its text or color changed.
+    fn set_cluster(&mut self, index: usize, col: u16, row: u16, cell: &Cell, color: [u8; 3]) {
+        let mut text = String::new();
+        cell.push_text(&mut text);
+
+        if index == self.cluster_buffers.len() {
+            let mut buffer = Buffer::new(&mut self.font_system, self.text_buffer.metrics());
+            buffer.set_wrap(&mut self.font_system, Wrap::None);
+            self.cluster_buffers.push(ClusterBuffer {
+                buffer,
+                col,
+                row,
+                text: String::new(),
+                color,
+            });
+        }
+
+        let cluster = &mut self.cluster_buffers[index];
+        cluster.col = col;
+        cluster.row = row;
+        if cluster.text != text || cluster.color != color {
+            let attrs = Attrs::new()
+                .family(self.font_family.as_family())
+                .color(Color::rgb(color[0], color[1], color[2]));
+            cluster
+                .buffer
+                .set_text(&mut self.font_system, &text, &attrs, Shaping::Advanced, None);
+            cluster.text = text;
+            cluster.color = color;
+        }
+    }
+
     fn update_text_buffer_debug(&mut self) {
+        self.cluster_count = 0;
         let attrs = Attrs::new()
             .family(self.font_family.as_family())
             .color(Color::rgb(255, 255, 255));
@@ -769,6 +1029,15 @@ impl WebGpuRenderer {
 }
 
 /// Measure the width of a character using the font system
+fn create_image_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("image-instance-buffer"),
+        size: (capacity * std::mem::size_of::<ImageInstance>()) as u64,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
 fn measure_char_width(
     font_system: &mut FontSystem,
     buffer: &mut Buffer,
@@ -827,6 +1096,15 @@ fn push_span(
     spans.push((std::mem::take(segment), color));
 }
 
+fn color_u8_to_f32(color: [u8; 3]) -> [f32; 4] {
+    [
+        color[0] as f32 / 255.0,
+        color[1] as f32 / 255.0,
+        color[2] as f32 / 255.0,
+        1.0,
+    ]
+}
+
 fn color_f32_to_u8(color: [f32; 4]) -> [u8; 3] {
     [
         (color[0].clamp(0.0, 1.0) * 255.0).round() as u8,
@@ -1,6 +1,6 @@
 //! Render pipeline setup
 
-use super::buffers::CellInstance;
+use super::buffers::{CellInstance, ImageInstance};
 
 /// Background rendering pipeline
 pub struct BackgroundPipeline {
@@ -94,3 +94,198 @@ impl BackgroundPipeline {
         &self.bind_group_layout
     }
 }
+
+/// Inline image pipeline: textured quads sharing the grid uniforms
+pub struct ImagePipeline {
+    pipeline: wgpu::RenderPipeline,
+    texture_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+}
+
+impl ImagePipeline {
+    /// Create the image pipeline; `uniform_layout` is the background
+    /// pipeline's, so the same uniform bind group serves both
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        uniform_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("image-shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("images.wgsl").into()),
+        });
+
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("image-bind-group-layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("image-pipeline-layout"),
+            bind_group_layouts: &[uniform_layout, &texture_layout],
+            immediate_size: 0,
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("image-pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[ImageInstance::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview_mask: None,
+            cache: None,
+        });
+
+        // Images are drawn one image pixel per device pixel
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("image-sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            pipeline,
+            texture_layout,
+            sampler,
+        }
+    }
+
+    /// Get the render pipeline
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    /// Upload an image (RGBA, row by row) and bind it for drawing. Images
+    /// larger than a texture can be are scaled down to fit.
+    pub fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: u32,
+        height: u32,
+        rgba: &[u8],
+    ) -> wgpu::BindGroup {
+        let max = device.limits().max_texture_dimension_2d;
+        let scaled;
+        let (width, height, rgba) = if width > max || height > max {
+            let (new_width, new_height) = if width >= height {
+                (max, (height as u64 * max as u64 / width as u64).max(1) as u32)
+            } else {
+                ((width as u64 * max as u64 / height as u64).max(1) as u32, max)
+            };
+            scaled = downscale(width, height, rgba, new_width, new_height);
+            (new_width, new_height, &scaled[..])
+        } else {
+            (width, height, rgba)
+        };
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        // Not sRGB: image colors go to the surface as they are, like the
+        // cell colors
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("image-texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            rgba,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // The bind group keeps the texture alive
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("image-bind-group"),
+            layout: &self.texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        })
+    }
+}
+
+/// Nearest-neighbour resize of an RGBA image.
+fn downscale(width: u32, height: u32, rgba: &[u8], new_width: u32, new_height: u32) -> Vec<u8> {
+    let mut scaled = Vec::with_capacity(new_width as usize * new_height as usize * 4);
+    for y in 0..new_height as u64 {
+        let row = (y * height as u64 / new_height as u64) as usize * width as usize;
+        for x in 0..new_width as u64 {
+            let at = (row + (x * width as u64 / new_width as u64) as usize) * 4;
+            scaled.extend_from_slice(&rgba[at..at + 4]);
+        }
+    }
+    scaled
+}
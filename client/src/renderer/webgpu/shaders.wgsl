@@ -4,7 +4,8 @@ struct GridUniforms {
     canvas_size: vec2<f32>,
     cell_size: vec2<f32>,
     grid_size: vec2<f32>,
-    _padding: vec2<f32>,
+    flash: f32,
+    _padding: f32,
     selection_color: vec4<f32>,
     cursor_color: vec4<f32>,
     background_color: vec4<f32>,
@@ -16,8 +17,11 @@ var<uniform> uniforms: GridUniforms;
 struct CellInstance {
     @location(0) pos: vec2<f32>,       // col, row
     @location(1) bg_color: vec4<f32>,  // background color
-    @location(2) flags: u32,           // bit 0 = has_bg, bit 1 = is_cursor, bit 2 = is_selected, bit 3 = underline
-    @location(3) fg_color: vec4<f32>,  // foreground color (for underline)
+    @location(2) flags: u32,           // bit 0 = has_bg, bit 1 = is_cursor, bit 2 = is_selected, bit 3 = underline, bits 4-5 = cursor shape,
+                                       // bits 6-8 = underline style, bit 9 = strikeout, bit 10 = command mark,
+                                       // bits 11-12 = command status
+    @location(3) fg_color: vec4<f32>,  // foreground color (for strikeout)
+    @location(4) underline_color: vec4<f32>,
 }
 
 struct VertexOutput {
@@ -26,6 +30,7 @@ struct VertexOutput {
     @location(1) local_pos: vec2<f32>,  // Position within cell (0-1)
     @location(2) flags: u32,
     @location(3) fg_color: vec4<f32>,
+    @location(4) underline_color: vec4<f32>,
 }
 
 // Quad vertices (two triangles)
@@ -61,6 +66,7 @@ fn vs_main(
     out.local_pos = local_pos;
     out.flags = instance.flags;
     out.fg_color = instance.fg_color;
+    out.underline_color = instance.underline_color;
 
     return out;
 }
@@ -69,24 +75,103 @@ fn vs_main(
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let has_bg = (in.flags & 1u) != 0u;
     let has_underline = (in.flags & 8u) != 0u;
+    let is_cursor = (in.flags & 2u) != 0u;
+    let has_strikeout = (in.flags & 512u) != 0u;
+    let px = in.local_pos * uniforms.cell_size;
+
+    // Non-block cursors are drawn over the cell; block cursors come in as bg
+    if is_cursor {
+        let shape = (in.flags >> 4u) & 3u;
+        let thickness = max(1.0, round(uniforms.cell_size.y * 0.1));
+        var in_cursor = false;
+        if shape == 1u {
+            in_cursor = px.y >= uniforms.cell_size.y - thickness;
+        } else if shape == 2u {
+            in_cursor = px.x < thickness;
+        } else if shape == 3u {
+            in_cursor = px.x < 1.0 || px.y < 1.0
+                || px.x >= uniforms.cell_size.x - 1.0
+                || px.y >= uniforms.cell_size.y - 1.0;
+        }
+        if in_cursor {
+            return flashed(uniforms.cursor_color);
+        }
+    }
+
+    // Shell integration mark at the left edge of a prompt row
+    let has_mark = (in.flags & 1024u) != 0u;
+    if has_mark && px.x < max(1.0, round(uniforms.cell_size.x * 0.15)) {
+        return flashed(mark_color((in.flags >> 11u) & 3u));
+    }
 
-    // Skip cells with no background and no underline
-    if !has_bg && !has_underline {
+    // Skip cells with no background and no decoration
+    if !has_bg && !has_underline && !has_strikeout {
         discard;
     }
 
-    // Underline region at bottom of cell
-    let in_underline_region = in.local_pos.y > 0.88 && in.local_pos.y < 0.96;
+    // Decorations are laid out in pixels so lines stay crisp at any cell size
+    let t = max(1.0, round(uniforms.cell_size.y * 0.06));
+    let base = uniforms.cell_size.y * 0.92;
 
     // Draw underline on top of background
-    if has_underline && in_underline_region {
-        return in.fg_color;
+    if has_underline && in_underline(px, t, base, (in.flags >> 6u) & 7u) {
+        return flashed(in.underline_color);
+    }
+
+    if has_strikeout && abs(px.y - uniforms.cell_size.y * 0.5) < t * 0.5 {
+        return flashed(in.fg_color);
     }
 
     // Draw background
     if has_bg {
-        return in.color;
+        return flashed(in.color);
     }
 
     discard;
 }
+
+// Command mark colors, as in CommandStatus::color
+// (0 unknown, 1 succeeded, 2 failed)
+fn mark_color(status: u32) -> vec4<f32> {
+    switch status {
+        case 1u: {
+            return vec4<f32>(80.0, 200.0, 120.0, 255.0) / 255.0;
+        }
+        case 2u: {
+            return vec4<f32>(230.0, 80.0, 80.0, 255.0) / 255.0;
+        }
+        default: {
+            return vec4<f32>(128.0, 128.0, 128.0, 255.0) / 255.0;
+        }
+    }
+}
+
+// Whether a pixel is covered by an underline of the given style
+// (0 single, 1 double, 2 curly, 3 dotted, 4 dashed)
+fn in_underline(px: vec2<f32>, t: f32, base: f32, style: u32) -> bool {
+    let on_line = abs(px.y - base) < t * 0.5;
+    switch style {
+        case 1u: {
+            return on_line || abs(px.y - (base - 2.0 * t)) < t * 0.5;
+        }
+        case 2u: {
+            // One full wave per cell, so adjacent cells join up
+            let wave = base - t - t * sin(px.x / uniforms.cell_size.x * 6.2831853);
+            return abs(px.y - wave) < t * 0.75;
+        }
+        case 3u: {
+            return on_line && fract(px.x / (2.0 * t)) < 0.5;
+        }
+        case 4u: {
+            return on_line && abs(px.x / uniforms.cell_size.x - 0.5) < 0.3;
+        }
+        default: {
+            return on_line;
+        }
+    }
+}
+
+// Bell flash: blend toward white (same factor as FLASH_MIX in mod.rs)
+fn flashed(color: vec4<f32>) -> vec4<f32> {
+    return vec4<f32>(mix(color.rgb, vec3<f32>(1.0), uniforms.flash * 0.25), color.a);
+}
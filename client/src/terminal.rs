@@ -2,36 +2,123 @@
 //!
 //! Provides VTE parsing and terminal grid state management.
 
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use serde::{Deserialize, Serialize};
-use vte::{Params, Parser, Perform};
+use unicode_width::UnicodeWidthChar;
+use vte::{Params, ParamsIter, Parser, Perform};
 
 /// Terminal cell
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Cell {
     pub c: char,
+    /// Zero-width characters completing the grapheme cluster started by `c`.
+    #[serde(default)]
+    pub combining: String,
+    /// First cell of a double-width character.
+    #[serde(default)]
+    pub wide: bool,
+    /// Cell covered by the wide character to its left; draws nothing.
+    #[serde(default)]
+    pub spacer: bool,
     pub fg: [u8; 3],
     pub bg: [u8; 3],
     pub bold: bool,
     pub italic: bool,
-    pub underline: bool,
+    #[serde(default)]
+    pub dim: bool,
+    #[serde(default)]
+    pub underline: Underline,
+    /// SGR 58 underline color; None to follow the foreground.
+    #[serde(default)]
+    pub underline_color: Option<[u8; 3]>,
+    #[serde(default)]
+    pub strikeout: bool,
+    #[serde(default)]
+    pub hidden: bool,
     pub inverse: bool,
+    /// OSC 8 hyperlink, as an index into the frame's link table.
+    #[serde(default)]
+    pub link: Option<u32>,
 }
 
 impl Default for Cell {
     fn default() -> Self {
         Cell {
             c: ' ',
+            combining: String::new(),
+            wide: false,
+            spacer: false,
             fg: [229, 229, 229], // Default foreground (light gray)
             bg: [30, 30, 30],     // Default background (dark gray)
             bold: false,
             italic: false,
-            underline: false,
+            dim: false,
+            underline: Underline::None,
+            underline_color: None,
+            strikeout: false,
+            hidden: false,
             inverse: false,
+            link: None,
         }
     }
 }
 
+impl Cell {
+    /// Append the cell's grapheme cluster; spacers add nothing.
+    pub fn push_text(&self, out: &mut String) {
+        if !self.spacer {
+            out.push(self.c);
+            out.push_str(&self.combining);
+        }
+    }
+
+    /// Whether the glyph cannot be laid out as one plain grid character.
+    pub fn is_cluster(&self) -> bool {
+        self.wide || !self.combining.is_empty()
+    }
+
+    /// Color to draw the glyph with: the foreground, halfway to the
+    /// background when dim.
+    pub fn text_color(&self) -> [u8; 3] {
+        if !self.dim {
+            return self.fg;
+        }
+        let mix = |fg: u8, bg: u8| ((fg as u16 + bg as u16) / 2) as u8;
+        [
+            mix(self.fg[0], self.bg[0]),
+            mix(self.fg[1], self.bg[1]),
+            mix(self.fg[2], self.bg[2]),
+        ]
+    }
+
+    /// Underline to draw; a hovered link gets one if it has none.
+    pub fn underline_style(&self, hovered_link: Option<u32>) -> Underline {
+        if self.underline == Underline::None && self.link.is_some() && self.link == hovered_link {
+            Underline::Single
+        } else {
+            self.underline
+        }
+    }
+
+    /// Color for underline decorations.
+    pub fn underline_color(&self) -> [u8; 3] {
+        self.underline_color.unwrap_or_else(|| self.text_color())
+    }
+}
+
+/// Underline style (SGR 4, 4:x and 21)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Underline {
+    #[default]
+    None,
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TerminalFrame {
     pub cols: u16,
@@ -39,7 +126,222 @@ pub struct TerminalFrame {
     pub cursor_col: u16,
     pub cursor_row: u16,
     pub cursor_visible: bool,
+    #[serde(default)]
+    pub cursor_shape: CursorShape,
+    #[serde(default)]
+    pub cursor_blink: bool,
+    #[serde(default)]
+    pub modes: TermModes,
+    /// Default background and cursor colors, including changes the program
+    /// made with OSC 11/12.
+    #[serde(default = "default_background")]
+    pub background: [u8; 3],
+    #[serde(default = "default_cursor_color")]
+    pub cursor_color: [u8; 3],
+    /// Absolute line number of the first row, counted from the first line
+    /// the session ever printed.
+    #[serde(default)]
+    pub top_line: u64,
+    /// First line still in the server's scrollback.
+    #[serde(default)]
+    pub history_start: u64,
+    /// OSC 8 hyperlink URIs referenced by `Cell::link`.
+    #[serde(default)]
+    pub links: Vec<String>,
+    /// Shell commands (OSC 133) overlapping the viewport.
+    #[serde(default)]
+    pub commands: Vec<CommandMark>,
+    /// Inline images on this screen, including the scrollback.
+    #[serde(default)]
+    pub images: Vec<ImagePlacement>,
     pub cells: Vec<Cell>,
+    /// Pixels of the images this frame shows first, which the server sends
+    /// just before it.
+    #[serde(skip)]
+    pub pixels: Vec<Image>,
+    /// Session colors, sent with the snapshot that starts a raw stream.
+    #[serde(skip)]
+    pub palette: Option<Palette>,
+}
+
+/// Colors SGR sequences pick when emulating locally: the session's default
+/// foreground and its 256 indexed colors.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Palette {
+    pub foreground: [u8; 3],
+    /// Colors 0-255.
+    pub indexed: Vec<[u8; 3]>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            foreground: Cell::default().fg,
+            indexed: (0..=255).map(color_256).collect(),
+        }
+    }
+}
+
+impl Palette {
+    fn color(&self, index: u16) -> [u8; 3] {
+        self.indexed.get(index as usize).copied().unwrap_or(self.foreground)
+    }
+}
+
+/// Where the server placed an image: its top left corner sits at `column`
+/// of an absolute line, `offset` pixels in. The pixels come separately, as
+/// an `Image`. Sizes and offsets are in device pixels.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImagePlacement {
+    pub id: u32,
+    pub line: u64,
+    pub column: u16,
+    pub offset: (u32, u32),
+    /// Part of the image drawn: x, y, width and height in image pixels.
+    pub source: [u32; 4],
+    pub width: u32,
+    pub height: u32,
+    /// Stacking order: negative under the text, otherwise over it.
+    pub z: i32,
+}
+
+impl ImagePlacement {
+    /// Images below this z go under cell backgrounds too.
+    pub const UNDER_BACKGROUND: i32 = i32::MIN / 2;
+}
+
+/// Pixels of an inline image, RGBA row by row.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub id: u32,
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+/// Scrollback lines `from_line..from_line + lines.len()` sent by the server,
+/// with trailing blank cells dropped.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryPage {
+    pub from_line: u64,
+    pub lines: Vec<Vec<Cell>>,
+    /// OSC 8 hyperlink URIs referenced by `Cell::link`.
+    #[serde(default)]
+    pub links: Vec<String>,
+    /// Commands overlapping the page, rows relative to `from_line`.
+    #[serde(default)]
+    pub commands: Vec<CommandMark>,
+}
+
+/// Lines asked for in one history request.
+const HISTORY_PAGE: u16 = 200;
+/// Lines above the view fetched ahead of scrolling.
+const HISTORY_PREFETCH: u64 = 100;
+/// Link ids of scrollback cells start here, past any frame's link table.
+const HISTORY_LINK_BASE: u32 = 1 << 31;
+
+fn default_background() -> [u8; 3] {
+    Cell::default().bg
+}
+
+fn default_cursor_color() -> [u8; 3] {
+    Cell::default().fg
+}
+
+/// A shell command marked by OSC 133. Rows are relative to the top of the
+/// viewport and may lie outside it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommandMark {
+    pub prompt: i32,
+    /// Output rows, `output_start..output_end`.
+    pub output_start: i32,
+    pub output_end: i32,
+    pub finished: bool,
+    /// Exit code, if the shell reported one.
+    pub exit: Option<i32>,
+}
+
+/// How a marked command ended, for its gutter mark.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandStatus {
+    /// Still running, or finished without an exit code.
+    Unknown,
+    Succeeded,
+    Failed,
+}
+
+impl CommandMark {
+    /// Shift the rows by `delta` lines.
+    fn shifted(&self, delta: i64) -> CommandMark {
+        let shift = |row: i32| (row as i64 + delta).clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+        CommandMark {
+            prompt: shift(self.prompt),
+            output_start: shift(self.output_start),
+            output_end: shift(self.output_end),
+            ..self.clone()
+        }
+    }
+
+    pub fn status(&self) -> CommandStatus {
+        match self.exit {
+            Some(0) => CommandStatus::Succeeded,
+            Some(_) => CommandStatus::Failed,
+            None => CommandStatus::Unknown,
+        }
+    }
+}
+
+impl CommandStatus {
+    /// Gutter mark color; the WebGPU shader has the same values.
+    pub fn color(self) -> [u8; 3] {
+        match self {
+            CommandStatus::Unknown => [128, 128, 128],
+            CommandStatus::Succeeded => [80, 200, 120],
+            CommandStatus::Failed => [230, 80, 80],
+        }
+    }
+}
+
+/// Terminal modes set by the program running on the server, which decide how
+/// input is encoded.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct TermModes {
+    /// DECCKM: cursor keys send `ESC O x`.
+    pub app_cursor: bool,
+    /// DECKPAM: keypad keys send `ESC O x`.
+    pub app_keypad: bool,
+    /// 2004: wrap pastes in `ESC [200~` / `ESC [201~`.
+    pub bracketed_paste: bool,
+    /// 1004: report focus changes.
+    pub focus_reporting: bool,
+    /// 1049/47: the alternate screen is active.
+    pub alt_screen: bool,
+    /// 1000/1002/1003: mouse button, drag and motion reporting.
+    pub mouse_click: bool,
+    pub mouse_drag: bool,
+    pub mouse_motion: bool,
+    /// 1006/1005: SGR and UTF-8 mouse coordinate encodings.
+    pub sgr_mouse: bool,
+    pub utf8_mouse: bool,
+    /// 9: X10 tracking, button presses only.
+    pub x10_mouse: bool,
+    /// 1015: urxvt coordinate encoding.
+    pub urxvt_mouse: bool,
+    /// 1007: the wheel sends arrow keys on the alternate screen.
+    pub alternate_scroll: bool,
+}
+
+/// Cursor shape requested by the application (DECSCUSR)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CursorShape {
+    #[default]
+    Block,
+    Underline,
+    Beam,
+    /// Outline drawn while the page does not have focus (never sent by the
+    /// server).
+    HollowBlock,
 }
 
 /// Cursor state
@@ -48,6 +350,8 @@ pub struct Cursor {
     pub col: u16,
     pub row: u16,
     pub visible: bool,
+    pub shape: CursorShape,
+    pub blink: bool,
 }
 
 impl Default for Cursor {
@@ -56,35 +360,85 @@ impl Default for Cursor {
             col: 0,
             row: 0,
             visible: true,
+            shape: CursorShape::Block,
+            blink: false,
         }
     }
 }
 
+/// Scrolled-back view: cells and commands of the rows on view, which mix
+/// cached scrollback with the top of the live screen.
+struct View {
+    cells: Vec<Cell>,
+    commands: Vec<CommandMark>,
+}
+
 /// Terminal grid and state
 pub struct Terminal {
     cols: u16,
     rows: u16,
     grid: Vec<Cell>,
     cursor: Cursor,
-    saved_cursor: Cursor,
     parser: Option<Parser>,
     dirty: bool,
 
-    // Current text attributes
-    current_fg: [u8; 3],
-    current_bg: [u8; 3],
-    current_bold: bool,
-    current_italic: bool,
-    current_underline: bool,
-    current_inverse: bool,
-
-    // Scrollback
-    scrollback: Vec<Vec<Cell>>,
+    // Attributes of the next character written (fg and bg not yet swapped
+    // for inverse), and the position and pen saved by DECSC
+    pen: Cell,
+    saved_cursor: (u16, u16),
+    saved_pen: Cell,
+    // Colors SGR picks from; the session's once a snapshot brought them
+    palette: Palette,
+    // Scroll region (DECSTBM), rows `scroll_top..scroll_bottom`
+    scroll_top: u16,
+    scroll_bottom: u16,
+    // The last column was written; the next character wraps first
+    wrap_pending: bool,
+    // DECAWM
+    autowrap: bool,
+    // DEC special graphics in G0 (`ESC ( 0`)
+    line_drawing: bool,
+    // Last character printed, for REP
+    last_char: Option<char>,
+
+    // Scrollback kept when emulating locally, see `history`
     max_scrollback: usize,
+    // Main screen while the alternate screen is shown
+    saved_grid: Option<Vec<Cell>>,
 
     // Modes
-    _application_cursor_keys: bool,
-    _bracketed_paste: bool,
+    modes: TermModes,
+
+    // Window title (OSC 0/2)
+    title: Option<String>,
+    title_changed: bool,
+    bell: bool,
+
+    // Colors from the server's palette (None until the first frame)
+    background: Option<[u8; 3]>,
+    cursor_color: Option<[u8; 3]>,
+
+    // Hyperlinks
+    links: Vec<String>,
+    hover: Option<(u16, u16)>, // (col, row) of a link under the pointer
+
+    // Shell integration (OSC 133)
+    commands: Vec<CommandMark>,
+
+    // Server scrollback, by absolute line number (see `TerminalFrame::top_line`)
+    top_line: u64,
+    history_start: u64,
+    scroll_offset: u64,                   // lines the view is scrolled back
+    history: BTreeMap<u64, Vec<Cell>>,    // link ids from HISTORY_LINK_BASE
+    history_links: Vec<String>,
+    history_link_ids: HashMap<String, u32>,
+    known_commands: BTreeMap<u64, CommandMark>, // by prompt line, rows relative to it
+    history_pending: bool,
+    view: Option<View>,                   // composed while scrolled back
+
+    // Inline images: pixels by id, and where the newest frame placed them
+    images: HashMap<u32, Image>,
+    placements: Vec<ImagePlacement>,
 
     // Selection
     selection_start: Option<(u16, u16)>, // (row, col)
@@ -106,19 +460,40 @@ impl Terminal {
             rows,
             grid,
             cursor: Cursor::default(),
-            saved_cursor: Cursor::default(),
             parser: Some(Parser::new()),
             dirty: true,
-            current_fg: [229, 229, 229],
-            current_bg: [30, 30, 30],
-            current_bold: false,
-            current_italic: false,
-            current_underline: false,
-            current_inverse: false,
-            scrollback: Vec::new(),
+            pen: Cell::default(),
+            saved_cursor: (0, 0),
+            saved_pen: Cell::default(),
+            palette: Palette::default(),
+            scroll_top: 0,
+            scroll_bottom: rows,
+            wrap_pending: false,
+            autowrap: true,
+            line_drawing: false,
+            last_char: None,
             max_scrollback: 10000,
-            _application_cursor_keys: false,
-            _bracketed_paste: false,
+            saved_grid: None,
+            modes: TermModes::default(),
+            title: None,
+            title_changed: false,
+            bell: false,
+            background: None,
+            cursor_color: None,
+            links: Vec::new(),
+            hover: None,
+            commands: Vec::new(),
+            top_line: 0,
+            history_start: 0,
+            scroll_offset: 0,
+            history: BTreeMap::new(),
+            history_links: Vec::new(),
+            history_link_ids: HashMap::new(),
+            known_commands: BTreeMap::new(),
+            history_pending: false,
+            view: None,
+            images: HashMap::new(),
+            placements: Vec::new(),
             selection_start: None,
             selection_end: None,
             selecting: false,
@@ -133,6 +508,7 @@ impl Terminal {
             parser.advance(self, data);
             self.parser = Some(parser);
         }
+        self.compose_view();
         self.dirty = true;
     }
 
@@ -159,16 +535,190 @@ impl Terminal {
         self.cursor.col = frame.cursor_col.min(cols.saturating_sub(1));
         self.cursor.row = frame.cursor_row.min(rows.saturating_sub(1));
         self.cursor.visible = frame.cursor_visible;
+        self.cursor.shape = frame.cursor_shape;
+        self.cursor.blink = frame.cursor_blink;
+        self.modes = frame.modes;
+        self.background = Some(frame.background);
+        self.cursor_color = Some(frame.cursor_color);
+        self.links = frame.links;
+        self.commands = frame.commands;
+        self.saved_grid = None;
+        self.set_images(frame.pixels, frame.images);
+        // Frames carry neither the pen nor the scroll region, so a raw
+        // stream starts from its snapshot with the defaults
+        if let Some(palette) = frame.palette {
+            self.palette = palette;
+        }
+        self.pen = self.default_pen();
+        self.scroll_top = 0;
+        self.scroll_bottom = rows;
+        self.wrap_pending = false;
+
+        // Rewrapping renumbers the scrollback, and the alternate screen has
+        // none
+        if size_changed || self.modes.alt_screen {
+            self.clear_history();
+        } else if self.scroll_offset > 0 {
+            // Keep the scrolled-back view on the same lines
+            self.scroll_offset += frame.top_line.saturating_sub(self.top_line);
+        }
+        self.top_line = frame.top_line;
+        self.history_start = frame.history_start;
+        self.history = self.history.split_off(&frame.history_start);
+        // Commands on screen come from the frame
+        let kept = frame.history_start..frame.top_line;
+        self.known_commands.retain(|prompt, _| kept.contains(prompt));
+        for command in &self.commands {
+            let prompt = frame.top_line as i64 + command.prompt as i64;
+            if prompt >= 0 {
+                self.known_commands.insert(prompt as u64, command.shifted(-(command.prompt as i64)));
+            }
+        }
+        self.scroll_offset = self.scroll_offset.min(self.max_scroll_offset());
 
         if size_changed {
             self.clear_selection();
         }
 
+        self.compose_view();
         self.dirty = true;
     }
 
+    /// Store a page of scrollback from the server.
+    pub fn add_history(&mut self, page: HistoryPage) {
+        self.history_pending = false;
+        let ids: Vec<u32> = page
+            .links
+            .into_iter()
+            .map(|uri| {
+                let next = HISTORY_LINK_BASE + self.history_links.len() as u32;
+                *self.history_link_ids.entry(uri).or_insert_with_key(|uri| {
+                    self.history_links.push(uri.clone());
+                    next
+                })
+            })
+            .collect();
+        for (i, mut line) in page.lines.into_iter().enumerate() {
+            let abs = page.from_line + i as u64;
+            if abs < self.history_start || abs >= self.top_line {
+                continue;
+            }
+            for cell in &mut line {
+                cell.link = cell.link.and_then(|link| ids.get(link as usize).copied());
+            }
+            self.history.insert(abs, line);
+        }
+        for command in page.commands {
+            let prompt = page.from_line as i64 + command.prompt as i64;
+            if prompt >= self.history_start as i64 && prompt < self.top_line as i64 {
+                self.known_commands.insert(prompt as u64, command.shifted(-(command.prompt as i64)));
+            }
+        }
+        if self.scroll_offset > 0 {
+            self.compose_view();
+            self.dirty = true;
+        }
+    }
+
+    /// Scrollback lines to ask the server for: `(from_line, count)` when the
+    /// view, or the page above it, shows lines not fetched yet. One request
+    /// is outstanding at a time.
+    pub fn take_history_request(&mut self) -> Option<(u64, u16)> {
+        if self.scroll_offset == 0 || self.history_pending {
+            return None;
+        }
+        let view_top = self.view_top_line();
+        let first = view_top.saturating_sub(HISTORY_PREFETCH).max(self.history_start);
+        let end = self.top_line.min(view_top + self.rows as u64);
+        let missing = (first..end).find(|line| !self.history.contains_key(line))?;
+        let count = (self.top_line - missing).min(HISTORY_PAGE as u64) as u16;
+        self.history_pending = true;
+        Some((missing, count))
+    }
+
+    /// Drop the fetched scrollback and return to the live screen.
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+        self.history_links.clear();
+        self.history_link_ids.clear();
+        self.known_commands.clear();
+        self.history_pending = false;
+        self.set_scroll_offset(0);
+    }
+
+    /// Scroll the view `delta` lines back into the scrollback (negative =
+    /// towards the live screen)
+    pub fn scroll_view(&mut self, delta: i32) {
+        let offset = self.scroll_offset as i64 + delta as i64;
+        self.set_scroll_offset(offset.max(0) as u64);
+    }
+
+    /// Scroll so an absolute line is at the top of the view
+    pub fn scroll_to_line(&mut self, line: u64) {
+        self.set_scroll_offset(self.top_line.saturating_sub(line));
+    }
+
+    /// Return to the live screen
+    pub fn scroll_to_bottom(&mut self) {
+        self.set_scroll_offset(0);
+    }
+
+    /// Absolute line number of the first row of the view
+    pub fn view_top_line(&self) -> u64 {
+        self.top_line - self.scroll_offset
+    }
+
+    fn max_scroll_offset(&self) -> u64 {
+        self.top_line.saturating_sub(self.history_start)
+    }
+
+    fn set_scroll_offset(&mut self, offset: u64) {
+        let offset = offset.min(self.max_scroll_offset());
+        if offset != self.scroll_offset {
+            self.scroll_offset = offset;
+            self.clear_selection();
+            self.set_hover(None);
+            self.compose_view();
+            self.dirty = true;
+        }
+    }
+
+    /// Build the scrolled-back view from cached scrollback lines and the
+    /// top of the live screen; lines not fetched yet are blank.
+    fn compose_view(&mut self) {
+        if self.scroll_offset == 0 {
+            self.view = None;
+            return;
+        }
+        let cols = self.cols as usize;
+        let view_top = self.view_top_line();
+        let view_end = view_top + self.rows as u64;
+        let blank = self.blank_cell();
+        let mut cells = Vec::with_capacity(cols * self.rows as usize);
+        for line in view_top..view_end {
+            if line >= self.top_line {
+                let start = (line - self.top_line) as usize * cols;
+                cells.extend_from_slice(&self.grid[start..start + cols]);
+                continue;
+            }
+            let cached = self.history.get(&line).map_or(&[][..], Vec::as_slice);
+            let cached = &cached[..cached.len().min(cols)];
+            cells.extend_from_slice(cached);
+            cells.extend(std::iter::repeat_n(blank.clone(), cols - cached.len()));
+        }
+        let commands = self
+            .known_commands
+            .range(..view_end)
+            .filter(|(&prompt, command)| prompt + command.output_end.max(1) as u64 > view_top)
+            .map(|(&prompt, command)| command.shifted(prompt as i64 - view_top as i64))
+            .collect();
+        self.view = Some(View { cells, commands });
+    }
+
     /// Resize terminal
     pub fn resize(&mut self, cols: u16, rows: u16) {
+        // The server rewraps, renumbering its scrollback
+        self.clear_history();
         let new_size = (cols as usize) * (rows as usize);
         let mut new_grid = vec![Cell::default(); new_size];
 
@@ -189,13 +739,125 @@ impl Terminal {
         self.cols = cols;
         self.rows = rows;
         self.grid = new_grid;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows;
 
         // Clamp cursor
         self.cursor.col = self.cursor.col.min(cols.saturating_sub(1));
         self.cursor.row = self.cursor.row.min(rows.saturating_sub(1));
+        self.wrap_pending = false;
         self.dirty = true;
     }
 
+    /// Input modes reported by the server
+    pub fn modes(&self) -> TermModes {
+        self.modes
+    }
+
+    /// Window title set by the running program
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Set or reset (None) the window title
+    pub fn set_title(&mut self, title: Option<String>) {
+        if title != self.title {
+            self.title = title;
+            self.title_changed = true;
+        }
+    }
+
+    /// Whether the title changed since the last call
+    pub fn take_title_changed(&mut self) -> bool {
+        std::mem::take(&mut self.title_changed)
+    }
+
+    /// Whether BEL was received since the last call
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::take(&mut self.bell)
+    }
+
+    /// Hyperlink at a cell: its index in the link table and its URI
+    pub fn link_at(&self, col: u16, row: u16) -> Option<(u32, &str)> {
+        let link = self.cell(col, row)?.link?;
+        let uri = match link.checked_sub(HISTORY_LINK_BASE) {
+            Some(index) => self.history_links.get(index as usize)?,
+            None => self.links.get(link as usize)?,
+        };
+        Some((link, uri))
+    }
+
+    /// Link under the pointer, if any
+    pub fn hovered_link(&self) -> Option<u32> {
+        let (col, row) = self.hover?;
+        self.cell(col, row)?.link
+    }
+
+    /// Set the cell of the link under the pointer (None when not over one)
+    pub fn set_hover(&mut self, hover: Option<(u16, u16)>) {
+        let before = self.hovered_link();
+        self.hover = hover;
+        if self.hovered_link() != before {
+            self.dirty = true;
+        }
+    }
+
+    /// Background color set by the server, which replaces the configured
+    /// one
+    pub fn background(&self) -> Option<[u8; 3]> {
+        self.background
+    }
+
+    /// Cell background that counts as the default and is left to the clear
+    /// color
+    pub fn default_bg(&self) -> [u8; 3] {
+        self.background.unwrap_or(Cell::default().bg)
+    }
+
+    /// Cursor color set by the server, which replaces the configured one
+    pub fn cursor_color(&self) -> Option<[u8; 3]> {
+        self.cursor_color
+    }
+
+    /// Take the images placed by a frame and the pixels new with it,
+    /// dropping the pixels of images no longer placed.
+    fn set_images(&mut self, pixels: Vec<Image>, placements: Vec<ImagePlacement>) {
+        self.images.extend(pixels.into_iter().map(|image| (image.id, image)));
+        self.images.retain(|id, _| placements.iter().any(|placement| placement.id == *id));
+        self.placements = placements;
+    }
+
+    /// Images on view with their row, in drawing order (by z, then oldest
+    /// first); the row is negative when the image starts above the view.
+    pub fn images(&self) -> impl Iterator<Item = (&ImagePlacement, i64, &Image)> {
+        let top = self.view_top_line() as i64;
+        let rows = self.rows as i64;
+        let mut placements: Vec<&ImagePlacement> = self.placements.iter().collect();
+        placements.sort_by_key(|placement| placement.z);
+        placements.into_iter().filter_map(move |placement| {
+            let row = placement.line as i64 - top;
+            let image = self.images.get(&placement.id)?;
+            (row < rows).then_some((placement, row, image))
+        })
+    }
+
+    /// Shell commands overlapping the viewport
+    pub fn commands(&self) -> &[CommandMark] {
+        match &self.view {
+            Some(view) => &view.commands,
+            None => &self.commands,
+        }
+    }
+
+    /// Command whose prompt or output covers a viewport row
+    pub fn command_at(&self, row: u16) -> Option<&CommandMark> {
+        let row = row as i32;
+        self.commands()
+            .iter()
+            .rev()
+            .find(|command| command.prompt <= row && row < command.output_end.max(command.prompt + 1))
+    }
+
     /// Get terminal columns
     pub fn cols(&self) -> u16 {
         self.cols
@@ -220,7 +882,7 @@ impl Terminal {
     pub fn cell(&self, col: u16, row: u16) -> Option<&Cell> {
         if col < self.cols && row < self.rows {
             let idx = row as usize * self.cols as usize + col as usize;
-            self.grid.get(idx)
+            self.cells().get(idx)
         } else {
             None
         }
@@ -236,14 +898,25 @@ impl Terminal {
         }
     }
 
-    /// Get cursor position
+    /// Get cursor position in the view
     pub fn cursor_position(&self) -> (u16, u16) {
-        (self.cursor.col, self.cursor.row)
+        let row = self.cursor.row as u64 + self.scroll_offset;
+        (self.cursor.col, row.min(u16::MAX as u64) as u16)
     }
 
-    /// Is cursor visible
+    /// Is cursor visible (and not scrolled out of view)
     pub fn cursor_visible(&self) -> bool {
-        self.cursor.visible
+        self.cursor.visible && (self.cursor.row as u64 + self.scroll_offset) < self.rows as u64
+    }
+
+    /// Cursor shape requested by the application
+    pub fn cursor_shape(&self) -> CursorShape {
+        self.cursor.shape
+    }
+
+    /// Whether the application asked for a blinking cursor
+    pub fn cursor_blink(&self) -> bool {
+        self.cursor.blink
     }
 
     /// Start selection at (col, row)
@@ -270,6 +943,20 @@ impl Terminal {
         }
     }
 
+    /// Select whole rows `start..end`, clamped to the viewport
+    pub fn select_rows(&mut self, start: i32, end: i32) {
+        let start = start.max(0);
+        let end = end.min(self.rows as i32);
+        if start >= end {
+            self.clear_selection();
+            return;
+        }
+        self.selection_start = Some((start as u16, 0));
+        self.selection_end = Some(((end - 1) as u16, self.cols - 1));
+        self.selecting = false;
+        self.dirty = true;
+    }
+
     /// End selection
     pub fn end_selection(&mut self) {
         self.selecting = false;
@@ -312,7 +999,7 @@ impl Terminal {
                 if let Some(cell) = self.cell(col, row) {
                     // Skip empty cells at the end of line unless it's part of a multi-line selection
                     // For simplicity, just add all chars for now
-                    text.push(cell.c);
+                    cell.push_text(&mut text);
                 }
             }
             
@@ -335,144 +1022,515 @@ impl Terminal {
 
     /// Iterate over all cells
     pub fn iter_cells(&self) -> impl Iterator<Item = (u16, u16, &Cell)> {
+        let cells = self.cells();
         (0..self.rows).flat_map(move |row| {
             (0..self.cols).map(move |col| {
                 let idx = row as usize * self.cols as usize + col as usize;
-                (col, row, &self.grid[idx])
+                (col, row, &cells[idx])
             })
         })
     }
 
-    /// Write character at current cursor position
+    /// Cells on view: the live grid, or the scrolled-back view
+    fn cells(&self) -> &[Cell] {
+        match &self.view {
+            Some(view) => &view.cells,
+            None => &self.grid,
+        }
+    }
+
+    /// The cell a character is written as: the pen, with foreground and
+    /// background swapped for inverse video as the server does
+    fn pen_cell(&self, c: char) -> Cell {
+        let mut cell = Cell { c, ..self.pen.clone() };
+        if cell.inverse {
+            std::mem::swap(&mut cell.fg, &mut cell.bg);
+        }
+        cell
+    }
+
+    /// Pen with every attribute reset
+    fn default_pen(&self) -> Cell {
+        Cell {
+            fg: self.palette.foreground,
+            bg: self.default_bg(),
+            ..Cell::default()
+        }
+    }
+
+    /// Write a printed character at the cursor. Wide characters take two
+    /// cells; zero-width ones join the character before them.
     fn write_char(&mut self, c: char) {
-        // Copy attributes before mutable borrow
-        let fg = if self.current_inverse { self.current_bg } else { self.current_fg };
-        let bg = if self.current_inverse { self.current_fg } else { self.current_bg };
-        let bold = self.current_bold;
-        let italic = self.current_italic;
-        let underline = self.current_underline;
-        let inverse = self.current_inverse;
-        let col = self.cursor.col;
-        let row = self.cursor.row;
+        let c = if self.line_drawing { dec_special_graphics(c) } else { c };
+        let width = match c.width() {
+            Some(0) => return self.write_combining(c),
+            Some(width) => width as u16,
+            None => return,
+        };
+        if width > self.cols {
+            return;
+        }
+        if std::mem::take(&mut self.wrap_pending) && self.autowrap {
+            self.cursor.col = 0;
+            self.linefeed();
+        }
+        // A wide character that does not fit goes to the next line
+        if self.cursor.col + width > self.cols {
+            if self.autowrap {
+                self.cursor.col = 0;
+                self.linefeed();
+            } else {
+                self.cursor.col = self.cols - width;
+            }
+        }
+
+        let (col, row) = (self.cursor.col, self.cursor.row);
+        let mut cell = self.pen_cell(c);
+        self.split_wide(col, row);
+        if width == 2 {
+            self.split_wide(col + 1, row);
+            cell.wide = true;
+            let spacer = Cell { c: ' ', wide: false, spacer: true, ..cell.clone() };
+            if let Some(dst) = self.cell_mut(col + 1, row) {
+                *dst = spacer;
+            }
+        }
+        if let Some(dst) = self.cell_mut(col, row) {
+            *dst = cell;
+        }
+        self.last_char = Some(c);
+
+        // The cursor stays on the last column until the next character
+        if col + width >= self.cols {
+            self.cursor.col = self.cols - 1;
+            self.wrap_pending = self.autowrap;
+        } else {
+            self.cursor.col = col + width;
+        }
+    }
 
+    /// Add a zero-width character to the cluster written last
+    fn write_combining(&mut self, c: char) {
+        let row = self.cursor.row;
+        let mut col = self.cursor.col;
+        if !self.wrap_pending {
+            let Some(before) = col.checked_sub(1) else { return };
+            col = before;
+        }
+        if col > 0 && self.cell_mut(col, row).is_some_and(|cell| cell.spacer) {
+            col -= 1;
+        }
         if let Some(cell) = self.cell_mut(col, row) {
-            cell.c = c;
-            cell.fg = fg;
-            cell.bg = bg;
-            cell.bold = bold;
-            cell.italic = italic;
-            cell.underline = underline;
-            cell.inverse = inverse;
+            cell.combining.push(c);
         }
+    }
 
-        self.cursor.col += 1;
-        if self.cursor.col >= self.cols {
-            self.cursor.col = 0;
+    /// Blank the other half of the wide character a cell about to be
+    /// overwritten belongs to
+    fn split_wide(&mut self, col: u16, row: u16) {
+        let other = match self.cell_mut(col, row) {
+            Some(cell) if cell.spacer => col.checked_sub(1),
+            Some(cell) if cell.wide => Some(col + 1),
+            _ => None,
+        };
+        if let Some(cell) = other.and_then(|other| self.cell_mut(other, row)) {
+            cell.c = ' ';
+            cell.combining.clear();
+            cell.wide = false;
+            cell.spacer = false;
+        }
+    }
+
+    /// Move down a line, scrolling at the bottom of the scroll region. A
+    /// pending wrap stays pending, as on the server.
+    fn linefeed(&mut self) {
+        if self.cursor.row + 1 == self.scroll_bottom {
+            self.scroll_up(self.scroll_top, 1);
+        } else if self.cursor.row + 1 < self.rows {
             self.cursor.row += 1;
-            if self.cursor.row >= self.rows {
-                self.scroll_up();
-                self.cursor.row = self.rows - 1;
-            }
         }
     }
 
-    /// Scroll terminal up by one line
-    fn scroll_up(&mut self) {
-        // Save first line to scrollback
-        if self.scrollback.len() >= self.max_scrollback {
-            self.scrollback.remove(0);
+    /// Move up a line, scrolling at the top of the scroll region (RI)
+    fn reverse_index(&mut self) {
+        self.wrap_pending = false;
+        if self.cursor.row == self.scroll_top {
+            self.scroll_down(self.scroll_top, 1);
+        } else if self.cursor.row > 0 {
+            self.cursor.row -= 1;
         }
-        let first_line: Vec<Cell> = (0..self.cols)
-            .map(|col| self.grid[col as usize].clone())
-            .collect();
-        self.scrollback.push(first_line);
+    }
 
-        // Shift grid up
-        let row_size = self.cols as usize;
-        for row in 0..(self.rows as usize - 1) {
-            for col in 0..row_size {
-                let src_idx = (row + 1) * row_size + col;
-                let dst_idx = row * row_size + col;
-                self.grid[dst_idx] = self.grid[src_idx].clone();
+    /// Scroll rows `origin..scroll_bottom` up `n` lines. Like the server,
+    /// lines scrolled off the top of the screen go to the scrollback, under
+    /// the number the server gives them; the alternate screen keeps none.
+    fn scroll_up(&mut self, origin: u16, n: u16) {
+        let n = n.min(self.scroll_bottom - self.scroll_top);
+        if origin == 0 {
+            self.push_history(n);
+        }
+        let blank = self.erased_cell();
+        let cols = self.cols as usize;
+        let region = self.rows_mut(origin, self.scroll_bottom);
+        let shift = (n as usize * cols).min(region.len());
+        region.rotate_left(shift);
+        let len = region.len();
+        region[len - shift..].fill(blank);
+    }
+
+    /// Copy the first `n` rows to the scrollback, on the main screen
+    fn push_history(&mut self, n: u16) {
+        if self.modes.alt_screen {
+            return;
+        }
+        let cols = self.cols as usize;
+        for row in 0..n.min(self.rows) as usize {
+            let line = self.grid[row * cols..(row + 1) * cols].to_vec();
+            self.history.insert(self.top_line, line);
+            self.top_line += 1;
+        }
+        let start = self.top_line.saturating_sub(self.max_scrollback as u64);
+        self.history_start = self.history_start.max(start);
+        while self.history.first_key_value().is_some_and(|(&line, _)| line < self.history_start) {
+            self.history.pop_first();
+        }
+        if self.scroll_offset > 0 {
+            // Keep the scrolled-back view on the same lines
+            self.scroll_offset = (self.scroll_offset + n as u64).min(self.max_scroll_offset());
+        }
+    }
+
+    /// Scroll rows `origin..scroll_bottom` down `n` lines
+    fn scroll_down(&mut self, origin: u16, n: u16) {
+        let blank = self.erased_cell();
+        let cols = self.cols as usize;
+        let region = self.rows_mut(origin, self.scroll_bottom);
+        let shift = (n as usize * cols).min(region.len());
+        region.rotate_right(shift);
+        region[..shift].fill(blank);
+    }
+
+    /// Cells of rows `top..bottom` of the grid
+    fn rows_mut(&mut self, top: u16, bottom: u16) -> &mut [Cell] {
+        let cols = self.cols as usize;
+        let bottom = bottom.min(self.rows).max(top);
+        &mut self.grid[top as usize * cols..bottom as usize * cols]
+    }
+
+    /// Empty cell on the default background
+    fn blank_cell(&self) -> Cell {
+        Cell {
+            bg: self.default_bg(),
+            ..Cell::default()
+        }
+    }
+
+    /// Empty cell left by erasing, scrolling and insertions: it takes the
+    /// pen's background, as on the server
+    fn erased_cell(&self) -> Cell {
+        Cell {
+            fg: self.palette.foreground,
+            bg: self.pen.bg,
+            ..Cell::default()
+        }
+    }
+
+    /// DEC private modes (`CSI ? Pm h` / `CSI ? Pm l`)
+    fn set_private_modes(&mut self, params: &[u16], set: bool) {
+        for &mode in params {
+            match mode {
+                1 => self.modes.app_cursor = set,
+                7 => self.autowrap = set,
+                9 => self.modes.x10_mouse = set,
+                25 => self.cursor.visible = set,
+                47 | 1047 => self.set_alt_screen(set, false),
+                1049 => self.set_alt_screen(set, true),
+                1000 | 1002 | 1003 => {
+                    // One tracking mode at a time
+                    if set {
+                        self.modes.mouse_click = false;
+                        self.modes.mouse_drag = false;
+                        self.modes.mouse_motion = false;
+                        self.modes.x10_mouse = false;
+                    }
+                    match mode {
+                        1000 => self.modes.mouse_click = set,
+                        1002 => self.modes.mouse_drag = set,
+                        _ => self.modes.mouse_motion = set,
+                    }
+                }
+                1004 => self.modes.focus_reporting = set,
+                1005 => self.modes.utf8_mouse = set,
+                1006 => self.modes.sgr_mouse = set,
+                1007 => self.modes.alternate_scroll = set,
+                1015 => self.modes.urxvt_mouse = set,
+                2004 => self.modes.bracketed_paste = set,
+                _ => {}
             }
         }
+    }
 
-        // Clear last line
-        let last_row = (self.rows - 1) as usize;
-        for col in 0..row_size {
-            self.grid[last_row * row_size + col] = Cell::default();
+    /// Switch to or from the alternate screen, saving the cursor for 1049
+    fn set_alt_screen(&mut self, enter: bool, save_cursor: bool) {
+        if enter == self.modes.alt_screen {
+            return;
         }
+        if enter {
+            if save_cursor {
+                self.save_cursor();
+            }
+            let blank = vec![self.blank_cell(); self.grid.len()];
+            self.saved_grid = Some(std::mem::replace(&mut self.grid, blank));
+            self.scroll_to_bottom();
+        } else {
+            // A resize on the alternate screen discards the main one
+            match self.saved_grid.take() {
+                Some(grid) if grid.len() == self.grid.len() => self.grid = grid,
+                _ => self.clear_screen(),
+            }
+            if save_cursor {
+                self.restore_cursor();
+            }
+        }
+        self.modes.alt_screen = enter;
+    }
+
+    /// Save the cursor position and the pen (DECSC)
+    fn save_cursor(&mut self) {
+        self.saved_cursor = (self.cursor.col, self.cursor.row);
+        self.saved_pen = self.pen.clone();
+    }
+
+    /// Restore what `save_cursor` saved (DECRC), within the current size
+    fn restore_cursor(&mut self) {
+        let (col, row) = self.saved_cursor;
+        self.cursor.col = col.min(self.cols - 1);
+        self.cursor.row = row.min(self.rows - 1);
+        self.pen = self.saved_pen.clone();
+        self.wrap_pending = false;
+    }
+
+    /// Blank columns `cols` of a row
+    fn erase(&mut self, row: u16, cols: std::ops::Range<u16>) {
+        let (start, end) = (cols.start.min(self.cols), cols.end.min(self.cols));
+        if start >= end {
+            return;
+        }
+        self.split_wide(start, row);
+        self.split_wide(end - 1, row);
+        let blank = self.erased_cell();
+        let line = self.rows_mut(row, row + 1);
+        line[start as usize..end as usize].fill(blank);
     }
 
-    /// Clear line from cursor to end
+    /// Clear line from cursor to end; nothing while a wrap is pending, as
+    /// on the server
     fn clear_to_eol(&mut self) {
-        let row = self.cursor.row;
-        for col in self.cursor.col..self.cols {
-            if let Some(cell) = self.cell_mut(col, row) {
-                *cell = Cell::default();
-            }
+        if self.wrap_pending {
+            return;
         }
+        self.erase(self.cursor.row, self.cursor.col..self.cols);
     }
 
     /// Clear line from start to cursor
     fn clear_to_bol(&mut self) {
-        let row = self.cursor.row;
-        for col in 0..=self.cursor.col {
-            if let Some(cell) = self.cell_mut(col, row) {
-                *cell = Cell::default();
-            }
-        }
+        self.erase(self.cursor.row, 0..self.cursor.col + 1);
     }
 
     /// Clear entire line
     fn clear_line(&mut self) {
-        let row = self.cursor.row;
-        for col in 0..self.cols {
-            if let Some(cell) = self.cell_mut(col, row) {
-                *cell = Cell::default();
-            }
-        }
+        self.erase(self.cursor.row, 0..self.cols);
     }
 
     /// Clear screen from cursor to end
     fn clear_to_eos(&mut self) {
-        self.clear_to_eol();
-        for row in (self.cursor.row + 1)..self.rows {
-            for col in 0..self.cols {
-                if let Some(cell) = self.cell_mut(col, row) {
-                    *cell = Cell::default();
-                }
-            }
-        }
+        self.erase(self.cursor.row, self.cursor.col..self.cols);
+        let blank = self.erased_cell();
+        self.rows_mut(self.cursor.row + 1, self.rows).fill(blank);
     }
 
     /// Clear screen from start to cursor
     fn clear_to_bos(&mut self) {
         self.clear_to_bol();
-        for row in 0..self.cursor.row {
-            for col in 0..self.cols {
-                if let Some(cell) = self.cell_mut(col, row) {
-                    *cell = Cell::default();
-                }
-            }
+        let blank = self.erased_cell();
+        self.rows_mut(0, self.cursor.row).fill(blank);
+    }
+
+    /// Erase the screen (ED 2). Like the server, the main screen first
+    /// scrolls its rows down to the last one in use into the scrollback.
+    fn erase_display(&mut self) {
+        let (fg, bg) = (self.palette.foreground, self.default_bg());
+        let used = |cell: &Cell| {
+            cell.c != ' '
+                || cell.fg != fg
+                || cell.bg != bg
+                || cell.inverse
+                || cell.strikeout
+                || cell.spacer
+                || cell.underline != Underline::None
+        };
+        // The server's search for that row stops at the top of the grid, so
+        // a blank screen without scrollback still pushes its first row.
+        let blank = usize::from(self.top_line == self.history_start);
+        let in_use = self.grid.iter().rposition(used).map_or(blank, |last| last / self.cols as usize + 1);
+        self.push_history(in_use as u16);
+        self.clear_screen();
+    }
+
+    /// Drop the scrollback (ED 3), as the server does
+    fn erase_scrollback(&mut self) {
+        if !self.modes.alt_screen {
+            self.history.clear();
+            self.known_commands.clear();
+            self.history_start = self.top_line;
+            self.set_scroll_offset(0);
         }
     }
 
     /// Clear entire screen
     fn clear_screen(&mut self) {
-        for cell in &mut self.grid {
-            *cell = Cell::default();
+        let blank = self.erased_cell();
+        self.grid.fill(blank);
+    }
+
+    /// Insert blank cells at the cursor, shifting the rest of the line right
+    /// (ICH)
+    fn insert_blanks(&mut self, count: u16) {
+        let (col, row) = (self.cursor.col, self.cursor.row);
+        self.split_wide(col, row);
+        let blank = self.erased_cell();
+        let line = &mut self.rows_mut(row, row + 1)[col as usize..];
+        let count = (count as usize).min(line.len());
+        line.rotate_right(count);
+        line[..count].fill(blank);
+    }
+
+    /// Delete cells at the cursor, shifting the rest of the line left (DCH)
+    fn delete_chars(&mut self, count: u16) {
+        let (col, row) = (self.cursor.col, self.cursor.row);
+        self.split_wide(col, row);
+        self.split_wide(col.saturating_add(count).min(self.cols) - 1, row);
+        let blank = self.erased_cell();
+        let line = &mut self.rows_mut(row, row + 1)[col as usize..];
+        let count = (count as usize).min(line.len());
+        line.rotate_left(count);
+        let len = line.len();
+        line[len - count..].fill(blank);
+    }
+
+    /// Set the scroll region to rows `top..bottom` (DECSTBM), which homes
+    /// the cursor
+    fn set_scroll_region(&mut self, top: u16, bottom: u16) {
+        let bottom = bottom.min(self.rows);
+        if top >= bottom {
+            return;
         }
+        self.scroll_top = top;
+        self.scroll_bottom = bottom;
+        self.cursor.col = 0;
+        self.cursor.row = 0;
+        self.wrap_pending = false;
     }
 
     /// Reset text attributes
     fn reset_attributes(&mut self) {
-        self.current_fg = [229, 229, 229];
-        self.current_bg = [30, 30, 30];
-        self.current_bold = false;
-        self.current_italic = false;
-        self.current_underline = false;
-        self.current_inverse = false;
+        self.pen = self.default_pen();
+    }
+
+    /// Select Graphic Rendition (SGR), in both the semicolon and the colon
+    /// forms (`4:3`, `38:2::r:g:b`)
+    fn set_attributes(&mut self, params: &Params) {
+        if params.is_empty() {
+            self.reset_attributes();
+            return;
+        }
+        let mut params = params.iter();
+        while let Some(param) = params.next() {
+            match param {
+                [0] => self.reset_attributes(),
+                [1] => self.pen.bold = true,
+                [2] => self.pen.dim = true,
+                [3] => self.pen.italic = true,
+                [4] => self.pen.underline = Underline::Single,
+                [4, style, ..] => {
+                    self.pen.underline = match style {
+                        0 => Underline::None,
+                        1 => Underline::Single,
+                        2 => Underline::Double,
+                        3 => Underline::Curly,
+                        4 => Underline::Dotted,
+                        5 => Underline::Dashed,
+                        _ => continue,
+                    }
+                }
+                [7] => self.pen.inverse = true,
+                [8] => self.pen.hidden = true,
+                [9] => self.pen.strikeout = true,
+                [21] => self.pen.underline = Underline::Double,
+                [22] => {
+                    self.pen.bold = false;
+                    self.pen.dim = false;
+                }
+                [23] => self.pen.italic = false,
+                [24] => self.pen.underline = Underline::None,
+                [27] => self.pen.inverse = false,
+                [28] => self.pen.hidden = false,
+                [29] => self.pen.strikeout = false,
+                [n @ 30..=37] => self.pen.fg = self.palette.color(n - 30),
+                [38, color @ ..] => {
+                    if let Some(color) = self.extended_color(color, &mut params) {
+                        self.pen.fg = color;
+                    }
+                }
+                [39] => self.pen.fg = self.palette.foreground,
+                [n @ 40..=47] => self.pen.bg = self.palette.color(n - 40),
+                [48, color @ ..] => {
+                    if let Some(color) = self.extended_color(color, &mut params) {
+                        self.pen.bg = color;
+                    }
+                }
+                [49] => self.pen.bg = self.default_bg(),
+                [58, color @ ..] => {
+                    if let Some(color) = self.extended_color(color, &mut params) {
+                        self.pen.underline_color = Some(color);
+                    }
+                }
+                [59] => self.pen.underline_color = None,
+                [n @ 90..=97] => self.pen.fg = self.palette.color(n - 90 + 8),
+                [n @ 100..=107] => self.pen.bg = self.palette.color(n - 100 + 8),
+                _ => {}
+            }
+        }
+    }
+
+    /// Color of SGR 38/48/58: from colon subparameters (`38:5:n`,
+    /// `38:2::r:g:b`, `38:2:r:g:b`) when there are any, else from the
+    /// parameters that follow (`38;5;n`, `38;2;r;g;b`)
+    fn extended_color(&self, subparams: &[u16], params: &mut ParamsIter) -> Option<[u8; 3]> {
+        let channel = |value: u16| value.min(255) as u8;
+        match subparams {
+            [5, index, ..] => return Some(self.palette.color(*index)),
+            [2, _, r, g, b, ..] | [2, r, g, b] => return Some([channel(*r), channel(*g), channel(*b)]),
+            [] => {}
+            _ => return None,
+        }
+        let mut next = || params.next().and_then(|param| param.first().copied());
+        match next()? {
+            5 => Some(self.palette.color(next()?)),
+            2 => Some([channel(next()?), channel(next()?), channel(next()?)]),
+            _ => None,
+        }
+    }
+
+    /// Index of a hyperlink URI in the link table, adding it if new
+    fn link_id(&mut self, uri: String) -> u32 {
+        match self.links.iter().position(|link| *link == uri) {
+            Some(index) => index as u32,
+            None => {
+                self.links.push(uri);
+                self.links.len() as u32 - 1
+            }
+        }
     }
 
     /// Write character speculatively for local echo.
@@ -483,29 +1541,16 @@ impl Terminal {
     /// The server is authoritative - when the next frame arrives, it will
     /// overwrite any speculative state, correcting any mispredictions.
     pub fn write_char_speculative(&mut self, c: char) -> bool {
-        // Only predict printable ASCII (0x20-0x7E)
-        if !c.is_ascii_graphic() && c != ' ' {
+        // Only predict printable ASCII (0x20-0x7E), and not across a wrap
+        if (!c.is_ascii_graphic() && c != ' ') || self.wrap_pending {
             return false;
         }
 
-        // Copy attributes before mutable borrow (same pattern as write_char)
-        let fg = if self.current_inverse { self.current_bg } else { self.current_fg };
-        let bg = if self.current_inverse { self.current_fg } else { self.current_bg };
-        let bold = self.current_bold;
-        let italic = self.current_italic;
-        let underline = self.current_underline;
-        let inverse = self.current_inverse;
         let col = self.cursor.col;
         let row = self.cursor.row;
-
-        if let Some(cell) = self.cell_mut(col, row) {
-            cell.c = c;
-            cell.fg = fg;
-            cell.bg = bg;
-            cell.bold = bold;
-            cell.italic = italic;
-            cell.underline = underline;
-            cell.inverse = inverse;
+        let cell = self.pen_cell(c);
+        if let Some(dst) = self.cell_mut(col, row) {
+            *dst = cell;
         } else {
             return false;
         }
@@ -536,30 +1581,30 @@ impl Perform for Terminal {
         match byte {
             // Bell
             0x07 => {
-                tracing::debug!("Bell");
+                self.bell = true;
             }
             // Backspace
             0x08 => {
+                self.wrap_pending = false;
                 if self.cursor.col > 0 {
                     self.cursor.col -= 1;
                 }
             }
-            // Tab
+            // Tab; after the last column it wraps
+            0x09 if std::mem::take(&mut self.wrap_pending) => {
+                self.cursor.col = 0;
+                self.linefeed();
+            }
             0x09 => {
                 let tab_stop = ((self.cursor.col / 8) + 1) * 8;
                 self.cursor.col = tab_stop.min(self.cols - 1);
             }
             // Line feed / Vertical tab / Form feed
-            0x0A..=0x0C => {
-                self.cursor.row += 1;
-                if self.cursor.row >= self.rows {
-                    self.scroll_up();
-                    self.cursor.row = self.rows - 1;
-                }
-            }
+            0x0A..=0x0C => self.linefeed(),
             // Carriage return
             0x0D => {
                 self.cursor.col = 0;
+                self.wrap_pending = false;
             }
             _ => {}
         }
@@ -572,43 +1617,72 @@ impl Perform for Terminal {
     fn unhook(&mut self) {}
 
     fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
-        // Handle OSC sequences (e.g., window title)
-        if params.len() >= 2 {
-            match params[0] {
-                b"0" | b"2" => {
-                    // Set window title
-                    if let Ok(title) = std::str::from_utf8(params[1]) {
-                        tracing::debug!("Title: {}", title);
-                    }
+        match params {
+            // Window title, which may contain the separator
+            [b"0" | b"2", title @ ..] if !title.is_empty() => {
+                if let Ok(title) = String::from_utf8(title.join(&b';')) {
+                    self.set_title(Some(title));
                 }
-                _ => {}
             }
+            // Hyperlink (OSC 8 ; params ; URI); an empty URI ends it
+            [b"8", _, uri @ ..] => {
+                let uri = uri.join(&b';');
+                self.pen.link = match String::from_utf8(uri) {
+                    Ok(uri) if !uri.is_empty() => Some(self.link_id(uri)),
+                    _ => None,
+                };
+            }
+            _ => {}
         }
     }
 
-    fn csi_dispatch(&mut self, params: &Params, _intermediates: &[u8], _ignore: bool, action: char) {
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, action: char) {
+        if action == 'm' && intermediates.is_empty() {
+            self.set_attributes(params);
+            return;
+        }
         let params: Vec<u16> = params.iter().map(|p| p.first().copied().unwrap_or(0)).collect();
+        // First parameter as a count, where 0 means 1 as well
+        let count = params.first().copied().unwrap_or(1).max(1);
+        if matches!(action, 'A'..='H' | 'd' | 'f' | '`') {
+            self.wrap_pending = false;
+        }
 
         match action {
-            // Cursor Up
+            // Cursor Up, stopping at the top margin
             'A' => {
-                let n = params.first().copied().unwrap_or(1).max(1);
-                self.cursor.row = self.cursor.row.saturating_sub(n);
+                let top = if self.cursor.row >= self.scroll_top { self.scroll_top } else { 0 };
+                self.cursor.row = self.cursor.row.saturating_sub(count).max(top);
             }
-            // Cursor Down
+            // Cursor Down, stopping at the bottom margin
             'B' => {
-                let n = params.first().copied().unwrap_or(1).max(1);
-                self.cursor.row = (self.cursor.row + n).min(self.rows - 1);
+                let bottom = if self.cursor.row < self.scroll_bottom { self.scroll_bottom } else { self.rows };
+                self.cursor.row = self.cursor.row.saturating_add(count).min(bottom - 1);
             }
             // Cursor Forward
             'C' => {
-                let n = params.first().copied().unwrap_or(1).max(1);
-                self.cursor.col = (self.cursor.col + n).min(self.cols - 1);
+                self.cursor.col = self.cursor.col.saturating_add(count).min(self.cols - 1);
             }
             // Cursor Back
             'D' => {
-                let n = params.first().copied().unwrap_or(1).max(1);
-                self.cursor.col = self.cursor.col.saturating_sub(n);
+                self.cursor.col = self.cursor.col.saturating_sub(count);
+            }
+            // Cursor Next / Previous Line (CNL / CPL)
+            'E' => {
+                self.cursor.row = self.cursor.row.saturating_add(count).min(self.rows - 1);
+                self.cursor.col = 0;
+            }
+            'F' => {
+                self.cursor.row = self.cursor.row.saturating_sub(count);
+                self.cursor.col = 0;
+            }
+            // Cursor Horizontal Absolute (CHA / HPA)
+            'G' | '`' => {
+                self.cursor.col = (count - 1).min(self.cols - 1);
+            }
+            // Line Position Absolute (VPA)
+            'd' => {
+                self.cursor.row = (count - 1).min(self.rows - 1);
             }
             // Cursor Position (CUP)
             'H' | 'f' => {
@@ -622,7 +1696,8 @@ impl Perform for Terminal {
                 match params.first().copied().unwrap_or(0) {
                     0 => self.clear_to_eos(),
                     1 => self.clear_to_bos(),
-                    2 | 3 => self.clear_screen(),
+                    2 => self.erase_display(),
+                    3 => self.erase_scrollback(),
                     _ => {}
                 }
             }
@@ -635,117 +1710,61 @@ impl Perform for Terminal {
                     _ => {}
                 }
             }
-            // Select Graphic Rendition (SGR)
-            'm' => {
-                if params.is_empty() {
-                    self.reset_attributes();
-                    return;
-                }
-
-                let mut i = 0;
-                while i < params.len() {
-                    match params[i] {
-                        0 => self.reset_attributes(),
-                        1 => self.current_bold = true,
-                        3 => self.current_italic = true,
-                        4 => self.current_underline = true,
-                        7 => self.current_inverse = true,
-                        22 => self.current_bold = false,
-                        23 => self.current_italic = false,
-                        24 => self.current_underline = false,
-                        27 => self.current_inverse = false,
-                        // Foreground colors
-                        30 => self.current_fg = [0, 0, 0],
-                        31 => self.current_fg = [205, 49, 49],
-                        32 => self.current_fg = [13, 188, 121],
-                        33 => self.current_fg = [229, 229, 16],
-                        34 => self.current_fg = [36, 114, 200],
-                        35 => self.current_fg = [188, 63, 188],
-                        36 => self.current_fg = [17, 168, 205],
-                        37 => self.current_fg = [229, 229, 229],
-                        39 => self.current_fg = [229, 229, 229], // Default
-                        // Bright foreground
-                        90 => self.current_fg = [102, 102, 102],
-                        91 => self.current_fg = [241, 76, 76],
-                        92 => self.current_fg = [35, 209, 139],
-                        93 => self.current_fg = [245, 245, 67],
-                        94 => self.current_fg = [59, 142, 234],
-                        95 => self.current_fg = [214, 112, 214],
-                        96 => self.current_fg = [41, 184, 219],
-                        97 => self.current_fg = [255, 255, 255],
-                        // Background colors
-                        40 => self.current_bg = [0, 0, 0],
-                        41 => self.current_bg = [205, 49, 49],
-                        42 => self.current_bg = [13, 188, 121],
-                        43 => self.current_bg = [229, 229, 16],
-                        44 => self.current_bg = [36, 114, 200],
-                        45 => self.current_bg = [188, 63, 188],
-                        46 => self.current_bg = [17, 168, 205],
-                        47 => self.current_bg = [229, 229, 229],
-                        49 => self.current_bg = [30, 30, 30], // Default
-                        // Bright background
-                        100 => self.current_bg = [102, 102, 102],
-                        101 => self.current_bg = [241, 76, 76],
-                        102 => self.current_bg = [35, 209, 139],
-                        103 => self.current_bg = [245, 245, 67],
-                        104 => self.current_bg = [59, 142, 234],
-                        105 => self.current_bg = [214, 112, 214],
-                        106 => self.current_bg = [41, 184, 219],
-                        107 => self.current_bg = [255, 255, 255],
-                        // 256 colors / RGB
-                        38 => {
-                            if params.len() > i + 2 && params[i + 1] == 5 {
-                                // 256 color
-                                self.current_fg = color_256(params[i + 2] as u8);
-                                i += 2;
-                            } else if params.len() > i + 4 && params[i + 1] == 2 {
-                                // RGB
-                                self.current_fg = [
-                                    params[i + 2] as u8,
-                                    params[i + 3] as u8,
-                                    params[i + 4] as u8,
-                                ];
-                                i += 4;
-                            }
-                        }
-                        48 => {
-                            if params.len() > i + 2 && params[i + 1] == 5 {
-                                // 256 color
-                                self.current_bg = color_256(params[i + 2] as u8);
-                                i += 2;
-                            } else if params.len() > i + 4 && params[i + 1] == 2 {
-                                // RGB
-                                self.current_bg = [
-                                    params[i + 2] as u8,
-                                    params[i + 3] as u8,
-                                    params[i + 4] as u8,
-                                ];
-                                i += 4;
-                            }
-                        }
-                        _ => {}
+            // Insert / Delete Lines (IL / DL), inside the scroll region
+            'L' if (self.scroll_top..self.scroll_bottom).contains(&self.cursor.row) => {
+                self.scroll_down(self.cursor.row, count);
+            }
+            'M' if (self.scroll_top..self.scroll_bottom).contains(&self.cursor.row) => {
+                self.scroll_up(self.cursor.row, count);
+            }
+            // Insert / Delete / Erase Characters (ICH / DCH / ECH)
+            '@' if intermediates.is_empty() => self.insert_blanks(count),
+            'P' => self.delete_chars(count),
+            'X' => {
+                let col = self.cursor.col;
+                self.erase(self.cursor.row, col..col.saturating_add(count));
+            }
+            // Scroll Up / Down (SU / SD)
+            'S' if intermediates.is_empty() => self.scroll_up(self.scroll_top, count),
+            'T' if intermediates.is_empty() => self.scroll_down(self.scroll_top, count),
+            // Repeat the last character (REP)
+            'b' => {
+                if let Some(c) = self.last_char {
+                    for _ in 0..count {
+                        self.write_char(c);
                     }
-                    i += 1;
                 }
             }
-            // Save cursor
-            's' => {
-                self.saved_cursor = self.cursor.clone();
+            // Set Top and Bottom Margins (DECSTBM)
+            'r' if intermediates.is_empty() => {
+                let top = params.first().copied().unwrap_or(1).max(1) - 1;
+                let bottom = match params.get(1).copied() {
+                    Some(bottom) if bottom > 0 => bottom,
+                    _ => self.rows,
+                };
+                self.set_scroll_region(top, bottom);
             }
-            // Restore cursor
-            'u' => {
-                self.cursor = self.saved_cursor.clone();
+            // Cursor style (DECSCUSR): odd styles blink
+            'q' if intermediates == b" " => {
+                let style = params.first().copied().unwrap_or(0);
+                self.cursor.shape = match style {
+                    0..=2 => CursorShape::Block,
+                    3 | 4 => CursorShape::Underline,
+                    5 | 6 => CursorShape::Beam,
+                    _ => return,
+                };
+                self.cursor.blink = style % 2 == 1;
             }
-            // Show cursor
-            'h' if params.first() == Some(&25) => {
-                self.cursor.visible = true;
-            }
-            // Hide cursor
-            'l' if params.first() == Some(&25) => {
-                self.cursor.visible = false;
+            // Save cursor
+            's' if intermediates.is_empty() => self.save_cursor(),
+            // Restore cursor
+            'u' if intermediates.is_empty() => self.restore_cursor(),
+            // Set / reset DEC private modes
+            'h' | 'l' if intermediates == b"?" => {
+                self.set_private_modes(&params, action == 'h');
             }
             // Device Status Report (DSR)
-            'n' => {
+            'n' if intermediates.is_empty() => {
                 let code = params.first().copied().unwrap_or(0);
                 match code {
                     5 => {
@@ -768,28 +1787,56 @@ impl Perform for Terminal {
         }
     }
 
-    fn esc_dispatch(&mut self, _intermediates: &[u8], _ignore: bool, byte: u8) {
-        match byte {
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        match (intermediates, byte) {
             // Save cursor (DECSC)
-            b'7' => {
-                self.saved_cursor = self.cursor.clone();
-            }
+            ([], b'7') => self.save_cursor(),
             // Restore cursor (DECRC)
-            b'8' => {
-                self.cursor = self.saved_cursor.clone();
+            ([], b'8') => self.restore_cursor(),
+            // Index, Next Line and Reverse Index (IND / NEL / RI)
+            ([], b'D') => self.linefeed(),
+            ([], b'E') => {
+                self.cursor.col = 0;
+                self.linefeed();
             }
+            ([], b'M') => self.reverse_index(),
+            // Application / normal keypad (DECKPAM / DECKPNM)
+            ([], b'=') => self.modes.app_keypad = true,
+            ([], b'>') => self.modes.app_keypad = false,
             // Reset
-            b'c' => {
+            ([], b'c') => {
+                self.reset_attributes();
                 self.clear_screen();
                 self.cursor = Cursor::default();
-                self.reset_attributes();
+                self.scroll_top = 0;
+                self.scroll_bottom = self.rows;
+                self.wrap_pending = false;
+                self.autowrap = true;
+                self.line_drawing = false;
             }
+            // G0 character set: DEC special graphics or ASCII
+            ([b'('], b'0') => self.line_drawing = true,
+            ([b'('], _) => self.line_drawing = false,
             _ => {}
         }
     }
 }
 
-/// Convert 256-color index to RGB
+/// DEC special graphics (`ESC ( 0`), which replaces `_` to `~` with line
+/// drawing characters and symbols
+fn dec_special_graphics(c: char) -> char {
+    const GRAPHICS: [char; 32] = [
+        ' ', '◆', '▒', '␉', '␌', '␍', '␊', '°', '±', '␤', '␋', '┘', '┐', '┌', '└', '┼',
+        '⎺', '⎻', '─', '⎼', '⎽', '├', '┤', '┴', '┬', '│', '≤', '≥', 'π', '≠', '£', '·',
+    ];
+    match c {
+        '_'..='~' => GRAPHICS[c as usize - '_' as usize],
+        _ => c,
+    }
+}
+
+/// Default RGB of a 256-color index, until the server sends the session's
+/// palette
 fn color_256(idx: u8) -> [u8; 3] {
     match idx {
         0 => [0, 0, 0],
@@ -823,3 +1870,221 @@ fn color_256(idx: u8) -> [u8; 3] {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terminal(cols: u16, rows: u16, bytes: &[u8]) -> Terminal {
+        let mut terminal = Terminal::new(cols, rows);
+        terminal.process(bytes);
+        terminal
+    }
+
+    /// Text of a row, spacers shown as `~` and trailing blanks dropped.
+    fn row(terminal: &Terminal, row: u16) -> String {
+        let mut text = String::new();
+        for col in 0..terminal.cols() {
+            let cell = terminal.cell(col, row).unwrap();
+            if cell.spacer {
+                text.push('~');
+            } else {
+                cell.push_text(&mut text);
+            }
+        }
+        text.trim_end().to_string()
+    }
+
+    fn rows(terminal: &Terminal) -> Vec<String> {
+        (0..terminal.rows()).map(|r| row(terminal, r)).collect()
+    }
+
+    #[test]
+    fn wide_and_combining_characters() {
+        let t = terminal(6, 2, "a\u{4e2d}e\u{301}".as_bytes());
+        assert_eq!(row(&t, 0), "a\u{4e2d}~e\u{301}");
+        assert!(t.cell(1, 0).unwrap().wide);
+        assert_eq!(t.cursor_position(), (4, 0));
+
+        // A wide character that does not fit wraps whole
+        let t = terminal(5, 2, "abcd\u{4e2d}".as_bytes());
+        assert_eq!(rows(&t), ["abcd", "\u{4e2d}~"]);
+
+        // Overwriting either half clears the other
+        let t = terminal(6, 1, "\u{4e2d}\u{4e2d}\x1b[2Gx\x1b[3Gy".as_bytes());
+        assert_eq!(row(&t, 0), " xy");
+        assert!((0..6).all(|col| !t.cell(col, 0).unwrap().wide && !t.cell(col, 0).unwrap().spacer));
+    }
+
+    #[test]
+    fn autowrap_is_deferred() {
+        let t = terminal(4, 2, b"abcd");
+        assert_eq!(t.cursor_position(), (3, 0));
+        let t = terminal(4, 2, b"abcdef");
+        assert_eq!(rows(&t), ["abcd", "ef"]);
+        let t = terminal(4, 2, b"\x1b[?7labcdef");
+        assert_eq!(rows(&t), ["abcf", ""]);
+    }
+
+    #[test]
+    fn scroll_region() {
+        let t = terminal(4, 5, b"1\r\n2\r\n3\r\n4\r\n5\x1b[2;4r\x1b[4H\n");
+        assert_eq!(rows(&t), ["1", "3", "4", "", "5"]);
+        assert_eq!(t.cursor_position(), (0, 3));
+
+        // Reverse index at the top margin scrolls the region down
+        let t = terminal(4, 5, b"1\r\n2\r\n3\r\n4\r\n5\x1b[2;4r\x1b[2H\x1bM");
+        assert_eq!(rows(&t), ["1", "", "2", "3", "5"]);
+
+        let t = terminal(4, 5, b"1\r\n2\r\n3\r\n4\r\n5\x1b[2;4r\x1b[2S");
+        assert_eq!(rows(&t), ["1", "4", "", "", "5"]);
+        let t = terminal(4, 5, b"1\r\n2\r\n3\r\n4\r\n5\x1b[2;4r\x1b[T");
+        assert_eq!(rows(&t), ["1", "", "2", "3", "5"]);
+    }
+
+    #[test]
+    fn insert_and_delete_lines() {
+        let t = terminal(4, 5, b"1\r\n2\r\n3\r\n4\r\n5\x1b[2;4r\x1b[2H\x1b[L");
+        assert_eq!(rows(&t), ["1", "", "2", "3", "5"]);
+        let t = terminal(4, 5, b"1\r\n2\r\n3\r\n4\r\n5\x1b[2;4r\x1b[2H\x1b[2M");
+        assert_eq!(rows(&t), ["1", "4", "", "", "5"]);
+        // Outside the region they do nothing
+        let t = terminal(4, 5, b"1\r\n2\r\n3\r\n4\r\n5\x1b[2;4r\x1b[5H\x1b[L\x1b[1H\x1b[M");
+        assert_eq!(rows(&t), ["1", "2", "3", "4", "5"]);
+    }
+
+    #[test]
+    fn insert_delete_and_erase_characters() {
+        let t = terminal(8, 1, b"abcdef\x1b[3G\x1b[2@");
+        assert_eq!(row(&t, 0), "ab  cdef");
+        let t = terminal(8, 1, b"abcdef\x1b[2G\x1b[2P");
+        assert_eq!(row(&t, 0), "adef");
+        let t = terminal(8, 1, b"abcdef\x1b[2G\x1b[3X");
+        assert_eq!(row(&t, 0), "a   ef");
+        assert_eq!(t.cursor_position(), (1, 0));
+        // Deleting into a wide character clears all of it
+        let t = terminal(8, 1, "ab\u{4e2d}cd\x1b[2G\x1b[2P".as_bytes());
+        assert_eq!(row(&t, 0), "a cd");
+    }
+
+    #[test]
+    fn repeat_last_character() {
+        let t = terminal(8, 2, b"ab\x1b[3b");
+        assert_eq!(row(&t, 0), "abbbb");
+        let t = terminal(4, 2, b"a\x1b[5b");
+        assert_eq!(rows(&t), ["aaaa", "aa"]);
+    }
+
+    #[test]
+    fn huge_counts_are_clamped() {
+        for sequence in [
+            "\x1b[65535P", "\x1b[65535@", "\x1b[65535X", "\x1b[65535L", "\x1b[65535M",
+            "\x1b[65535S", "\x1b[65535T", "\x1b[65535C", "\x1b[65535B", "\x1b[65535E",
+            "\x1b[65535G", "\x1b[65535d", "\x1b[65535;65535H", "\x1b[65535b",
+        ] {
+            let t = terminal(8, 3, format!("ab\u{4e2d}cd\x1b[2;2H{sequence}x").as_bytes());
+            let (col, row) = t.cursor_position();
+            assert!(col < 8 && row < 3, "{sequence:?}");
+        }
+        let t = terminal(8, 1, "ab\u{4e2d}cd\x1b[2G\x1b[65535P".as_bytes());
+        assert_eq!(row(&t, 0), "a");
+    }
+
+    #[test]
+    fn sgr_attributes() {
+        let palette = Palette::default();
+        let t = terminal(8, 1, b"\x1b[1;3;4;9ma\x1b[22;23;24;29mb\x1b[2;8mc");
+        let a = t.cell(0, 0).unwrap();
+        assert!(a.bold && a.italic && a.strikeout && a.underline == Underline::Single);
+        let b = t.cell(1, 0).unwrap();
+        assert!(!b.bold && !b.italic && !b.strikeout && b.underline == Underline::None);
+        let c = t.cell(2, 0).unwrap();
+        assert!(c.dim && c.hidden);
+
+        let t = terminal(8, 1, b"\x1b[4:3ma\x1b[21mb\x1b[4:0mc\x1b[4;58:2::1:2:3md\x1b[59me");
+        let underlines: Vec<_> = (0..5).map(|col| t.cell(col, 0).unwrap().underline).collect();
+        assert_eq!(
+            underlines,
+            [Underline::Curly, Underline::Double, Underline::None, Underline::Single, Underline::Single]
+        );
+        assert_eq!(t.cell(3, 0).unwrap().underline_color, Some([1, 2, 3]));
+        assert_eq!(t.cell(4, 0).unwrap().underline_color, None);
+
+        // Colon and semicolon forms, indexed, bright and direct
+        let t = terminal(8, 1, b"\x1b[38:2::10:20:30ma\x1b[38;2;1;2;3mb\x1b[48:5:196mc\x1b[31;104md\x1b[0me");
+        assert_eq!(t.cell(0, 0).unwrap().fg, [10, 20, 30]);
+        assert_eq!(t.cell(1, 0).unwrap().fg, [1, 2, 3]);
+        assert_eq!(t.cell(2, 0).unwrap().bg, palette.color(196));
+        assert_eq!((t.cell(3, 0).unwrap().fg, t.cell(3, 0).unwrap().bg), (palette.color(1), palette.color(12)));
+        let e = t.cell(4, 0).unwrap();
+        assert_eq!((e.fg, e.bg), (palette.foreground, t.default_bg()));
+
+        // Inverse swaps the colors as the cell is written
+        let t = terminal(8, 1, b"\x1b[31;7ma");
+        let a = t.cell(0, 0).unwrap();
+        assert!(a.inverse);
+        assert_eq!((a.fg, a.bg), (t.default_bg(), palette.color(1)));
+    }
+
+    #[test]
+    fn session_palette() {
+        let mut t = Terminal::new(4, 1);
+        let mut frame = TerminalFrame {
+            cols: 4,
+            rows: 1,
+            cursor_col: 0,
+            cursor_row: 0,
+            cursor_visible: true,
+            cursor_shape: CursorShape::Block,
+            cursor_blink: false,
+            modes: TermModes::default(),
+            background: default_background(),
+            cursor_color: default_cursor_color(),
+            top_line: 0,
+            history_start: 0,
+            links: Vec::new(),
+            commands: Vec::new(),
+            images: Vec::new(),
+            cells: vec![Cell::default(); 4],
+            pixels: Vec::new(),
+            palette: None,
+        };
+        let mut palette = Palette { foreground: [1, 1, 1], ..Palette::default() };
+        palette.indexed[1] = [9, 0, 0];
+        frame.palette = Some(palette);
+        t.apply_frame(frame);
+        t.process(b"a\x1b[31mb");
+        assert_eq!(t.cell(0, 0).unwrap().fg, [1, 1, 1]);
+        assert_eq!(t.cell(1, 0).unwrap().fg, [9, 0, 0]);
+    }
+
+    #[test]
+    fn cursor_style() {
+        let mut t = terminal(4, 1, b"\x1b[5 q");
+        assert_eq!((t.cursor_shape(), t.cursor_blink()), (CursorShape::Beam, true));
+        t.process(b"\x1b[4 q");
+        assert_eq!((t.cursor_shape(), t.cursor_blink()), (CursorShape::Underline, false));
+        t.process(b"\x1b[1 q");
+        assert_eq!((t.cursor_shape(), t.cursor_blink()), (CursorShape::Block, true));
+        // Unknown styles are ignored
+        t.process(b"\x1b[9 q");
+        assert_eq!((t.cursor_shape(), t.cursor_blink()), (CursorShape::Block, true));
+    }
+
+    #[test]
+    fn hyperlinks() {
+        let t = terminal(8, 1, b"\x1b]8;;https://example.com/a;b\x1b\\ab\x1b]8;;\x07c");
+        let (id, uri) = t.link_at(0, 0).unwrap();
+        assert_eq!(uri, "https://example.com/a;b");
+        assert_eq!(t.cell(1, 0).unwrap().link, Some(id));
+        assert!(t.link_at(2, 0).is_none());
+    }
+
+    #[test]
+    fn status_reports() {
+        let mut t = terminal(10, 5, b"\x1b[3;5H\x1b[6n\x1b[5n\x1b[1n");
+        assert_eq!(t.take_response().as_deref(), Some(&b"\x1b[3;5R"[..]));
+        assert_eq!(t.take_response().as_deref(), Some(&b"\x1b[0n"[..]));
+        assert_eq!(t.take_response(), None);
+    }
+}
@@ -0,0 +1,188 @@
+//! Binary encoding of client messages.
+//!
+//! Each message is a tag byte followed by little-endian fields:
+//!
+//! | tag | message   | body                                              |
+//! |-----|-----------|---------------------------------------------------|
+//! | 0   | data      | raw input bytes                                   |
+//! | 1   | resize    | `u16` cols, `u16` rows, [`u16` cell w, `u16` h]   |
+//! | 2   | scroll    | `i32` delta                                       |
+//! | 3   | quality   | `u32` min interval ms, `u8` compress (2 = unset)  |
+//! | 4   | credit    | `u32` frames                                      |
+//! | 5   | ack       | `u32` rendered frames                             |
+//! | 6   | ping      | `f64` client timestamp                            |
+//! | 7   | pong      | `u64` echoed server timestamp                     |
+//! | 8   | clipboard | `u8` granted (0 = refused), UTF-8 text            |
+//! | 9   | jump      | `i32` prompts to move, [`u64` view top line]      |
+//! | 10  | copy out  | `u16` viewport row, [`u64` absolute line]         |
+//! | 11  | history   | `u64` first line, `u16` line count                |
+//!
+//! Bracketed fields are optional; this client sends the cell size (in
+//! device pixels) once its renderer knows it, and the others always.
+//! The server has the decoding side; both must stay in step.
+
+use super::ClientMessage;
+
+const TAG_DATA: u8 = 0;
+const TAG_RESIZE: u8 = 1;
+const TAG_SCROLL: u8 = 2;
+const TAG_QUALITY: u8 = 3;
+const TAG_CREDIT: u8 = 4;
+const TAG_ACK: u8 = 5;
+const TAG_PING: u8 = 6;
+const TAG_PONG: u8 = 7;
+const TAG_CLIPBOARD: u8 = 8;
+const TAG_JUMP_PROMPT: u8 = 9;
+const TAG_COPY_OUTPUT: u8 = 10;
+const TAG_FETCH_HISTORY: u8 = 11;
+
+impl ClientMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(16);
+        match self {
+            ClientMessage::Data { data } => {
+                out.reserve(data.len());
+                out.push(TAG_DATA);
+                out.extend_from_slice(data);
+            }
+            ClientMessage::Resize { cols, rows, cell } => {
+                out.push(TAG_RESIZE);
+                out.extend_from_slice(&cols.to_le_bytes());
+                out.extend_from_slice(&rows.to_le_bytes());
+                if let Some((width, height)) = cell {
+                    out.extend_from_slice(&width.to_le_bytes());
+                    out.extend_from_slice(&height.to_le_bytes());
+                }
+            }
+            ClientMessage::Scroll { delta } => {
+                out.push(TAG_SCROLL);
+                out.extend_from_slice(&delta.to_le_bytes());
+            }
+            ClientMessage::Quality { min_interval_ms, compress } => {
+                out.push(TAG_QUALITY);
+                out.extend_from_slice(&min_interval_ms.to_le_bytes());
+                out.push(match compress {
+                    Some(false) => 0,
+                    Some(true) => 1,
+                    None => 2,
+                });
+            }
+            ClientMessage::Credit { frames } => {
+                out.push(TAG_CREDIT);
+                out.extend_from_slice(&frames.to_le_bytes());
+            }
+            ClientMessage::Ack { count } => {
+                out.push(TAG_ACK);
+                out.extend_from_slice(&count.to_le_bytes());
+            }
+            ClientMessage::Ping { ts } => {
+                out.push(TAG_PING);
+                out.extend_from_slice(&ts.to_le_bytes());
+            }
+            ClientMessage::Pong { ts } => {
+                out.push(TAG_PONG);
+                out.extend_from_slice(&ts.to_le_bytes());
+            }
+            ClientMessage::Clipboard { text } => {
+                out.push(TAG_CLIPBOARD);
+                match text {
+                    Some(text) => {
+                        out.push(1);
+                        out.extend_from_slice(text.as_bytes());
+                    }
+                    None => out.push(0),
+                }
+            }
+            ClientMessage::JumpPrompt { delta, from_line } => {
+                out.push(TAG_JUMP_PROMPT);
+                out.extend_from_slice(&delta.to_le_bytes());
+                out.extend_from_slice(&from_line.to_le_bytes());
+            }
+            ClientMessage::CopyOutput { row, line } => {
+                out.push(TAG_COPY_OUTPUT);
+                out.extend_from_slice(&row.to_le_bytes());
+                out.extend_from_slice(&line.to_le_bytes());
+            }
+            ClientMessage::FetchHistory { from_line, count } => {
+                out.push(TAG_FETCH_HISTORY);
+                out.extend_from_slice(&from_line.to_le_bytes());
+                out.extend_from_slice(&count.to_le_bytes());
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every message and its encoding, which the server decodes.
+    fn messages() -> Vec<(ClientMessage, Vec<u8>)> {
+        let message = |tag: u8, fields: &[&[u8]]| [&[tag][..], &fields.concat()].concat();
+        vec![
+            (ClientMessage::Data { data: b"ls\r".to_vec() }, message(TAG_DATA, &[b"ls\r"])),
+            (ClientMessage::Data { data: Vec::new() }, message(TAG_DATA, &[])),
+            (
+                ClientMessage::Resize { cols: 80, rows: 24, cell: None },
+                message(TAG_RESIZE, &[&80u16.to_le_bytes(), &24u16.to_le_bytes()]),
+            ),
+            (
+                ClientMessage::Resize { cols: 80, rows: 24, cell: Some((9, 18)) },
+                message(
+                    TAG_RESIZE,
+                    &[&80u16.to_le_bytes(), &24u16.to_le_bytes(), &9u16.to_le_bytes(), &18u16.to_le_bytes()],
+                ),
+            ),
+            (ClientMessage::Scroll { delta: -3 }, message(TAG_SCROLL, &[&(-3i32).to_le_bytes()])),
+            (
+                ClientMessage::Quality { min_interval_ms: 33, compress: Some(false) },
+                message(TAG_QUALITY, &[&33u32.to_le_bytes(), &[0]]),
+            ),
+            (
+                ClientMessage::Quality { min_interval_ms: 0, compress: Some(true) },
+                message(TAG_QUALITY, &[&0u32.to_le_bytes(), &[1]]),
+            ),
+            (
+                ClientMessage::Quality { min_interval_ms: 16, compress: None },
+                message(TAG_QUALITY, &[&16u32.to_le_bytes(), &[2]]),
+            ),
+            (ClientMessage::Credit { frames: 8 }, message(TAG_CREDIT, &[&8u32.to_le_bytes()])),
+            (ClientMessage::Ack { count: 2 }, message(TAG_ACK, &[&2u32.to_le_bytes()])),
+            (ClientMessage::Ping { ts: 1234.5 }, message(TAG_PING, &[&1234.5f64.to_le_bytes()])),
+            (ClientMessage::Pong { ts: u64::MAX }, message(TAG_PONG, &[&u64::MAX.to_le_bytes()])),
+            (ClientMessage::Clipboard { text: None }, message(TAG_CLIPBOARD, &[&[0]])),
+            (ClientMessage::Clipboard { text: Some(String::new()) }, message(TAG_CLIPBOARD, &[&[1]])),
+            (
+                ClientMessage::Clipboard { text: Some("h\u{e9}llo".into()) },
+                message(TAG_CLIPBOARD, &[&[1], "h\u{e9}llo".as_bytes()]),
+            ),
+            (
+                ClientMessage::JumpPrompt { delta: 1, from_line: 500 },
+                message(TAG_JUMP_PROMPT, &[&1i32.to_le_bytes(), &500u64.to_le_bytes()]),
+            ),
+            (
+                ClientMessage::CopyOutput { row: 7, line: 12345 },
+                message(TAG_COPY_OUTPUT, &[&7u16.to_le_bytes(), &12345u64.to_le_bytes()]),
+            ),
+            (
+                ClientMessage::FetchHistory { from_line: 9800, count: 200 },
+                message(TAG_FETCH_HISTORY, &[&9800u64.to_le_bytes(), &200u16.to_le_bytes()]),
+            ),
+        ]
+    }
+
+    #[test]
+    fn encodes_every_message() {
+        for (message, expected) in messages() {
+            assert_eq!(message.encode(), expected);
+        }
+    }
+
+    #[test]
+    fn every_tag_is_covered() {
+        let mut tags: Vec<u8> = messages().iter().map(|(_, bytes)| bytes[0]).collect();
+        tags.dedup();
+        assert_eq!(tags, (TAG_DATA..=TAG_FETCH_HISTORY).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,656 @@
+//! Terminal I/O transport with WebTransport and WebSocket fallback
+
+mod codec;
+mod websocket;
+#[cfg(web)]
+mod webtransport;
+
+use websocket::WebSocketConnection;
+#[cfg(web)]
+use webtransport::WebTransportConnection;
+
+use wasm_bindgen::prelude::*;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use serde::Deserialize;
+use base64::Engine;
+use crate::alerts::Alerts;
+use crate::compression::FrameDecompressor;
+use crate::terminal::{HistoryPage, Image, Palette, TerminalFrame};
+
+/// Messages to the server, sent in the binary encoding of `codec`.
+enum ClientMessage {
+    /// Terminal input, passed to the PTY byte for byte.
+    Data { data: Vec<u8> },
+    /// New grid size, with the cell size in device pixels if known.
+    Resize { cols: u16, rows: u16, cell: Option<(u16, u16)> },
+    Scroll { delta: i32 },
+    Quality { min_interval_ms: u32, compress: Option<bool> },
+    Credit { frames: u32 },
+    /// Rendered frames, returning one credit each.
+    Ack { count: u32 },
+    Ping { ts: f64 },
+    Pong { ts: u64 },
+    /// Answer to a clipboard request; None when the user refused.
+    Clipboard { text: Option<String> },
+    /// Prompt `delta` prompts away from the view's top line (negative =
+    /// earlier), answered with the line to scroll to.
+    JumpPrompt { delta: i32, from_line: u64 },
+    /// Copy the output of the command at an absolute line.
+    CopyOutput { row: u16, line: u64 },
+    /// Scrollback lines `from_line..from_line + count`.
+    FetchHistory { from_line: u64, count: u16 },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum ServerMessage {
+    #[serde(rename = "frame")]
+    Frame(TerminalFrame),
+    #[serde(rename = "ping")]
+    Ping { ts: u64 },
+    #[serde(rename = "pong")]
+    Pong { ts: f64 },
+    #[serde(rename = "title")]
+    Title { title: Option<String> },
+    #[serde(rename = "bell")]
+    Bell,
+    #[serde(rename = "clipboard")]
+    Clipboard { text: String },
+    #[serde(rename = "clipboard_request")]
+    ClipboardRequest,
+    #[serde(rename = "notify")]
+    Notify { title: Option<String>, body: String },
+    #[serde(rename = "history")]
+    History(HistoryPage),
+    #[serde(rename = "scroll_to")]
+    ScrollTo { line: u64 },
+    #[serde(rename = "snapshot")]
+    Snapshot { respond: bool, palette: Palette, frame: TerminalFrame },
+    #[serde(rename = "output")]
+    Output {
+        #[serde(deserialize_with = "latin1_bytes")]
+        data: Vec<u8>,
+    },
+    #[serde(rename = "image")]
+    Image {
+        id: u32,
+        width: u32,
+        height: u32,
+        #[serde(deserialize_with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+}
+
+/// Raw output travels as a string of U+0000-U+00FF in JSON, one char per
+/// byte, and as it is in bincode.
+fn latin1_bytes<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    if deserializer.is_human_readable() {
+        let text = String::deserialize(deserializer)?;
+        Ok(text.chars().map(|c| c as u8).collect())
+    } else {
+        Vec::deserialize(deserializer)
+    }
+}
+
+/// Image pixels travel as base64 in JSON and as they are in bincode.
+fn base64_bytes<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    if deserializer.is_human_readable() {
+        let text = String::deserialize(deserializer)?;
+        base64::engine::general_purpose::STANDARD
+            .decode(text)
+            .map_err(serde::de::Error::custom)
+    } else {
+        Vec::deserialize(deserializer)
+    }
+}
+
+/// Default frame credit window granted to the server.
+pub const DEFAULT_MAX_FRAMES: usize = 8;
+
+/// WebTransport endpoint advertised by the server.
+pub struct WebTransportEndpoint {
+    pub url: String,
+    /// SHA-256 of the server's self-signed certificate.
+    pub cert_hash: Option<Vec<u8>>,
+}
+
+/// Decoded frames, traffic counters and heartbeat state shared with the
+/// connection callbacks.
+struct Inbox {
+    /// Newest frame not yet taken. Frames replace the whole screen, so an
+    /// older one is dropped when a newer one arrives, leaving its pixels.
+    frame: RefCell<Option<TerminalFrame>>,
+    /// Frames received since the last take, dropped ones included.
+    frames_received: Cell<usize>,
+    /// Title update not yet taken; the inner None is a reset.
+    title: RefCell<Option<Option<String>>>,
+    /// Latest text copied by the running program, not yet taken.
+    clipboard: RefCell<Option<String>>,
+    /// The running program is waiting for the clipboard contents.
+    clipboard_request: Cell<bool>,
+    /// Bells and notifications are raised on arrival.
+    alerts: Rc<Alerts>,
+    /// Scrollback pages not yet taken.
+    history: RefCell<Vec<HistoryPage>>,
+    /// Line a prompt jump should scroll to, not yet taken.
+    scroll_to: Cell<Option<u64>>,
+    /// The server streams raw PTY output (after a snapshot frame).
+    raw: Cell<bool>,
+    /// This client answers status reports on the raw stream.
+    respond: Cell<bool>,
+    /// Raw output following the newest frame, not yet taken.
+    output: RefCell<Vec<u8>>,
+    /// Image pixels for the next frame.
+    images: RefCell<Vec<Image>>,
+    decompressor: RefCell<Option<FrameDecompressor>>,
+    bytes_received: Cell<u64>,
+    bytes_decoded: Cell<u64>,
+    messages_received: Cell<u64>,
+    /// `performance.now()` of the last message / frame.
+    last_message_at: Cell<f64>,
+    last_frame_at: Cell<f64>,
+    /// Smoothed RTT and its mean deviation (RFC 6298), in ms.
+    srtt: Cell<Option<f64>>,
+    rttvar: Cell<f64>,
+}
+
+impl Inbox {
+    fn new(compress: bool, alerts: Rc<Alerts>) -> Rc<Self> {
+        let now = now_ms();
+        Rc::new(Inbox {
+            frame: RefCell::new(None),
+            frames_received: Cell::new(0),
+            title: RefCell::new(None),
+            clipboard: RefCell::new(None),
+            clipboard_request: Cell::new(false),
+            alerts,
+            history: RefCell::new(Vec::new()),
+            scroll_to: Cell::new(None),
+            raw: Cell::new(false),
+            respond: Cell::new(false),
+            output: RefCell::new(Vec::new()),
+            images: RefCell::new(Vec::new()),
+            decompressor: RefCell::new(compress.then(FrameDecompressor::new)),
+            bytes_received: Cell::new(0),
+            bytes_decoded: Cell::new(0),
+            messages_received: Cell::new(0),
+            last_message_at: Cell::new(now),
+            last_frame_at: Cell::new(now),
+            srtt: Cell::new(None),
+            rttvar: Cell::new(0.0),
+        })
+    }
+
+    /// Uncompressed JSON text message. Returns a reply to send right away.
+    fn push_text(&self, text: &str) -> Option<ClientMessage> {
+        self.messages_received.set(self.messages_received.get().wrapping_add(1));
+        self.bytes_received.set(self.bytes_received.get().wrapping_add(text.len() as u64));
+        self.bytes_decoded.set(self.bytes_decoded.get().wrapping_add(text.len() as u64));
+        self.push_message(serde_json::from_str::<ServerMessage>(text).ok())
+    }
+
+    /// Binary payload, possibly deflated. Returns a reply to send right away.
+    fn push_binary(&self, bytes: Vec<u8>) -> Option<ClientMessage> {
+        self.messages_received.set(self.messages_received.get().wrapping_add(1));
+        self.bytes_received.set(self.bytes_received.get().wrapping_add(bytes.len() as u64));
+        // The server may skip deflate for JSON; a sync-flushed deflate block
+        // never starts with '{' (its BFINAL bit is clear).
+        let bytes = match self.decompressor.borrow_mut().as_mut() {
+            Some(decompressor) if bytes.first() != Some(&b'{') => match decompressor.decompress(&bytes) {
+                Some(inflated) => inflated,
+                None => {
+                    tracing::warn!("Failed to inflate frame");
+                    return None;
+                }
+            },
+            _ => bytes,
+        };
+        self.bytes_decoded.set(self.bytes_decoded.get().wrapping_add(bytes.len() as u64));
+        // Compressed payloads may carry either encoding; JSON always
+        // starts with '{', bincode never does.
+        let msg = if bytes.first() == Some(&b'{') {
+            serde_json::from_slice::<ServerMessage>(&bytes).ok()
+        } else {
+            bincode::deserialize::<ServerMessage>(&bytes).ok()
+        };
+        self.push_message(msg)
+    }
+
+    /// Keep `frame` as the one to draw next, with the pixels that arrived
+    /// for it and for any frame it replaces.
+    fn push_frame(&self, mut frame: TerminalFrame) {
+        let mut pixels = std::mem::take(&mut *self.images.borrow_mut());
+        if let Some(dropped) = self.frame.borrow_mut().take() {
+            pixels.splice(0..0, dropped.pixels);
+            if frame.palette.is_none() {
+                frame.palette = dropped.palette;
+            }
+        }
+        frame.pixels = pixels;
+        *self.frame.borrow_mut() = Some(frame);
+        self.frames_received.set(self.frames_received.get() + 1);
+    }
+
+    fn push_message(&self, msg: Option<ServerMessage>) -> Option<ClientMessage> {
+        let now = now_ms();
+        self.last_message_at.set(now);
+        match msg? {
+            ServerMessage::Frame(frame) => {
+                self.last_frame_at.set(now);
+                self.push_frame(frame);
+                None
+            }
+            ServerMessage::Ping { ts } => Some(ClientMessage::Pong { ts }),
+            ServerMessage::Pong { ts } => {
+                self.record_rtt(now - ts);
+                None
+            }
+            ServerMessage::Title { title } => {
+                self.alerts.set_title(title.clone());
+                *self.title.borrow_mut() = Some(title);
+                None
+            }
+            ServerMessage::Bell => {
+                self.alerts.ring();
+                None
+            }
+            ServerMessage::Clipboard { text } => {
+                *self.clipboard.borrow_mut() = Some(text);
+                None
+            }
+            ServerMessage::ClipboardRequest => {
+                self.clipboard_request.set(true);
+                None
+            }
+            ServerMessage::Notify { title, body } => {
+                self.alerts.notify(title.as_deref(), &body);
+                None
+            }
+            ServerMessage::History(page) => {
+                self.history.borrow_mut().push(page);
+                None
+            }
+            ServerMessage::ScrollTo { line } => {
+                self.scroll_to.set(Some(line));
+                None
+            }
+            ServerMessage::Snapshot { respond, palette, mut frame } => {
+                self.last_frame_at.set(now);
+                frame.palette = Some(palette);
+                self.raw.set(true);
+                self.respond.set(respond);
+                // The snapshot already shows any output before it
+                self.output.borrow_mut().clear();
+                self.push_frame(frame);
+                None
+            }
+            ServerMessage::Output { data } => {
+                self.last_frame_at.set(now);
+                self.output.borrow_mut().extend_from_slice(&data);
+                None
+            }
+            ServerMessage::Image { id, width, height, data } => {
+                self.images.borrow_mut().push(Image { id, width, height, rgba: data });
+                None
+            }
+        }
+    }
+
+    fn record_rtt(&self, sample: f64) {
+        let sample = sample.max(0.0);
+        match self.srtt.get() {
+            None => {
+                self.srtt.set(Some(sample));
+                self.rttvar.set(sample / 2.0);
+            }
+            Some(srtt) => {
+                self.rttvar.set(0.75 * self.rttvar.get() + 0.25 * (srtt - sample).abs());
+                self.srtt.set(Some(0.875 * srtt + 0.125 * sample));
+            }
+        }
+    }
+}
+
+/// Monotonic clock in ms.
+fn now_ms() -> f64 {
+    web_sys::window()
+        .and_then(|w| w.performance())
+        .map(|p| p.now())
+        .unwrap_or_else(js_sys::Date::now)
+}
+
+/// Underlying connection
+enum Connection {
+    WebSocket(WebSocketConnection),
+    #[cfg(web)]
+    WebTransport(WebTransportConnection),
+}
+
+impl Connection {
+    fn send_message(&self, msg: &ClientMessage) -> Result<(), JsValue> {
+        match self {
+            Connection::WebSocket(c) => c.send_message(msg),
+            #[cfg(web)]
+            Connection::WebTransport(c) => c.send_message(msg),
+        }
+    }
+
+    fn send_input(&self, data: &[u8]) -> Result<(), JsValue> {
+        match self {
+            Connection::WebSocket(c) => c.send_message(&ClientMessage::Data { data: data.to_vec() }),
+            #[cfg(web)]
+            Connection::WebTransport(c) => c.send_input(data),
+        }
+    }
+
+    fn send_acks(&self, count: u32) -> Result<(), JsValue> {
+        match self {
+            Connection::WebSocket(c) => c.send_message(&ClientMessage::Ack { count }),
+            #[cfg(web)]
+            Connection::WebTransport(c) => c.send_acks(count),
+        }
+    }
+
+    fn ready_state(&self) -> u16 {
+        match self {
+            Connection::WebSocket(c) => c.ready_state(),
+            #[cfg(web)]
+            Connection::WebTransport(c) => c.ready_state(),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Connection::WebSocket(_) => "websocket",
+            #[cfg(web)]
+            Connection::WebTransport(_) => "webtransport",
+        }
+    }
+}
+
+/// Terminal transport, preferring WebTransport with WebSocket fallback
+///
+/// Flow control is credit based: the client grants the server `max_frames`
+/// frames up front and returns one credit per frame once it has been rendered.
+/// The server coalesces to the newest state while out of credit, so nothing
+/// is dropped on the client side.
+pub struct Transport {
+    connection: Connection,
+    inbox: Rc<Inbox>,
+    max_frames: Cell<usize>,
+    /// Acks to withhold after the credit window was shrunk.
+    ack_debt: Cell<usize>,
+}
+
+impl Transport {
+    /// Connect to the server, granting `max_frames` credits (0 = unlimited)
+    /// and optionally requesting deflate-compressed frames. Bells and
+    /// notifications go to `alerts` as they arrive.
+    ///
+    /// WebTransport is tried first when an endpoint is given; any failure
+    /// falls back to the WebSocket URL.
+    #[cfg_attr(not(web), allow(unused_variables))]
+    pub async fn connect(
+        ws_url: &str,
+        webtransport: Option<&WebTransportEndpoint>,
+        max_frames: usize,
+        compress: bool,
+        alerts: Rc<Alerts>,
+    ) -> Result<Self, JsValue> {
+        #[cfg(web)]
+        if let Some(endpoint) = webtransport {
+            let url = session_url(&endpoint.url, max_frames, compress);
+            let inbox = Inbox::new(compress, alerts.clone());
+            match WebTransportConnection::connect(&url, endpoint.cert_hash.as_deref(), inbox.clone())
+                .await
+            {
+                Ok(connection) => {
+                    tracing::info!("Using WebTransport");
+                    return Ok(Self::new(Connection::WebTransport(connection), inbox, max_frames));
+                }
+                Err(e) => {
+                    tracing::warn!("WebTransport not available: {:?}, falling back to WebSocket", e);
+                }
+            }
+        }
+
+        let url = session_url(ws_url, max_frames, compress);
+        let inbox = Inbox::new(compress, alerts);
+        let connection = WebSocketConnection::connect(&url, inbox.clone()).await?;
+        Ok(Self::new(Connection::WebSocket(connection), inbox, max_frames))
+    }
+
+    fn new(connection: Connection, inbox: Rc<Inbox>, max_frames: usize) -> Self {
+        Transport {
+            connection,
+            inbox,
+            max_frames: Cell::new(max_frames),
+            ack_debt: Cell::new(0),
+        }
+    }
+
+    fn send_message(&self, msg: &ClientMessage) -> Result<(), JsValue> {
+        self.connection.send_message(msg)
+    }
+
+    /// Send data to terminal
+    pub fn send(&self, data: &[u8]) -> Result<(), JsValue> {
+        self.connection.send_input(data)
+    }
+
+    /// Send resize command, with the cell size in device pixels so the
+    /// server can lay out images
+    pub fn send_resize(&self, cols: u16, rows: u16, cell: Option<(u16, u16)>) -> Result<(), JsValue> {
+        self.send_message(&ClientMessage::Resize { cols, rows, cell })
+    }
+
+    /// Send scroll command (positive = scroll up).
+    pub fn send_scroll(&self, delta: i32) -> Result<(), JsValue> {
+        self.send_message(&ClientMessage::Scroll { delta })
+    }
+
+    /// Change the frame credit window (0 = unlimited).
+    ///
+    /// Growing the window grants the difference immediately; shrinking it
+    /// withholds acks until the server is back inside the new window.
+    pub fn set_max_frames(&self, max_frames: usize) -> Result<(), JsValue> {
+        let current = self.max_frames.replace(max_frames);
+        if max_frames == current {
+            return Ok(());
+        }
+        if max_frames == 0 {
+            self.ack_debt.set(0);
+            return self.send_message(&ClientMessage::Credit { frames: 0 });
+        }
+        if current == 0 {
+            self.ack_debt.set(0);
+            return self.send_message(&ClientMessage::Credit { frames: max_frames as u32 });
+        }
+        if max_frames > current {
+            let grant = max_frames - current;
+            let debt = self.ack_debt.get();
+            let settled = debt.min(grant);
+            self.ack_debt.set(debt - settled);
+            if grant > settled {
+                return self.send_message(&ClientMessage::Credit {
+                    frames: (grant - settled) as u32,
+                });
+            }
+        } else {
+            self.ack_debt.set(self.ack_debt.get() + (current - max_frames));
+        }
+        Ok(())
+    }
+
+    /// Return credits for frames that have been rendered.
+    pub fn ack_frames(&self, count: usize) -> Result<(), JsValue> {
+        if self.max_frames.get() == 0 {
+            return Ok(());
+        }
+        let debt = self.ack_debt.get();
+        let withheld = debt.min(count);
+        self.ack_debt.set(debt - withheld);
+        let acks = count - withheld;
+        if acks > 0 {
+            self.connection.send_acks(acks as u32)?;
+        }
+        Ok(())
+    }
+
+    /// Throttle server frame rate (0 = no throttle) and optionally tell the
+    /// server whether deflating frames is currently worth it.
+    pub fn send_quality(&self, min_interval_ms: u32, compress: Option<bool>) -> Result<(), JsValue> {
+        self.send_message(&ClientMessage::Quality { min_interval_ms, compress })
+    }
+
+    /// Newest frame received since the last call, and how many frames
+    /// that was: older ones are folded into it.
+    pub fn take_frame(&self) -> Option<(TerminalFrame, usize)> {
+        let frame = self.inbox.frame.borrow_mut().take()?;
+        Some((frame, self.inbox.frames_received.replace(0)))
+    }
+
+    /// Latest window title sent since the last call: Some(None) when the
+    /// program reset it.
+    pub fn take_title(&self) -> Option<Option<String>> {
+        self.inbox.title.borrow_mut().take()
+    }
+
+    /// Latest text the program copied since the last call
+    pub fn take_clipboard(&self) -> Option<String> {
+        self.inbox.clipboard.borrow_mut().take()
+    }
+
+    /// Whether the program asked to read the clipboard since the last call
+    pub fn take_clipboard_request(&self) -> bool {
+        self.inbox.clipboard_request.take()
+    }
+
+    /// Answer a clipboard request (None = refused)
+    pub fn send_clipboard(&self, text: Option<String>) -> Result<(), JsValue> {
+        self.send_message(&ClientMessage::Clipboard { text })
+    }
+
+    /// Ask where the prompt `delta` prompts away from `from_line` is
+    /// (negative = earlier); the answer comes back through `take_scroll_to`
+    pub fn send_jump_prompt(&self, delta: i32, from_line: u64) -> Result<(), JsValue> {
+        self.send_message(&ClientMessage::JumpPrompt { delta, from_line })
+    }
+
+    /// Ask for the output of the command at a viewport row and absolute
+    /// line; it comes back as clipboard text
+    pub fn send_copy_output(&self, row: u16, line: u64) -> Result<(), JsValue> {
+        self.send_message(&ClientMessage::CopyOutput { row, line })
+    }
+
+    /// Ask for scrollback lines; they come back through `take_history`
+    pub fn send_fetch_history(&self, from_line: u64, count: u16) -> Result<(), JsValue> {
+        self.send_message(&ClientMessage::FetchHistory { from_line, count })
+    }
+
+    /// Scrollback pages received since the last call
+    pub fn take_history(&self) -> Vec<HistoryPage> {
+        std::mem::take(&mut *self.inbox.history.borrow_mut())
+    }
+
+    /// Line to scroll to for a prompt jump, if one was answered
+    pub fn take_scroll_to(&self) -> Option<u64> {
+        self.inbox.scroll_to.take()
+    }
+
+    /// Whether the server streams raw PTY output for the client to emulate
+    pub fn is_raw(&self) -> bool {
+        self.inbox.raw.get()
+    }
+
+    /// Whether this client answers status reports (DSR) on the raw stream
+    pub fn responds(&self) -> bool {
+        self.inbox.respond.get()
+    }
+
+    /// Raw PTY output received since the last call, to be processed after
+    /// any frames taken before it
+    pub fn take_output(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.inbox.output.borrow_mut())
+    }
+
+    /// Frames received and not yet taken.
+    pub fn queue_len(&self) -> usize {
+        self.inbox.frames_received.get()
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.inbox.bytes_received.get()
+    }
+
+    /// Total payload bytes after decompression.
+    pub fn bytes_decoded(&self) -> u64 {
+        self.inbox.bytes_decoded.get()
+    }
+
+    pub fn messages_received(&self) -> u64 {
+        self.inbox.messages_received.get()
+    }
+
+    pub fn reset_counters(&self) {
+        self.inbox.bytes_received.set(0);
+        self.inbox.bytes_decoded.set(0);
+        self.inbox.messages_received.set(0);
+    }
+
+    /// Send a heartbeat; the pong updates the RTT estimate.
+    pub fn ping(&self) -> Result<(), JsValue> {
+        self.send_message(&ClientMessage::Ping { ts: now_ms() })
+    }
+
+    /// Smoothed round-trip time in ms, once a pong has arrived.
+    pub fn rtt_ms(&self) -> Option<f64> {
+        self.inbox.srtt.get()
+    }
+
+    /// RTT mean deviation in ms.
+    pub fn jitter_ms(&self) -> f64 {
+        self.inbox.rttvar.get()
+    }
+
+    /// Time since the last frame arrived, in ms.
+    pub fn last_frame_age_ms(&self) -> f64 {
+        now_ms() - self.inbox.last_frame_at.get()
+    }
+
+    /// Time since anything (frame or heartbeat) arrived, in ms.
+    pub fn last_message_age_ms(&self) -> f64 {
+        now_ms() - self.inbox.last_message_at.get()
+    }
+
+    /// Connection ready state (0..=3, WebSocket numbering)
+    pub fn ready_state(&self) -> u16 {
+        self.connection.ready_state()
+    }
+
+    /// Active connection kind ("webtransport" or "websocket")
+    pub fn kind(&self) -> &'static str {
+        self.connection.kind()
+    }
+}
+
+/// Append the per-connection session parameters to an endpoint URL.
+fn session_url(url: &str, max_frames: usize, compress: bool) -> String {
+    let mut url = url.to_string();
+    if max_frames > 0 {
+        append_query(&mut url, "credits", &max_frames.to_string());
+    }
+    if compress {
+        append_query(&mut url, "compress", "deflate");
+    }
+    url
+}
+
+fn append_query(url: &mut String, key: &str, value: &str) {
+    let sep = if url.contains('?') { '&' } else { '?' };
+    url.push(sep);
+    url.push_str(key);
+    url.push('=');
+    url.push_str(value);
+}
@@ -0,0 +1,76 @@
+//! WebSocket connection
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{MessageEvent, WebSocket};
+use std::rc::Rc;
+use super::{ClientMessage, Inbox};
+
+pub struct WebSocketConnection {
+    ws: WebSocket,
+}
+
+impl WebSocketConnection {
+    pub async fn connect(url: &str, inbox: Rc<Inbox>) -> Result<Self, JsValue> {
+        let ws = WebSocket::new(url)?;
+        ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+
+        // Wait for connection
+        let ws_clone = ws.clone();
+        let open_promise = js_sys::Promise::new(&mut |resolve, reject| {
+            let ws = ws_clone.clone();
+            let onopen = Closure::once(Box::new(move || {
+                resolve.call0(&JsValue::NULL).unwrap();
+            }) as Box<dyn FnOnce()>);
+
+            let onerror = Closure::once(Box::new(move |_: JsValue| {
+                reject.call0(&JsValue::NULL).unwrap();
+            }) as Box<dyn FnOnce(JsValue)>);
+
+            ws.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+            ws.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+
+            onopen.forget();
+            onerror.forget();
+        });
+
+        wasm_bindgen_futures::JsFuture::from(open_promise).await?;
+
+        // Setup message handler; heartbeat replies go out immediately so they
+        // keep working while rendering is paused in a background tab.
+        let reply_ws = ws.clone();
+        let onmessage = Closure::wrap(Box::new(move |e: MessageEvent| {
+            let data = e.data();
+            let reply = if let Some(text) = data.as_string() {
+                inbox.push_text(&text)
+            } else if let Ok(array_buf) = data.dyn_into::<js_sys::ArrayBuffer>() {
+                inbox.push_binary(js_sys::Uint8Array::new(&array_buf).to_vec())
+            } else {
+                None
+            };
+            if let Some(reply) = reply {
+                let _ = reply_ws.send_with_u8_array(&reply.encode());
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+
+        ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        onmessage.forget();
+
+        Ok(WebSocketConnection { ws })
+    }
+
+    pub fn send_message(&self, msg: &ClientMessage) -> Result<(), JsValue> {
+        self.ws.send_with_u8_array(&msg.encode())
+    }
+
+    /// WebSocket ready state (0..=3)
+    pub fn ready_state(&self) -> u16 {
+        self.ws.ready_state()
+    }
+}
+
+impl Drop for WebSocketConnection {
+    fn drop(&mut self) {
+        let _ = self.ws.close();
+    }
+}
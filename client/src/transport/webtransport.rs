@@ -0,0 +1,274 @@
+//! WebTransport (HTTP/3) connection
+//!
+//! Frames arrive on a bidirectional stream as length-prefixed payloads, while
+//! keystrokes and frame acks go out as datagrams so a lost packet never holds
+//! up the frame stream. Datagram state is cumulative: every input datagram
+//! carries all unconfirmed bytes from the last offset the server confirmed,
+//! and every ack datagram carries the total number of rendered frames. Both
+//! are resent on a timer until the server confirms them.
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    ReadableStreamDefaultReader, WebTransport, WebTransportBidirectionalStream, WebTransportHash,
+    WebTransportOptions, WritableStreamDefaultWriter,
+};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use super::{ClientMessage, Inbox};
+
+/// `ClientMessage` in the binary encoding of `codec` (stream only).
+const KIND_MESSAGE: u8 = 0;
+/// Terminal input at a byte offset.
+const KIND_INPUT: u8 = 1;
+/// Cumulative count of rendered frames.
+const KIND_ACK: u8 = 2;
+
+/// Largest input record written to the stream in one go.
+const MAX_STREAM_INPUT: usize = 64 * 1024;
+/// Resend unconfirmed datagram state this often.
+const RETRANSMIT_MS: i32 = 60;
+
+/// Datagram state awaiting server confirmation.
+struct Unconfirmed {
+    /// Input bytes starting at `input_offset`.
+    input: RefCell<Vec<u8>>,
+    input_offset: Cell<u64>,
+    /// Rendered frames reported so far.
+    acks_sent: Cell<u32>,
+    acks_confirmed: Cell<u32>,
+}
+
+/// Writers shared with the retransmit timer.
+struct Writers {
+    stream: WritableStreamDefaultWriter,
+    datagrams: WritableStreamDefaultWriter,
+    max_datagram_size: usize,
+    /// Swallows write rejections once the session is gone.
+    on_error: Closure<dyn FnMut(JsValue)>,
+}
+
+impl Writers {
+    fn write(writer: &WritableStreamDefaultWriter, bytes: &[u8], on_error: &Closure<dyn FnMut(JsValue)>) {
+        let chunk = js_sys::Uint8Array::from(bytes);
+        let _ = writer.write_with_chunk(&chunk).catch(on_error);
+    }
+
+    fn send_record(&self, kind: u8, body: &[u8]) {
+        let mut record = Vec::with_capacity(5 + body.len());
+        record.extend_from_slice(&(1 + body.len() as u32).to_le_bytes());
+        record.push(kind);
+        record.extend_from_slice(body);
+        Self::write(&self.stream, &record, &self.on_error);
+    }
+
+    fn send_message(&self, msg: &ClientMessage) -> Result<(), JsValue> {
+        self.send_record(KIND_MESSAGE, &msg.encode());
+        Ok(())
+    }
+
+    fn send_datagram(&self, kind: u8, parts: &[&[u8]]) {
+        let mut datagram = vec![kind];
+        for part in parts {
+            datagram.extend_from_slice(part);
+        }
+        Self::write(&self.datagrams, &datagram, &self.on_error);
+    }
+
+    /// Send all unconfirmed input, by datagram when it fits.
+    fn flush_input(&self, state: &Unconfirmed) {
+        let mut input = state.input.borrow_mut();
+        if input.is_empty() {
+            return;
+        }
+        let offset = state.input_offset.get();
+        if 1 + 8 + input.len() <= self.max_datagram_size {
+            self.send_datagram(KIND_INPUT, &[&offset.to_le_bytes(), &input]);
+            return;
+        }
+        // Too big for a datagram (e.g. a paste): the stream is reliable, so
+        // the bytes count as delivered once written.
+        let mut offset = offset;
+        for chunk in input.chunks(MAX_STREAM_INPUT) {
+            let mut body = Vec::with_capacity(8 + chunk.len());
+            body.extend_from_slice(&offset.to_le_bytes());
+            body.extend_from_slice(chunk);
+            self.send_record(KIND_INPUT, &body);
+            offset += chunk.len() as u64;
+        }
+        state.input_offset.set(offset);
+        input.clear();
+    }
+
+    fn flush_acks(&self, state: &Unconfirmed) {
+        let sent = state.acks_sent.get();
+        if sent != state.acks_confirmed.get() {
+            self.send_datagram(KIND_ACK, &[&sent.to_le_bytes()]);
+        }
+    }
+}
+
+pub struct WebTransportConnection {
+    transport: WebTransport,
+    writers: Rc<Writers>,
+    unconfirmed: Rc<Unconfirmed>,
+    state: Rc<Cell<u16>>,
+    retransmit_id: i32,
+    _retransmit: Closure<dyn FnMut()>,
+}
+
+impl WebTransportConnection {
+    pub async fn connect(url: &str, cert_hash: Option<&[u8]>, inbox: Rc<Inbox>) -> Result<Self, JsValue> {
+        let options = WebTransportOptions::new();
+        if let Some(cert_hash) = cert_hash {
+            let hash = WebTransportHash::new();
+            hash.set_algorithm("sha-256");
+            hash.set_value(&js_sys::Uint8Array::from(cert_hash));
+            options.set_server_certificate_hashes(&js_sys::Array::of1(&hash));
+        }
+        let transport = WebTransport::new_with_options(url, &options)?;
+        JsFuture::from(transport.ready()).await?;
+
+        let stream: WebTransportBidirectionalStream =
+            JsFuture::from(transport.create_bidirectional_stream()).await?.unchecked_into();
+        let datagrams = transport.datagrams();
+        let writers = Rc::new(Writers {
+            stream: stream.writable().get_writer()?,
+            datagrams: datagrams.writable().get_writer()?,
+            max_datagram_size: datagrams.max_datagram_size() as usize,
+            on_error: Closure::wrap(Box::new(|_: JsValue| {}) as Box<dyn FnMut(JsValue)>),
+        });
+        // Streams are only announced to the server once written to; an empty
+        // record is ignored on the other side.
+        Writers::write(&writers.stream, &0_u32.to_le_bytes(), &writers.on_error);
+
+        let state = Rc::new(Cell::new(1_u16));
+        let unconfirmed = Rc::new(Unconfirmed {
+            input: RefCell::new(Vec::new()),
+            input_offset: Cell::new(0),
+            acks_sent: Cell::new(0),
+            acks_confirmed: Cell::new(0),
+        });
+
+        // Frames: u32 LE length + payload, possibly split across reads.
+        let reader: ReadableStreamDefaultReader = stream.readable().get_reader().unchecked_into();
+        let frame_state = state.clone();
+        let reply_writers = writers.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let mut buf: Vec<u8> = Vec::new();
+            while let Some(chunk) = read_chunk(&reader).await {
+                buf.extend_from_slice(&chunk);
+                let mut start = 0;
+                while let Some(len) = buf.get(start..start + 4) {
+                    let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+                    let end = start + 4 + len;
+                    if buf.len() < end {
+                        break;
+                    }
+                    if let Some(reply) = inbox.push_binary(buf[start + 4..end].to_vec()) {
+                        let _ = reply_writers.send_message(&reply);
+                    }
+                    start = end;
+                }
+                buf.drain(..start);
+            }
+            frame_state.set(3);
+        });
+
+        // Confirmations for input and acks.
+        let reader: ReadableStreamDefaultReader = datagrams.readable().get_reader().unchecked_into();
+        let confirm_state = unconfirmed.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            while let Some(datagram) = read_chunk(&reader).await {
+                match datagram.split_first() {
+                    Some((&KIND_INPUT, body)) => {
+                        let Some(received) = body.first_chunk::<8>() else { continue };
+                        let received = u64::from_le_bytes(*received);
+                        let offset = confirm_state.input_offset.get();
+                        if received > offset {
+                            let mut input = confirm_state.input.borrow_mut();
+                            let done = ((received - offset) as usize).min(input.len());
+                            input.drain(..done);
+                            confirm_state.input_offset.set(received);
+                        }
+                    }
+                    Some((&KIND_ACK, body)) => {
+                        let Some(counted) = body.first_chunk::<4>() else { continue };
+                        confirm_state.acks_confirmed.set(u32::from_le_bytes(*counted));
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let closed_state = state.clone();
+        let closed = transport.closed();
+        wasm_bindgen_futures::spawn_local(async move {
+            let _ = JsFuture::from(closed).await;
+            closed_state.set(3);
+        });
+
+        let timer_writers = writers.clone();
+        let timer_state = unconfirmed.clone();
+        let retransmit = Closure::wrap(Box::new(move || {
+            timer_writers.flush_input(&timer_state);
+            timer_writers.flush_acks(&timer_state);
+        }) as Box<dyn FnMut()>);
+        let window = web_sys::window().ok_or_else(|| JsValue::from_str("No window"))?;
+        let retransmit_id = window.set_interval_with_callback_and_timeout_and_arguments_0(
+            retransmit.as_ref().unchecked_ref(),
+            RETRANSMIT_MS,
+        )?;
+
+        Ok(WebTransportConnection {
+            transport,
+            writers,
+            unconfirmed,
+            state,
+            retransmit_id,
+            _retransmit: retransmit,
+        })
+    }
+
+    pub fn send_message(&self, msg: &ClientMessage) -> Result<(), JsValue> {
+        self.writers.send_message(msg)
+    }
+
+    pub fn send_input(&self, data: &[u8]) -> Result<(), JsValue> {
+        self.unconfirmed.input.borrow_mut().extend_from_slice(data);
+        self.writers.flush_input(&self.unconfirmed);
+        Ok(())
+    }
+
+    pub fn send_acks(&self, count: u32) -> Result<(), JsValue> {
+        let sent = self.unconfirmed.acks_sent.get().wrapping_add(count);
+        self.unconfirmed.acks_sent.set(sent);
+        self.writers.flush_acks(&self.unconfirmed);
+        Ok(())
+    }
+
+    /// Ready state using WebSocket numbering (1 = open, 3 = closed)
+    pub fn ready_state(&self) -> u16 {
+        self.state.get()
+    }
+}
+
+async fn read_chunk(reader: &ReadableStreamDefaultReader) -> Option<Vec<u8>> {
+    let result = JsFuture::from(reader.read()).await.ok()?;
+    let done = js_sys::Reflect::get(&result, &JsValue::from_str("done")).ok()?;
+    if done.as_bool().unwrap_or(true) {
+        return None;
+    }
+    let value = js_sys::Reflect::get(&result, &JsValue::from_str("value")).ok()?;
+    Some(js_sys::Uint8Array::new(&value).to_vec())
+}
+
+impl Drop for WebTransportConnection {
+    fn drop(&mut self) {
+        if let Some(window) = web_sys::window() {
+            window.clear_interval_with_handle(self.retransmit_id);
+        }
+        self.transport.close();
+    }
+}
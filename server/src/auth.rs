@@ -25,6 +25,8 @@ use webauthn_rs::prelude::*;
 const SESSION_COOKIE: &str = "noirtty_session";
 /// Session validity duration (24 hours)
 const SESSION_DURATION_SECS: i64 = 24 * 60 * 60;
+/// Connect ticket validity duration (30 seconds)
+const TICKET_DURATION_SECS: i64 = 30;
 
 /// Authentication state shared across handlers
 #[derive(Clone)]
@@ -45,6 +47,9 @@ struct AuthStateInner {
     auth_state: RwLock<Option<PasskeyAuthentication>>,
     /// Active sessions (session_id -> expiry timestamp)
     sessions: RwLock<std::collections::HashMap<String, i64>>,
+    /// One-time connect tickets for transports that cannot send cookies
+    /// (ticket -> expiry timestamp)
+    tickets: RwLock<std::collections::HashMap<String, i64>>,
     /// Is this an IP-based (non-domain) setup?
     is_ip_mode: bool,
 }
@@ -120,6 +125,7 @@ impl AuthState {
                 reg_state: RwLock::new(None),
                 auth_state: RwLock::new(None),
                 sessions: RwLock::new(std::collections::HashMap::new()),
+                tickets: RwLock::new(std::collections::HashMap::new()),
                 is_ip_mode,
             }),
         })
@@ -164,6 +170,32 @@ impl AuthState {
         session_id
     }
 
+    /// Issue a short-lived, single-use connect ticket
+    pub async fn issue_ticket(&self) -> String {
+        let ticket = generate_token();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let mut tickets = self.inner.tickets.write().await;
+        tickets.retain(|_, expiry| *expiry > now);
+        tickets.insert(ticket.clone(), now + TICKET_DURATION_SECS);
+        ticket
+    }
+
+    /// Consume a connect ticket, returning whether it was valid
+    pub async fn redeem_ticket(&self, ticket: &str) -> bool {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        match self.inner.tickets.write().await.remove(ticket) {
+            Some(expiry) => expiry > now,
+            None => false,
+        }
+    }
+
     /// Validate setup token
     pub async fn validate_setup_token(&self, token: &str) -> bool {
         let setup_token = self.inner.setup_token.read().await;
@@ -251,7 +283,7 @@ impl AuthState {
 
         let webauthn = self.webauthn()?;
         let (rcr, auth_state) = webauthn.start_passkey_authentication(
-            &[credential.passkey.clone()]
+            std::slice::from_ref(&credential.passkey)
         )?;
 
         *self.inner.auth_state.write().await = Some(auth_state);
@@ -0,0 +1,213 @@
+//! Binary encoding of client messages.
+//!
+//! Each message is a tag byte followed by little-endian fields:
+//!
+//! | tag | message   | body                                              |
+//! |-----|-----------|---------------------------------------------------|
+//! | 0   | data      | raw input bytes                                   |
+//! | 1   | resize    | `u16` cols, `u16` rows, [`u16` cell w, `u16` h]   |
+//! | 2   | scroll    | `i32` delta                                       |
+//! | 3   | quality   | `u32` min interval ms, `u8` compress (2 = unset)  |
+//! | 4   | credit    | `u32` frames                                      |
+//! | 5   | ack       | `u32` rendered frames                             |
+//! | 6   | ping      | `f64` client timestamp                            |
+//! | 7   | pong      | `u64` echoed server timestamp                     |
+//! | 8   | clipboard | `u8` granted (0 = refused), UTF-8 text            |
+//! | 9   | jump      | `i32` prompts to move, [`u64` view top line]      |
+//! | 10  | copy out  | `u16` viewport row, [`u64` absolute line]         |
+//! | 11  | history   | `u64` first line, `u16` line count                |
+//!
+//! Bracketed fields are optional; cell sizes are in pixels. Trailing bytes
+//! are ignored so fields can be appended later. The WASM client has the
+//! encoding side; both must stay in step.
+
+use crate::ClientMessage;
+
+const TAG_DATA: u8 = 0;
+const TAG_RESIZE: u8 = 1;
+const TAG_SCROLL: u8 = 2;
+const TAG_QUALITY: u8 = 3;
+const TAG_CREDIT: u8 = 4;
+const TAG_ACK: u8 = 5;
+const TAG_PING: u8 = 6;
+const TAG_PONG: u8 = 7;
+const TAG_CLIPBOARD: u8 = 8;
+const TAG_JUMP_PROMPT: u8 = 9;
+const TAG_COPY_OUTPUT: u8 = 10;
+const TAG_FETCH_HISTORY: u8 = 11;
+
+/// Decode one binary client message; None if it is malformed or unknown.
+pub fn decode(bytes: &[u8]) -> Option<ClientMessage> {
+    let (&tag, body) = bytes.split_first()?;
+    let mut body = Fields(body);
+    let msg = match tag {
+        TAG_DATA => ClientMessage::Data { data: body.0.to_vec() },
+        TAG_RESIZE => ClientMessage::Resize {
+            cols: u16::from_le_bytes(body.take()?),
+            rows: u16::from_le_bytes(body.take()?),
+            cell_width: body.take().map(u16::from_le_bytes),
+            cell_height: body.take().map(u16::from_le_bytes),
+        },
+        TAG_SCROLL => ClientMessage::Scroll {
+            delta: i32::from_le_bytes(body.take()?),
+        },
+        TAG_QUALITY => ClientMessage::Quality {
+            min_interval_ms: u32::from_le_bytes(body.take()?),
+            compress: match body.take::<1>()? {
+                [0] => Some(false),
+                [1] => Some(true),
+                _ => None,
+            },
+        },
+        TAG_CREDIT => ClientMessage::Credit {
+            frames: u32::from_le_bytes(body.take()?),
+        },
+        TAG_ACK => ClientMessage::Ack {
+            count: u32::from_le_bytes(body.take()?),
+        },
+        TAG_PING => ClientMessage::Ping {
+            ts: f64::from_le_bytes(body.take()?),
+        },
+        TAG_PONG => ClientMessage::Pong {
+            ts: u64::from_le_bytes(body.take()?),
+        },
+        TAG_CLIPBOARD => ClientMessage::Clipboard {
+            text: match body.take::<1>()? {
+                [0] => None,
+                _ => Some(String::from_utf8_lossy(body.0).into_owned()),
+            },
+        },
+        TAG_JUMP_PROMPT => ClientMessage::JumpPrompt {
+            delta: i32::from_le_bytes(body.take()?),
+            from_line: body.take().map(u64::from_le_bytes),
+        },
+        TAG_COPY_OUTPUT => ClientMessage::CopyOutput {
+            row: u16::from_le_bytes(body.take()?),
+            line: body.take().map(u64::from_le_bytes),
+        },
+        TAG_FETCH_HISTORY => ClientMessage::FetchHistory {
+            from_line: u64::from_le_bytes(body.take()?),
+            count: u16::from_le_bytes(body.take()?),
+        },
+        _ => return None,
+    };
+    Some(msg)
+}
+
+/// Cursor over a message body.
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (field, rest) = self.0.split_first_chunk::<N>()?;
+        self.0 = rest;
+        Some(*field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Messages as the client encodes them.
+    fn messages() -> Vec<(Vec<u8>, ClientMessage)> {
+        let message = |tag: u8, fields: &[&[u8]]| [&[tag][..], &fields.concat()].concat();
+        vec![
+            (message(TAG_DATA, &[b"ls\r"]), ClientMessage::Data { data: b"ls\r".to_vec() }),
+            (message(TAG_DATA, &[]), ClientMessage::Data { data: Vec::new() }),
+            (
+                message(TAG_RESIZE, &[&80u16.to_le_bytes(), &24u16.to_le_bytes()]),
+                ClientMessage::Resize { cols: 80, rows: 24, cell_width: None, cell_height: None },
+            ),
+            (
+                message(
+                    TAG_RESIZE,
+                    &[&80u16.to_le_bytes(), &24u16.to_le_bytes(), &9u16.to_le_bytes(), &18u16.to_le_bytes()],
+                ),
+                ClientMessage::Resize { cols: 80, rows: 24, cell_width: Some(9), cell_height: Some(18) },
+            ),
+            (message(TAG_SCROLL, &[&(-3i32).to_le_bytes()]), ClientMessage::Scroll { delta: -3 }),
+            (
+                message(TAG_QUALITY, &[&33u32.to_le_bytes(), &[0]]),
+                ClientMessage::Quality { min_interval_ms: 33, compress: Some(false) },
+            ),
+            (
+                message(TAG_QUALITY, &[&0u32.to_le_bytes(), &[1]]),
+                ClientMessage::Quality { min_interval_ms: 0, compress: Some(true) },
+            ),
+            (
+                message(TAG_QUALITY, &[&16u32.to_le_bytes(), &[2]]),
+                ClientMessage::Quality { min_interval_ms: 16, compress: None },
+            ),
+            (message(TAG_CREDIT, &[&8u32.to_le_bytes()]), ClientMessage::Credit { frames: 8 }),
+            (message(TAG_ACK, &[&2u32.to_le_bytes()]), ClientMessage::Ack { count: 2 }),
+            (message(TAG_PING, &[&1234.5f64.to_le_bytes()]), ClientMessage::Ping { ts: 1234.5 }),
+            (message(TAG_PONG, &[&u64::MAX.to_le_bytes()]), ClientMessage::Pong { ts: u64::MAX }),
+            (message(TAG_CLIPBOARD, &[&[0]]), ClientMessage::Clipboard { text: None }),
+            (message(TAG_CLIPBOARD, &[&[1]]), ClientMessage::Clipboard { text: Some(String::new()) }),
+            (
+                message(TAG_CLIPBOARD, &[&[1], "h\u{e9}llo".as_bytes()]),
+                ClientMessage::Clipboard { text: Some("h\u{e9}llo".into()) },
+            ),
+            (
+                message(TAG_JUMP_PROMPT, &[&(-2i32).to_le_bytes()]),
+                ClientMessage::JumpPrompt { delta: -2, from_line: None },
+            ),
+            (
+                message(TAG_JUMP_PROMPT, &[&1i32.to_le_bytes(), &500u64.to_le_bytes()]),
+                ClientMessage::JumpPrompt { delta: 1, from_line: Some(500) },
+            ),
+            (message(TAG_COPY_OUTPUT, &[&7u16.to_le_bytes()]), ClientMessage::CopyOutput { row: 7, line: None }),
+            (
+                message(TAG_COPY_OUTPUT, &[&7u16.to_le_bytes(), &12345u64.to_le_bytes()]),
+                ClientMessage::CopyOutput { row: 7, line: Some(12345) },
+            ),
+            (
+                message(TAG_FETCH_HISTORY, &[&9800u64.to_le_bytes(), &200u16.to_le_bytes()]),
+                ClientMessage::FetchHistory { from_line: 9800, count: 200 },
+            ),
+        ]
+    }
+
+    #[test]
+    fn decodes_every_message() {
+        for (bytes, expected) in messages() {
+            assert_eq!(decode(&bytes), Some(expected), "{bytes:?}");
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_are_ignored() {
+        for (mut bytes, expected) in messages() {
+            // Data and clipboard text run to the end of the message
+            if matches!(expected, ClientMessage::Data { .. } | ClientMessage::Clipboard { .. }) {
+                continue;
+            }
+            // Optional fields come last, so only complete messages can grow
+            let complete = match expected {
+                ClientMessage::Resize { cell_width, .. } => cell_width.is_some(),
+                ClientMessage::JumpPrompt { from_line, .. } => from_line.is_some(),
+                ClientMessage::CopyOutput { line, .. } => line.is_some(),
+                _ => true,
+            };
+            if complete {
+                bytes.extend_from_slice(&[0xaa; 9]);
+            } else {
+                // Too short to be the optional field
+                bytes.push(0xaa);
+            }
+            assert_eq!(decode(&bytes), Some(expected), "{bytes:?}");
+        }
+    }
+
+    #[test]
+    fn malformed_messages() {
+        assert_eq!(decode(&[]), None);
+        assert_eq!(decode(&[12]), None);
+        assert_eq!(decode(&[TAG_RESIZE, 80, 0, 24]), None);
+        assert_eq!(decode(&[TAG_SCROLL, 1, 0, 0]), None);
+        assert_eq!(decode(&[TAG_QUALITY, 16, 0, 0, 0]), None);
+        assert_eq!(decode(&[TAG_CLIPBOARD]), None);
+        assert_eq!(decode(&[TAG_FETCH_HISTORY, 0, 0, 0, 0, 0, 0, 0, 0, 200]), None);
+    }
+}
@@ -0,0 +1,213 @@
+//! Application-level frame compression for the WebSocket transport.
+//!
+//! Each connection owns one raw deflate stream that is sync-flushed after every
+//! message, so earlier frames stay in the window and act as a dictionary for
+//! the next one. Both ends prime the stream with `static/frame.dict`, a small
+//! frame serialized in bincode and then in JSON; the client feeds it to its
+//! inflater as a stored block, which leaves both windows holding the same
+//! bytes without sending the dictionary. The file is generated by the test
+//! below, which fails whenever the wire format drifts from it.
+
+use flate2::{Compress, Compression, FlushCompress, Status};
+
+/// Dictionary shared with the WASM client. Both builds must embed the same bytes.
+static FRAME_DICTIONARY: &[u8] = include_bytes!("../../static/frame.dict");
+
+/// Per-connection deflate stream with context takeover.
+pub struct FrameCompressor {
+    stream: Compress,
+}
+
+impl FrameCompressor {
+    pub fn new() -> Self {
+        let mut compressor = FrameCompressor {
+            stream: Compress::new(Compression::fast(), false),
+        };
+        // Prime the window; the output is discarded because the client
+        // reconstructs the same history locally.
+        let _ = compressor.compress(FRAME_DICTIONARY);
+        compressor
+    }
+
+    /// Compress one message, ending on a byte boundary so it can be inflated
+    /// on its own by a decoder that has seen every previous message.
+    pub fn compress(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(input.len() / 4 + 64);
+        let mut consumed = 0;
+        loop {
+            if out.len() == out.capacity() {
+                out.reserve(out.capacity().max(1024));
+            }
+            let before_in = self.stream.total_in();
+            let before_out = out.len();
+            let status = self
+                .stream
+                .compress_vec(&input[consumed..], &mut out, FlushCompress::Sync);
+            consumed += (self.stream.total_in() - before_in) as usize;
+            let stalled = self.stream.total_in() == before_in && out.len() == before_out;
+            match status {
+                Ok(Status::Ok) | Ok(Status::BufError) => {
+                    // Finished once all input is in and the flush fit in the buffer.
+                    if consumed == input.len() && out.len() < out.capacity() {
+                        break;
+                    }
+                    if stalled && out.len() < out.capacity() {
+                        break;
+                    }
+                }
+                Ok(Status::StreamEnd) | Err(_) => break,
+            }
+        }
+        out
+    }
+}
+
+impl Default for FrameCompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build_frame, palette, Emulator, ServerMessage, TermCommand, TermEvents};
+    use flate2::{Decompress, FlushDecompress};
+    use tokio::sync::{broadcast, mpsc, watch};
+
+    /// Deflate window: the part of the dictionary a first frame can refer to.
+    const WINDOW: usize = 32 * 1024;
+
+    /// Output of a short shell session, as the dictionary shows it.
+    const SESSION: &[u8] = b"\x1b]0;user@host: ~\x07\
+        \x1b[1;32muser@host\x1b[0m:\x1b[1;34m~\x1b[0m$ ls\r\n\
+        \x1b[01;34mdocs\x1b[0m  \x1b]8;;file:///home/user/README.md\x1b\\\x1b[4mREADME.md\x1b[24m\x1b]8;;\x1b\\\r\n\
+        \x1b[7m\xe4\xb8\xad\xe6\x96\x87\x1b[27m \x1b[3;9mdone\x1b[0m \x1b[38;5;208m!\x1b[0m\r\n\
+        \x1b[1;32muser@host\x1b[0m:\x1b[1;34m~\x1b[0m$ ";
+
+    /// The dictionary: a frame of `SESSION` in bincode, then in JSON.
+    fn frame_dictionary() -> Vec<u8> {
+        let events = TermEvents {
+            title_tx: watch::channel(None).0,
+            bell_tx: watch::channel(0).0,
+            clipboard_tx: watch::channel(None).0,
+            paste_tx: watch::channel(None).0,
+            notify_tx: broadcast::channel(1).0,
+            cwd_tx: watch::channel(None).0,
+            responder_rx: watch::channel(false).1,
+        };
+        let mut emulator = Emulator::new(events, mpsc::channel(16).0, palette::Palette::default());
+        emulator.apply(TermCommand::Resize(24, 4, None));
+        emulator.apply(TermCommand::Data(SESSION.to_vec()));
+        let frame = ServerMessage::Frame(build_frame(&emulator));
+        [
+            bincode::serialize(&frame).unwrap(),
+            serde_json::to_vec(&frame).unwrap(),
+        ]
+        .concat()
+    }
+
+    /// An inflater primed the way the client primes it.
+    fn client_inflater() -> Decompress {
+        let mut stream = Decompress::new(false);
+        for chunk in FRAME_DICTIONARY.chunks(u16::MAX as usize) {
+            let len = chunk.len() as u16;
+            let block = [&[0][..], &len.to_le_bytes(), &(!len).to_le_bytes(), chunk].concat();
+            let mut out = Vec::with_capacity(chunk.len());
+            stream
+                .decompress_vec(&block, &mut out, FlushDecompress::Sync)
+                .unwrap();
+            assert_eq!(out, chunk);
+        }
+        stream
+    }
+
+    /// Inflates one message, checking that all of it was consumed.
+    fn inflate(stream: &mut Decompress, input: &[u8], len: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(len + 1);
+        let before = stream.total_in();
+        stream
+            .decompress_vec(input, &mut out, FlushDecompress::Sync)
+            .unwrap();
+        assert_eq!((stream.total_in() - before) as usize, input.len());
+        out
+    }
+
+    /// Regenerate with `NOIRTTY_WRITE_DICTIONARY=1 cargo test frame_dictionary`.
+    #[test]
+    fn frame_dictionary_matches_the_wire_format() {
+        let dictionary = frame_dictionary();
+        assert!(
+            dictionary.len() <= WINDOW,
+            "dictionary is {} bytes",
+            dictionary.len()
+        );
+        if std::env::var_os("NOIRTTY_WRITE_DICTIONARY").is_some() {
+            std::fs::write(
+                concat!(env!("CARGO_MANIFEST_DIR"), "/../static/frame.dict"),
+                &dictionary,
+            )
+            .unwrap();
+        }
+        assert!(
+            FRAME_DICTIONARY == dictionary,
+            "static/frame.dict is out of date; regenerate it with NOIRTTY_WRITE_DICTIONARY=1"
+        );
+    }
+
+    #[test]
+    fn round_trip_with_context_takeover() {
+        let mut compressor = FrameCompressor::new();
+        let mut inflater = client_inflater();
+        let frames = [
+            br#"{"type":"frame","cols":80,"rows":24,"cells":[{"c":"a"}]}"#.to_vec(),
+            br#"{"type":"title","title":"vim"}"#.to_vec(),
+            br#"{"type":"frame","cols":80,"rows":24,"cells":[{"c":"b"}]}"#.to_vec(),
+            Vec::new(),
+            frame_dictionary(),
+        ];
+        for frame in &frames {
+            let compressed = compressor.compress(frame);
+            assert!(compressed.ends_with(&[0, 0, 0xff, 0xff]));
+            assert_eq!(&inflate(&mut inflater, &compressed, frame.len()), frame);
+        }
+    }
+
+    #[test]
+    fn primed_window_shrinks_the_first_frame() {
+        let frame = frame_dictionary();
+        let primed = FrameCompressor::new().compress(&frame);
+        let mut cold = FrameCompressor {
+            stream: Compress::new(Compression::fast(), false),
+        };
+        let cold = cold.compress(&frame);
+        assert!(
+            primed.len() * 2 < cold.len(),
+            "{} bytes primed, {} cold",
+            primed.len(),
+            cold.len()
+        );
+    }
+
+    #[test]
+    fn message_larger_than_the_output_buffer() {
+        // Noise does not compress, so the output outgrows its first guess
+        let mut seed = 0x2545_f491_u32;
+        let noise: Vec<u8> = (0..256 * 1024)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect();
+        let mut compressor = FrameCompressor::new();
+        let mut inflater = client_inflater();
+        let compressed = compressor.compress(&noise);
+        assert!(compressed.len() > noise.len() / 4 + 64);
+        assert_eq!(inflate(&mut inflater, &compressed, noise.len()), noise);
+        // The stream stays in step afterwards
+        let compressed = compressor.compress(b"after");
+        assert_eq!(inflate(&mut inflater, &compressed, 5), b"after");
+    }
+}
@@ -0,0 +1,797 @@
+//! Inline images (sixel and the kitty graphics protocol) anchored to the
+//! grid.
+//!
+//! alacritty_terminal ignores DCS and APC strings, so `ImageScanner` picks
+//! sixel images and kitty graphics commands out of the PTY output next to
+//! the terminal, the same way `OscScanner` does for OSC strings. A placed
+//! image sits at the cursor and the cursor moves past it; `ImageStore`
+//! remembers where each placement sits as an absolute line (see `marks`),
+//! so images scroll with the text into the scrollback and are dropped once
+//! their lines are. Kitty images are kept apart from their placements, as
+//! programs may transmit an image once and place it many times.
+//!
+//! The grid itself knows nothing of the images: the client draws them over
+//! or under the cells they cover.
+
+use crate::kitty;
+use crate::marks::CommandMarks;
+use crate::sixel;
+use alacritty_terminal::grid::Dimensions;
+use alacritty_terminal::term::{Term, TermMode};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Largest image decoded, in pixels.
+pub const MAX_PIXELS: usize = 4096 * 4096;
+/// Longest DCS or APC string kept; longer ones are dropped whole.
+const MAX_STRING_LEN: usize = 16 << 20;
+/// Longest CSI parameter string kept.
+const MAX_PARAMS_LEN: usize = 64;
+/// Longest payload of a chunked kitty transmission: `MAX_PIXELS` of RGBA
+/// in base64.
+const MAX_PAYLOAD_LEN: usize = MAX_PIXELS * 4 / 3 * 4;
+/// Images kept at once, oldest dropped first.
+const MAX_IMAGES: usize = 256;
+/// Placements kept at once, oldest dropped first.
+const MAX_PLACEMENTS: usize = 1024;
+/// Pixel data kept at once, oldest images dropped first.
+const MAX_IMAGE_BYTES: usize = 128 << 20;
+/// Longest side an image is drawn at, in pixels.
+const MAX_DRAWN_SIDE: u32 = 1 << 15;
+/// Cell size assumed until the client reports its own.
+const DEFAULT_CELL_SIZE: (u16, u16) = (10, 20);
+/// First id handed to kitty images sent without one, clear of the small
+/// ids programs pick.
+const FIRST_KITTY_ID: u32 = 1 << 31;
+
+const ESC: u8 = 0x1b;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+
+/// A decoded image, RGBA with 4 bytes per pixel, row by row.
+pub struct Pixels {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+/// Something in the PTY output that affects images.
+pub enum ImageEvent {
+    Sixel(Pixels),
+    /// A kitty graphics command: the APC string after its `G`.
+    Graphics(Vec<u8>),
+    /// `CSI 2 J`
+    EraseDisplay,
+    /// `ESC c`
+    Reset,
+}
+
+/// The strings `ImageScanner` keeps.
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Sixel,
+    Graphics,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+    /// Inside a CSI sequence that is not of interest.
+    CsiIgnore,
+    /// After `DCS`, in the parameters.
+    DcsEntry,
+    /// After `APC`, before its first byte.
+    ApcEntry,
+    /// Inside a string of interest, collecting its data.
+    String(Kind),
+    /// ESC inside such a string, possibly starting the ST terminator.
+    StringEscape(Kind),
+    /// Inside a DCS or APC string that is not of interest.
+    StringIgnore,
+}
+
+/// Incremental extractor of image strings; sequences may span reads.
+pub struct ImageScanner {
+    state: State,
+    params: Vec<u8>,
+    data: Vec<u8>,
+    overflow: bool,
+}
+
+impl ImageScanner {
+    pub fn new() -> Self {
+        ImageScanner {
+            state: State::Ground,
+            params: Vec::new(),
+            data: Vec::new(),
+            overflow: false,
+        }
+    }
+
+    /// Scan a chunk of PTY output, calling `on_event` with the offset just
+    /// past every complete sequence of interest.
+    pub fn feed(&mut self, bytes: &[u8], mut on_event: impl FnMut(usize, ImageEvent)) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.state = match (self.state, byte) {
+                (State::String(kind), ESC) => State::StringEscape(kind),
+                (State::String(_), CAN | SUB) => self.end_string(),
+                (State::String(kind), _) => {
+                    if self.data.len() < MAX_STRING_LEN {
+                        self.data.push(byte);
+                    } else {
+                        self.overflow = true;
+                    }
+                    State::String(kind)
+                }
+                (State::StringEscape(kind), b'\\') => {
+                    if !self.overflow {
+                        if let Some(event) = self.finish(kind) {
+                            on_event(i + 1, event);
+                        }
+                    }
+                    self.end_string()
+                }
+                // Any other escape aborts the string and may start a new
+                // sequence, as in the terminal itself
+                (State::StringEscape(_), _) => {
+                    self.end_string();
+                    self.escape(byte, |event| on_event(i + 1, event))
+                }
+                (_, CAN | SUB) => State::Ground,
+                (_, ESC) => State::Escape,
+                (State::Ground, _) => State::Ground,
+                (State::Escape, _) => self.escape(byte, |event| on_event(i + 1, event)),
+                (State::Csi, b'0'..=b'9' | b';') => {
+                    if self.params.len() < MAX_PARAMS_LEN {
+                        self.params.push(byte);
+                    }
+                    State::Csi
+                }
+                (State::Csi, b'J') => {
+                    if self.params == b"2" {
+                        on_event(i + 1, ImageEvent::EraseDisplay);
+                    }
+                    State::Ground
+                }
+                (State::Csi | State::CsiIgnore, 0x40..=0x7e) => State::Ground,
+                (State::Csi | State::CsiIgnore, _) => State::CsiIgnore,
+                (State::DcsEntry, b'0'..=b'9' | b';') => {
+                    if self.params.len() < MAX_PARAMS_LEN {
+                        self.params.push(byte);
+                    }
+                    State::DcsEntry
+                }
+                (State::DcsEntry, b'q') => self.start_string(Kind::Sixel),
+                (State::ApcEntry, b'G') => self.start_string(Kind::Graphics),
+                (State::DcsEntry | State::ApcEntry | State::StringIgnore, _) => State::StringIgnore,
+            };
+        }
+    }
+
+    /// State after `ESC byte`.
+    fn escape(&mut self, byte: u8, on_event: impl FnOnce(ImageEvent)) -> State {
+        match byte {
+            b'[' => {
+                self.params.clear();
+                State::Csi
+            }
+            b'P' => {
+                self.params.clear();
+                State::DcsEntry
+            }
+            b'_' => State::ApcEntry,
+            b'c' => {
+                on_event(ImageEvent::Reset);
+                State::Ground
+            }
+            ESC => State::Escape,
+            _ => State::Ground,
+        }
+    }
+
+    fn start_string(&mut self, kind: Kind) -> State {
+        self.data.clear();
+        self.overflow = false;
+        State::String(kind)
+    }
+
+    /// The event a complete string makes, if any.
+    fn finish(&mut self, kind: Kind) -> Option<ImageEvent> {
+        match kind {
+            Kind::Sixel => sixel::decode(&self.params, &self.data).map(ImageEvent::Sixel),
+            Kind::Graphics => Some(ImageEvent::Graphics(std::mem::take(&mut self.data))),
+        }
+    }
+
+    fn end_string(&mut self) -> State {
+        self.data = Vec::new();
+        State::Ground
+    }
+}
+
+/// Pixel data of an image, shared by every frame that shows it.
+#[derive(Clone, Debug)]
+pub struct Image {
+    /// Unique for the session's lifetime, unlike kitty image ids, which
+    /// programs reuse.
+    pub id: u32,
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Arc<[u8]>,
+}
+
+/// An image and the cell its top left corner is anchored to.
+pub struct Placement {
+    pub image: Image,
+    /// Absolute line, as in `CommandMarks`.
+    pub line: u64,
+    pub column: u16,
+    /// Offset of the top left corner into the cell, in pixels.
+    pub offset: (u32, u32),
+    /// Part of the image shown: x, y, width and height in image pixels.
+    pub source: [u32; 4],
+    /// Size drawn, in pixels.
+    pub size: (u32, u32),
+    /// Stacking order: negative under the text, otherwise over it.
+    pub z: i32,
+    /// Cells covered.
+    rows: u32,
+    columns: u32,
+    /// Placed on the alternate screen, which has lines of its own.
+    alt: bool,
+    /// Kitty image and placement ids (0 if unset); None for sixel images.
+    kitty: Option<(u32, u32)>,
+}
+
+impl Placement {
+    fn covers(&self, line: u64, column: u32) -> bool {
+        self.covers_line(line) && self.covers_column(column)
+    }
+
+    fn covers_line(&self, line: u64) -> bool {
+        (self.line..self.line + self.rows as u64).contains(&line)
+    }
+
+    fn covers_column(&self, column: u32) -> bool {
+        (self.column as u32..self.column as u32 + self.columns).contains(&column)
+    }
+}
+
+/// An image sent with the kitty graphics protocol, placed or not.
+struct KittyImage {
+    /// `i`, chosen by the program or handed out.
+    id: u32,
+    /// `I`, 0 if the program gave none.
+    number: u32,
+    image: Image,
+}
+
+/// A chunked kitty transmission still in progress.
+struct Upload {
+    /// The first chunk's command, with the payload of every chunk so far.
+    command: kitty::Command,
+    too_large: bool,
+}
+
+/// What the terminal must do after an image event.
+#[derive(Default)]
+pub struct Effect {
+    /// Cursor motion past a new image, fed to the terminal.
+    pub cursor: Vec<u8>,
+    /// Reply to the program.
+    pub reply: Option<String>,
+}
+
+pub struct ImageStore {
+    /// Oldest first.
+    placements: VecDeque<Placement>,
+    /// Oldest first.
+    kitty: Vec<KittyImage>,
+    upload: Option<Upload>,
+    next_id: u32,
+    next_kitty_id: u32,
+    /// Cell size in pixels, as the client last reported it.
+    cell: (u16, u16),
+}
+
+impl ImageStore {
+    pub fn new() -> Self {
+        ImageStore {
+            placements: VecDeque::new(),
+            kitty: Vec::new(),
+            upload: None,
+            next_id: 1,
+            next_kitty_id: FIRST_KITTY_ID,
+            cell: DEFAULT_CELL_SIZE,
+        }
+    }
+
+    pub fn set_cell_size(&mut self, width: u16, height: u16) {
+        self.cell = (width.max(1), height.max(1));
+    }
+
+    /// Act on an event at the current cursor.
+    pub fn handle<T>(&mut self, term: &Term<T>, marks: &CommandMarks, event: ImageEvent) -> Effect {
+        let alt = term.mode().contains(TermMode::ALT_SCREEN);
+        match event {
+            ImageEvent::Sixel(pixels) => {
+                let image = self.new_image(pixels);
+                let (line, column) = anchor(term, marks);
+                let size = (image.width, image.height);
+                let (columns, rows) = self.cells((0, 0), size);
+                // A redraw in place replaces the old image
+                self.placements.retain(|old| {
+                    !(old.kitty.is_none() && old.line == line && old.column == column && old.alt == alt)
+                });
+                self.placements.push_back(Placement {
+                    source: [0, 0, image.width, image.height],
+                    image,
+                    line,
+                    column,
+                    offset: (0, 0),
+                    size,
+                    z: 0,
+                    rows,
+                    columns,
+                    alt,
+                    kitty: None,
+                });
+                self.trim();
+                // Sixel leaves the cursor below the image, in its column
+                Effect { cursor: b"\x1bD".repeat(rows as usize), reply: None }
+            }
+            ImageEvent::Graphics(data) => self.graphics(term, marks, &data),
+            // The primary screen scrolls its contents into the scrollback
+            // instead, and the images go with them
+            ImageEvent::EraseDisplay => {
+                if alt {
+                    self.placements.retain(|placement| !placement.alt);
+                }
+                Effect::default()
+            }
+            ImageEvent::Reset => {
+                self.placements.clear();
+                self.kitty.clear();
+                self.upload = None;
+                Effect::default()
+            }
+        }
+    }
+
+    /// Run a kitty graphics command, once all its chunks are in.
+    fn graphics<T>(&mut self, term: &Term<T>, marks: &CommandMarks, data: &[u8]) -> Effect {
+        let Some(mut command) = kitty::parse(data) else {
+            return Effect::default();
+        };
+        let mut too_large = false;
+        if let Some(mut upload) = self.upload.take() {
+            // Later chunks carry the payload and little else
+            if upload.command.payload.len() + command.payload.len() > MAX_PAYLOAD_LEN {
+                upload.too_large = true;
+                upload.command.payload = Vec::new();
+            } else if !upload.too_large {
+                upload.command.payload.append(&mut command.payload);
+            }
+            if command.more {
+                self.upload = Some(upload);
+                return Effect::default();
+            }
+            command = upload.command;
+            too_large = upload.too_large;
+        } else if command.more {
+            self.upload = Some(Upload { command, too_large: false });
+            return Effect::default();
+        }
+
+        let result = match command.action {
+            _ if too_large => Err("EFBIG:image too large"),
+            b't' | b'T' | b'q' => self.transmit(term, marks, &mut command),
+            b'p' => self.put(term, marks, &mut command),
+            b'd' => {
+                self.delete(term, marks, &command);
+                return Effect::default();
+            }
+            _ => Err("EINVAL:unsupported action"),
+        };
+        let reply = kitty::reply(&command, result.as_ref().map(|_| ()).map_err(|error| *error));
+        Effect { cursor: result.unwrap_or_default(), reply }
+    }
+
+    /// `a=t`, `a=T` and `a=q`: decode an image, and keep and place it
+    /// unless it is a query. Returns the cursor motion.
+    fn transmit<T>(
+        &mut self,
+        term: &Term<T>,
+        marks: &CommandMarks,
+        command: &mut kitty::Command,
+    ) -> Result<Vec<u8>, &'static str> {
+        let pixels = kitty::decode(command)?;
+        if command.action == b'q' {
+            return Ok(Vec::new());
+        }
+        let id = match command.id {
+            0 => self.unused_kitty_id(),
+            id => id,
+        };
+        // Programs that numbered the image learn its id from the reply
+        if command.number != 0 {
+            command.id = id;
+        }
+        // A new image under an old id replaces it, placements and all
+        self.remove_kitty(|old| old.id == id);
+        let image = self.new_image(pixels);
+        self.kitty.push(KittyImage { id, number: command.number, image: image.clone() });
+        self.trim();
+        if command.action == b'T' {
+            self.place(term, marks, command, id, image)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// `a=p`: place an image sent before, by id or by number.
+    fn put<T>(
+        &mut self,
+        term: &Term<T>,
+        marks: &CommandMarks,
+        command: &mut kitty::Command,
+    ) -> Result<Vec<u8>, &'static str> {
+        let found = if command.id != 0 {
+            self.kitty.iter().find(|image| image.id == command.id)
+        } else if command.number != 0 {
+            self.kitty.iter().rev().find(|image| image.number == command.number)
+        } else {
+            return Err("EINVAL:no image id or number");
+        };
+        let found = found.ok_or("ENOENT:no such image")?;
+        command.id = found.id;
+        let image = found.image.clone();
+        self.place(term, marks, command, command.id, image)
+    }
+
+    /// Place a kitty image at the cursor. Returns the cursor motion past it.
+    fn place<T>(
+        &mut self,
+        term: &Term<T>,
+        marks: &CommandMarks,
+        command: &kitty::Command,
+        id: u32,
+        image: Image,
+    ) -> Result<Vec<u8>, &'static str> {
+        let x = command.x.min(image.width);
+        let y = command.y.min(image.height);
+        let width = if command.w == 0 { image.width - x } else { command.w.min(image.width - x) };
+        let height = if command.h == 0 { image.height - y } else { command.h.min(image.height - y) };
+        if width == 0 || height == 0 {
+            return Err("EINVAL:source rectangle is empty");
+        }
+        // Scaled to `c` columns and `r` rows; given one, the other follows
+        // the aspect ratio
+        let (cell_width, cell_height) = (self.cell.0 as u32, self.cell.1 as u32);
+        let fit_width = command.columns.min(MAX_DRAWN_SIDE) * cell_width;
+        let fit_height = command.rows.min(MAX_DRAWN_SIDE) * cell_height;
+        let size = match (fit_width, fit_height) {
+            (0, 0) => (width, height),
+            (fit_width, 0) => (fit_width, scale(height, fit_width, width)),
+            (0, fit_height) => (scale(width, fit_height, height), fit_height),
+            fit => fit,
+        };
+        let size = (size.0.clamp(1, MAX_DRAWN_SIDE), size.1.clamp(1, MAX_DRAWN_SIDE));
+        let offset = (command.offset_x.min(cell_width - 1), command.offset_y.min(cell_height - 1));
+        let (columns, rows) = self.cells(offset, size);
+        let (line, column) = anchor(term, marks);
+        let kitty = Some((id, command.placement));
+        if command.placement != 0 {
+            // Placing it again moves it
+            self.placements.retain(|old| old.kitty != kitty);
+        }
+        self.placements.push_back(Placement {
+            image,
+            line,
+            column,
+            offset,
+            source: [x, y, width, height],
+            size,
+            z: command.z,
+            rows,
+            columns,
+            alt: term.mode().contains(TermMode::ALT_SCREEN),
+            kitty,
+        });
+        self.trim();
+
+        // Like text, the image leaves the cursor just after its last cell
+        if command.no_move {
+            return Ok(Vec::new());
+        }
+        let mut cursor = b"\x1bD".repeat(rows as usize - 1);
+        if column as u32 + columns >= term.columns() as u32 {
+            cursor.extend_from_slice(b"\r\x1bD");
+        } else {
+            cursor.extend_from_slice(format!("\x1b[{columns}C").as_bytes());
+        }
+        Ok(cursor)
+    }
+
+    /// `a=d`: delete placements, and with an uppercase `d` the images left
+    /// without any.
+    fn delete<T>(&mut self, term: &Term<T>, marks: &CommandMarks, command: &kitty::Command) {
+        let alt = term.mode().contains(TermMode::ALT_SCREEN);
+        let cursor = anchor(term, marks);
+        let screen_top = cursor.0 - term.grid().cursor.point.line.0 as u64;
+        let screen = screen_top..screen_top + term.screen_lines() as u64;
+        // Cells in commands count from 1, at the top left of the screen
+        let line = screen_top + command.y.saturating_sub(1) as u64;
+        let column = command.x.saturating_sub(1);
+        let numbered = self.kitty.iter().rev().find(|image| image.number == command.number).map(|image| image.id);
+        let by_id = |id: u32| match command.delete.to_ascii_lowercase() {
+            b'i' => id == command.id,
+            b'n' => Some(id) == numbered,
+            b'r' => (command.x..=command.y).contains(&id),
+            _ => false,
+        };
+        let doomed = |placement: &Placement| match command.delete.to_ascii_lowercase() {
+            b'i' | b'n' => placement.kitty.is_some_and(|(id, placement)| {
+                by_id(id) && (command.placement == 0 || placement == command.placement)
+            }),
+            b'r' => placement.kitty.is_some_and(|(id, _)| by_id(id)),
+            _ if placement.alt != alt => false,
+            b'a' => placement.line < screen.end && placement.line + placement.rows as u64 > screen.start,
+            b'c' => placement.covers(cursor.0, cursor.1 as u32),
+            b'p' => placement.covers(line, column),
+            b'q' => placement.covers(line, column) && placement.z == command.z,
+            b'x' => placement.covers_column(column),
+            b'y' => placement.covers_line(line),
+            b'z' => placement.z == command.z,
+            _ => false,
+        };
+        let mut unplaced = Vec::new();
+        self.placements.retain(|placement| {
+            let gone = doomed(placement);
+            if gone {
+                unplaced.extend(placement.kitty.map(|(id, _)| id));
+            }
+            !gone
+        });
+        if command.delete.is_ascii_uppercase() {
+            let placements = &self.placements;
+            let placed = |image: &KittyImage| placements.iter().any(|placement| placement.image.id == image.image.id);
+            let free: Vec<u32> = self
+                .kitty
+                .iter()
+                .filter(|image| (unplaced.contains(&image.id) || by_id(image.id)) && !placed(image))
+                .map(|image| image.id)
+                .collect();
+            self.remove_kitty(|image| free.contains(&image.id));
+        }
+    }
+
+    /// Forget placements whose lines are gone, and those of the alternate
+    /// screen once it is left.
+    pub fn sync<T>(&mut self, term: &Term<T>, history_start: u64) {
+        let alt = term.mode().contains(TermMode::ALT_SCREEN);
+        self.placements.retain(|placement| {
+            if placement.alt {
+                alt
+            } else {
+                placement.line + placement.rows as u64 > history_start
+            }
+        });
+    }
+
+    /// Placements on the screen the terminal is showing, oldest first.
+    pub fn visible<T>(&self, term: &Term<T>) -> impl Iterator<Item = &Placement> {
+        let alt = term.mode().contains(TermMode::ALT_SCREEN);
+        self.placements.iter().filter(move |placement| placement.alt == alt)
+    }
+
+    fn new_image(&mut self, pixels: Pixels) -> Image {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        Image {
+            id,
+            width: pixels.width as u32,
+            height: pixels.height as u32,
+            rgba: pixels.rgba.into(),
+        }
+    }
+
+    fn unused_kitty_id(&mut self) -> u32 {
+        loop {
+            let id = self.next_kitty_id;
+            self.next_kitty_id = self.next_kitty_id.wrapping_add(1).max(FIRST_KITTY_ID);
+            if !self.kitty.iter().any(|image| image.id == id) {
+                return id;
+            }
+        }
+    }
+
+    /// Columns and rows covered by an image drawn at `size` from `offset`.
+    fn cells(&self, offset: (u32, u32), size: (u32, u32)) -> (u32, u32) {
+        let columns = (offset.0 + size.0).div_ceil(self.cell.0 as u32);
+        let rows = (offset.1 + size.1).div_ceil(self.cell.1 as u32);
+        (columns.max(1), rows.max(1))
+    }
+
+    /// Drop kitty images, and their placements.
+    fn remove_kitty(&mut self, remove: impl Fn(&KittyImage) -> bool) {
+        let ids: Vec<u32> = self.kitty.iter().filter(|image| remove(image)).map(|image| image.image.id).collect();
+        if !ids.is_empty() {
+            self.kitty.retain(|image| !ids.contains(&image.image.id));
+            self.placements.retain(|placement| !ids.contains(&placement.image.id));
+        }
+    }
+
+    /// Drop the oldest placements and images over the limits. Images are
+    /// the kitty images, placed or not, and the placed sixel images.
+    fn trim(&mut self) {
+        while self.placements.len() > MAX_PLACEMENTS {
+            self.placements.pop_front();
+        }
+        loop {
+            let sixels = self.placements.iter().filter(|placement| placement.kitty.is_none());
+            let count = sixels.clone().count() + self.kitty.len();
+            let bytes = sixels.map(|placement| placement.image.rgba.len()).sum::<usize>()
+                + self.kitty.iter().map(|image| image.image.rgba.len()).sum::<usize>();
+            if count <= MAX_IMAGES && bytes <= MAX_IMAGE_BYTES {
+                break;
+            }
+            // Ids grow, so the smaller one is older
+            let oldest_sixel = self.placements.iter().position(|placement| placement.kitty.is_none());
+            let oldest_kitty = self.kitty.first().map(|image| image.image.id);
+            match (oldest_sixel, oldest_kitty) {
+                (Some(index), Some(id)) if self.placements[index].image.id > id => {
+                    self.remove_kitty(|image| image.image.id == id);
+                }
+                (Some(index), _) => {
+                    self.placements.remove(index);
+                }
+                (None, Some(id)) => self.remove_kitty(|image| image.image.id == id),
+                (None, None) => break,
+            }
+        }
+    }
+}
+
+/// Absolute line and column of the cursor.
+fn anchor<T>(term: &Term<T>, marks: &CommandMarks) -> (u64, u16) {
+    (marks.cursor_line(term), term.grid().cursor.point.column.0 as u16)
+}
+
+/// `value * to / from`, without overflow.
+fn scale(value: u32, to: u32, from: u32) -> u32 {
+    (value as u64 * to as u64 / from as u64).min(u32::MAX as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alacritty_terminal::event::VoidListener;
+    use alacritty_terminal::term::Config;
+    use alacritty_terminal::vte::ansi::{Processor, StdSyncHandler};
+    use base64::Engine;
+
+    fn term() -> Term<VoidListener> {
+        Term::new(Config::default(), &crate::TermSize { cols: 80, rows: 24 }, VoidListener)
+    }
+
+    /// Events in `chunks` fed one after the other, with the offset into
+    /// their chunk.
+    fn scan(chunks: &[&[u8]]) -> Vec<(usize, usize, ImageEvent)> {
+        let mut scanner = ImageScanner::new();
+        let mut events = Vec::new();
+        for (chunk, bytes) in chunks.iter().enumerate() {
+            scanner.feed(bytes, |at, event| events.push((chunk, at, event)));
+        }
+        events
+    }
+
+    fn base64(data: &[u8]) -> String {
+        base64::engine::general_purpose::STANDARD.encode(data)
+    }
+
+    #[test]
+    fn scanner_finds_images_across_chunks() {
+        let events = scan(&[b"ab\x1bP0;1q#1;2;100;0;0~~", b"-~\x1b", b"\\cd\x1b_Ga=d\x1b\\"]);
+        let [(2, 1, ImageEvent::Sixel(pixels)), (2, 11, ImageEvent::Graphics(data))] = &events[..] else {
+            panic!("unexpected events");
+        };
+        assert_eq!((pixels.width, pixels.height), (2, 12));
+        assert_eq!(data, b"a=d");
+    }
+
+    #[test]
+    fn scanner_events_and_aborted_strings() {
+        let events = scan(&[b"\x1b[2J\x1b[J\x1b[?2J\x1bc"]);
+        assert!(matches!(events[..], [(0, 4, ImageEvent::EraseDisplay), (0, 14, ImageEvent::Reset)]));
+        // Cancelled, interrupted by another sequence, or not of interest
+        let events = scan(&[b"\x1b_Ga=d\x18 \x1b_Ga=d\x1b[2J\x1bP1$qm\x1b\\\x1b_Hx\x1b\\"]);
+        assert!(matches!(events[..], [(0, 18, ImageEvent::EraseDisplay)]));
+    }
+
+    #[test]
+    fn chunked_transmission() {
+        let term = term();
+        let marks = CommandMarks::new();
+        let mut store = ImageStore::new();
+        let payload = base64(&[255, 0, 0, 0, 255, 0]);
+        let (first, rest) = payload.split_at(4);
+        let (second, third) = rest.split_at(4);
+        for chunk in [format!("a=T,f=24,s=2,v=1,i=9,m=1;{first}"), format!("m=1;{second}")] {
+            let effect = store.handle(&term, &marks, ImageEvent::Graphics(chunk.into_bytes()));
+            assert!(effect.reply.is_none() && effect.cursor.is_empty());
+        }
+        assert_eq!(store.visible(&term).count(), 0);
+        let effect = store.handle(&term, &marks, ImageEvent::Graphics(format!("m=0;{third}").into_bytes()));
+        assert_eq!(effect.reply.as_deref(), Some("\x1b_Gi=9;OK\x1b\\"));
+        assert_eq!(effect.cursor, b"\x1b[1C");
+        let placements: Vec<_> = store.visible(&term).collect();
+        assert_eq!(placements.len(), 1);
+        assert_eq!(&placements[0].image.rgba[..], [255, 0, 0, 255, 0, 255, 0, 255]);
+        assert_eq!(placements[0].source, [0, 0, 2, 1]);
+    }
+
+    #[test]
+    fn oversized_chunked_transmission() {
+        let term = term();
+        let marks = CommandMarks::new();
+        let mut store = ImageStore::new();
+        let mut first = b"a=t,i=4,s=1,v=1,m=1;".to_vec();
+        first.resize(first.len() + MAX_PAYLOAD_LEN, b'A');
+        assert!(store.handle(&term, &marks, ImageEvent::Graphics(first)).reply.is_none());
+        let effect = store.handle(&term, &marks, ImageEvent::Graphics(b"m=0;AAAA".to_vec()));
+        assert_eq!(effect.reply.as_deref(), Some("\x1b_Gi=4;EFBIG:image too large\x1b\\"));
+        // The next transmission starts over
+        let effect = store.handle(&term, &marks, ImageEvent::Graphics(b"i=5,s=1,v=1;AAAAAA==".to_vec()));
+        assert_eq!(effect.reply.as_deref(), Some("\x1b_Gi=5;OK\x1b\\"));
+    }
+
+    #[test]
+    fn transmission_errors_are_replied_to() {
+        let term = term();
+        let marks = CommandMarks::new();
+        let mut store = ImageStore::new();
+        let short = format!("a=T,i=3,f=32,s=2,v=2;{}", base64(&[0; 4]));
+        let effect = store.handle(&term, &marks, ImageEvent::Graphics(short.into_bytes()));
+        assert_eq!(effect.reply.as_deref(), Some("\x1b_Gi=3;ENODATA:insufficient image data\x1b\\"));
+        assert!(effect.cursor.is_empty());
+        let effect = store.handle(&term, &marks, ImageEvent::Graphics(b"a=p,i=3".to_vec()));
+        assert_eq!(effect.reply.as_deref(), Some("\x1b_Gi=3;ENOENT:no such image\x1b\\"));
+        assert_eq!(store.visible(&term).count(), 0);
+    }
+
+    #[test]
+    fn numbered_images_are_placed_and_deleted() {
+        let term = term();
+        let marks = CommandMarks::new();
+        let mut store = ImageStore::new();
+        let pixel = base64(&[1, 2, 3, 4]);
+        let effect = store.handle(&term, &marks, ImageEvent::Graphics(format!("I=7,s=1,v=1;{pixel}").into_bytes()));
+        assert_eq!(effect.reply.as_deref(), Some(format!("\x1b_Gi={FIRST_KITTY_ID},I=7;OK\x1b\\").as_str()));
+        let effect = store.handle(&term, &marks, ImageEvent::Graphics(b"a=p,I=7,q=1,C=1".to_vec()));
+        assert!(effect.reply.is_none() && effect.cursor.is_empty());
+        assert_eq!(store.visible(&term).count(), 1);
+        store.handle(&term, &marks, ImageEvent::Graphics(b"a=d,d=N,I=7".to_vec()));
+        assert_eq!(store.visible(&term).count(), 0);
+        let effect = store.handle(&term, &marks, ImageEvent::Graphics(b"a=p,I=7".to_vec()));
+        assert_eq!(effect.reply.as_deref(), Some("\x1b_Gi=0,I=7;ENOENT:no such image\x1b\\"));
+    }
+
+    #[test]
+    fn images_scroll_away_with_their_lines() {
+        let mut term = term();
+        let marks = CommandMarks::new();
+        let mut store = ImageStore::new();
+        let Some((_, _, sixel)) = scan(&[b"\x1bPq~~\x1b\\"]).pop() else { panic!("no sixel") };
+        let effect = store.handle(&term, &marks, sixel);
+        assert_eq!(effect.cursor, b"\x1bD");
+        let mut processor: Processor<StdSyncHandler> = Processor::new();
+        processor.advance(&mut term, &b"\n".repeat(30));
+        store.sync(&term, 0);
+        assert_eq!(store.visible(&term).count(), 1);
+        store.sync(&term, 1);
+        assert_eq!(store.visible(&term).count(), 0);
+    }
+}
@@ -0,0 +1,322 @@
+//! Kitty graphics protocol commands.
+//!
+//! A command arrives as `APC G <control data> ; <payload> ST`. The control
+//! data is a comma separated list of `key=value` pairs, each key a single
+//! letter; the payload is base64, split over several commands when `m=1`
+//! says more is coming. Only direct transmission (`t=d`) is supported:
+//! reading the files a command names would let any output, a `cat`ed file
+//! say, put the server's files on screen.
+//!
+//! Errors are reported the way kitty does, as `CODE:message` strings sent
+//! back to the program.
+
+use crate::images::{Pixels, MAX_PIXELS};
+use base64::Engine;
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+
+/// A graphics command; absent keys take kitty's defaults.
+pub struct Command {
+    /// `a`: t(ransmit), T (transmit and put), p(ut), d(elete), q(uery).
+    pub action: u8,
+    /// `q`: 1 suppresses OK replies, 2 errors too.
+    pub quiet: u32,
+    /// `f`: 24 (RGB), 32 (RGBA) or 100 (PNG).
+    pub format: u32,
+    /// `t`: transmission medium.
+    pub medium: u8,
+    /// `o`: z for zlib compressed data.
+    pub compression: Option<u8>,
+    /// `s`, `v`: size of RGB(A) data in pixels.
+    pub width: u32,
+    pub height: u32,
+    /// `m`: more chunks follow.
+    pub more: bool,
+    /// `i`, `I`, `p`: image id, image number and placement id; 0 if unset.
+    pub id: u32,
+    pub number: u32,
+    pub placement: u32,
+    /// `x`, `y`, `w`, `h`: part of the image to show, in pixels. Deletion
+    /// takes cells and ids from `x` and `y`.
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+    /// `X`, `Y`: offset into the first cell, in pixels.
+    pub offset_x: u32,
+    pub offset_y: u32,
+    /// `c`, `r`: cells to scale the image to.
+    pub columns: u32,
+    pub rows: u32,
+    /// `C=1`: leave the cursor where it is.
+    pub no_move: bool,
+    /// `z`: stacking order, negative under the text.
+    pub z: i32,
+    /// `d`: what to delete.
+    pub delete: u8,
+    /// Base64 payload, still encoded.
+    pub payload: Vec<u8>,
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Command {
+            action: b't',
+            quiet: 0,
+            format: 32,
+            medium: b'd',
+            compression: None,
+            width: 0,
+            height: 0,
+            more: false,
+            id: 0,
+            number: 0,
+            placement: 0,
+            x: 0,
+            y: 0,
+            w: 0,
+            h: 0,
+            offset_x: 0,
+            offset_y: 0,
+            columns: 0,
+            rows: 0,
+            no_move: false,
+            z: 0,
+            delete: b'a',
+            payload: Vec::new(),
+        }
+    }
+}
+
+/// Parse the body of an APC string after its `G`. None if it is malformed.
+pub fn parse(data: &[u8]) -> Option<Command> {
+    let (control, payload) = match data.iter().position(|&b| b == b';') {
+        Some(at) => (&data[..at], &data[at + 1..]),
+        None => (data, &[][..]),
+    };
+    let mut command = Command { payload: payload.to_vec(), ..Command::default() };
+    for pair in control.split(|&b| b == b',').filter(|pair| !pair.is_empty()) {
+        let [key, b'=', value @ ..] = pair else { return None };
+        let letter = || match value {
+            [letter] => Some(*letter),
+            _ => None,
+        };
+        let number = || std::str::from_utf8(value).ok()?.parse::<u32>().ok();
+        match key {
+            b'a' => command.action = letter()?,
+            b'q' => command.quiet = number()?,
+            b'f' => command.format = number()?,
+            b't' => command.medium = letter()?,
+            b'o' => command.compression = Some(letter()?),
+            b's' => command.width = number()?,
+            b'v' => command.height = number()?,
+            b'm' => command.more = number()? == 1,
+            b'i' => command.id = number()?,
+            b'I' => command.number = number()?,
+            b'p' => command.placement = number()?,
+            b'x' => command.x = number()?,
+            b'y' => command.y = number()?,
+            b'w' => command.w = number()?,
+            b'h' => command.h = number()?,
+            b'X' => command.offset_x = number()?,
+            b'Y' => command.offset_y = number()?,
+            b'c' => command.columns = number()?,
+            b'r' => command.rows = number()?,
+            b'C' => command.no_move = number()? == 1,
+            b'z' => command.z = std::str::from_utf8(value).ok()?.parse().ok()?,
+            b'd' => command.delete = letter()?,
+            // Animation, unicode placeholders, relative placements and the
+            // like are not supported
+            _ => {}
+        }
+    }
+    Some(command)
+}
+
+/// Decode the image a transmission carries.
+pub fn decode(command: &Command) -> Result<Pixels, &'static str> {
+    if command.medium != b'd' {
+        return Err("EINVAL:only direct transmission is supported");
+    }
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(command.payload.trim_ascii_end())
+        .or_else(|_| base64::engine::general_purpose::STANDARD_NO_PAD.decode(command.payload.trim_ascii_end()))
+        .map_err(|_| "EINVAL:payload is not valid base64")?;
+    let data = match command.compression {
+        None => data,
+        Some(b'z') => {
+            let mut inflated = Vec::new();
+            ZlibDecoder::new(&data[..])
+                .take(MAX_PIXELS as u64 * 4 + 1)
+                .read_to_end(&mut inflated)
+                .map_err(|_| "EINVAL:payload is not valid zlib data")?;
+            inflated
+        }
+        Some(_) => return Err("EINVAL:unknown compression"),
+    };
+    match command.format {
+        100 => crate::png::decode(&data).ok_or("EBADPNG:cannot decode PNG"),
+        24 | 32 => {
+            let (width, height) = (command.width as usize, command.height as usize);
+            if width == 0 || height == 0 {
+                return Err("EINVAL:image size missing");
+            }
+            if width * height > MAX_PIXELS {
+                return Err("EFBIG:image too large");
+            }
+            let channels = command.format as usize / 8;
+            let data = data.get(..width * height * channels).ok_or("ENODATA:insufficient image data")?;
+            let rgba = if channels == 4 {
+                data.to_vec()
+            } else {
+                data.chunks_exact(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255]).collect()
+            };
+            Ok(Pixels { width, height, rgba })
+        }
+        _ => Err("EINVAL:unknown format"),
+    }
+}
+
+/// The reply to a command, or None if it wants none: commands that name
+/// no image get no reply, and `q` silences OK and then errors.
+pub fn reply(command: &Command, result: Result<(), &str>) -> Option<String> {
+    if command.id == 0 && command.number == 0 {
+        return None;
+    }
+    let message = match result {
+        Ok(()) if command.quiet == 0 => "OK",
+        Err(error) if command.quiet < 2 => error,
+        _ => return None,
+    };
+    let mut keys = format!("i={}", command.id);
+    if command.number != 0 {
+        keys += &format!(",I={}", command.number);
+    }
+    if command.placement != 0 {
+        keys += &format!(",p={}", command.placement);
+    }
+    Some(format!("\x1b_G{keys};{message}\x1b\\"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    /// A command with `control` data and `data` as its payload.
+    fn command(control: &str, data: &[u8]) -> Command {
+        let payload = base64::engine::general_purpose::STANDARD.encode(data);
+        parse(format!("{control};{payload}").as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn parse_control_data() {
+        let command = parse(b"a=T,f=100,i=7,I=3,p=2,m=1,C=1,z=-5,c=4,r=2,X=3,Y=1;AAAA").unwrap();
+        assert_eq!(command.action, b'T');
+        assert_eq!(command.format, 100);
+        assert_eq!((command.id, command.number, command.placement), (7, 3, 2));
+        assert!(command.more && command.no_move);
+        assert_eq!(command.z, -5);
+        assert_eq!((command.columns, command.rows, command.offset_x, command.offset_y), (4, 2, 3, 1));
+        assert_eq!(command.payload, b"AAAA");
+
+        let defaults = parse(b"").unwrap();
+        assert_eq!((defaults.action, defaults.format, defaults.medium), (b't', 32, b'd'));
+        assert!(!defaults.more && defaults.payload.is_empty());
+
+        assert!(parse(b"a").is_none());
+        assert!(parse(b"i=x").is_none());
+        assert!(parse(b"a=tt").is_none());
+    }
+
+    #[test]
+    fn rgb_and_rgba() {
+        let pixels = decode(&command("f=24,s=2,v=1", &[1, 2, 3, 4, 5, 6])).unwrap();
+        assert_eq!((pixels.width, pixels.height), (2, 1));
+        assert_eq!(pixels.rgba, [1, 2, 3, 255, 4, 5, 6, 255]);
+
+        let pixels = decode(&command("f=32,s=1,v=2", &[1, 2, 3, 4, 5, 6, 7, 8])).unwrap();
+        assert_eq!((pixels.width, pixels.height), (1, 2));
+        assert_eq!(pixels.rgba, [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn png() {
+        // 1x1 RGBA
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut chunk = |kind: &[u8], data: &[u8]| {
+            png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            png.extend_from_slice(kind);
+            png.extend_from_slice(data);
+            png.extend_from_slice(&[0; 4]);
+        };
+        chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 0]);
+        chunk(b"IDAT", &zlib(&[0, 9, 8, 7, 6]));
+        chunk(b"IEND", &[]);
+        let pixels = decode(&command("f=100", &png)).unwrap();
+        assert_eq!((pixels.width, pixels.height), (1, 1));
+        assert_eq!(pixels.rgba, [9, 8, 7, 6]);
+
+        assert_eq!(decode(&command("f=100", b"not a png")).err(), Some("EBADPNG:cannot decode PNG"));
+    }
+
+    #[test]
+    fn zlib_compressed() {
+        let compressed = zlib(&[10, 20, 30, 40, 50, 60]);
+        let pixels = decode(&command("f=24,o=z,s=2,v=1", &compressed)).unwrap();
+        assert_eq!(pixels.rgba, [10, 20, 30, 255, 40, 50, 60, 255]);
+
+        let error = decode(&command("f=24,o=z,s=2,v=1", &[10, 20, 30, 40, 50, 60])).err();
+        assert_eq!(error, Some("EINVAL:payload is not valid zlib data"));
+        assert_eq!(decode(&command("o=x,s=1,v=1", &[0; 4])).err(), Some("EINVAL:unknown compression"));
+    }
+
+    #[test]
+    fn unpadded_base64() {
+        let command = parse(b"f=24,s=1,v=1;AQID").unwrap();
+        assert_eq!(decode(&command).unwrap().rgba, [1, 2, 3, 255]);
+        let command = parse(b"f=32,s=1,v=1;AQIDBA").unwrap();
+        assert_eq!(decode(&command).unwrap().rgba, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn errors() {
+        let short = command("f=32,s=2,v=2", &[0; 15]);
+        assert_eq!(decode(&short).err(), Some("ENODATA:insufficient image data"));
+        let large = command("f=32,s=4097,v=4096", &[]);
+        assert_eq!(decode(&large).err(), Some("EFBIG:image too large"));
+        assert_eq!(decode(&command("f=32,s=1", &[0; 4])).err(), Some("EINVAL:image size missing"));
+        assert_eq!(decode(&command("f=8,s=1,v=1", &[0; 4])).err(), Some("EINVAL:unknown format"));
+        let file = command("t=f,s=1,v=1", b"/etc/passwd");
+        assert_eq!(decode(&file).err(), Some("EINVAL:only direct transmission is supported"));
+        assert_eq!(
+            decode(&parse(b"s=1,v=1;!!!!").unwrap()).err(),
+            Some("EINVAL:payload is not valid base64")
+        );
+    }
+
+    #[test]
+    fn replies() {
+        let reply = |control: &[u8], result| reply(&parse(control).unwrap(), result);
+        assert_eq!(reply(b"i=5", Ok(())).as_deref(), Some("\x1b_Gi=5;OK\x1b\\"));
+        assert_eq!(
+            reply(b"i=5,I=2,p=3", Err("ENODATA:insufficient image data")).as_deref(),
+            Some("\x1b_Gi=5,I=2,p=3;ENODATA:insufficient image data\x1b\\")
+        );
+        assert_eq!(
+            reply(b"i=5,q=1", Err("EFBIG:image too large")).as_deref(),
+            Some("\x1b_Gi=5;EFBIG:image too large\x1b\\")
+        );
+        assert_eq!(reply(b"i=5,q=1", Ok(())), None);
+        assert_eq!(reply(b"i=5,q=2", Err("EFBIG:image too large")), None);
+        assert_eq!(reply(b"a=T", Err("EFBIG:image too large")), None);
+    }
+}
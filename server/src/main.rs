@@ -1,6 +1,18 @@
 //! NoirTTY Web Server - WebSocket Terminal Server
 
 mod auth;
+mod codec;
+mod compression;
+mod images;
+mod kitty;
+mod marks;
+mod modes;
+mod osc;
+mod palette;
+mod png;
+mod quality;
+mod sixel;
+mod webtransport;
 
 use axum::{
     extract::{
@@ -16,20 +28,25 @@ use axum::{
 };
 use alacritty_terminal::{
     event::{Event, EventListener},
-    grid::{Dimensions, Scroll},
-    term::{cell::Flags as TermFlags, Term, Config as TermConfig},
+    grid::{Dimensions, GridCell, Scroll},
+    index::Column,
+    term::{cell::{Flags as TermFlags, Hyperlink}, Term, TermMode, Config as TermConfig, Osc52},
 };
-use alacritty_terminal::vte::ansi::{Color, NamedColor, CursorShape, Processor, StdSyncHandler};
+use alacritty_terminal::vte::ansi::{CursorShape, NamedColor, Processor, Rgb, StdSyncHandler};
+use base64::Engine;
 use rcgen::{generate_simple_self_signed, CertifiedKey};
 use futures::{SinkExt, StreamExt};
-use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use portable_pty::{native_pty_system, CommandBuilder, MasterPty, PtySize};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeSet;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+use std::rc::Rc;
 use std::io::{Read, Write};
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::net::SocketAddr;
-use tokio::sync::{mpsc, broadcast};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 use tower_http::{cors::CorsLayer, services::ServeDir};
 use tower_http::set_header::SetResponseHeaderLayer;
 use axum::http::{header, HeaderValue};
@@ -39,48 +56,150 @@ use uuid::Uuid;
 use dashmap::DashMap;
 use rustls::crypto::ring;
 use gethostname::gethostname;
-use bincode;
-use std::sync::atomic::{AtomicU64, Ordering};
 use include_dir::{include_dir, Dir};
 use axum::body::Body;
 use mime_guess::from_path;
 
 static EMBEDDED_STATIC: Dir = include_dir!("$CARGO_MANIFEST_DIR/../static");
 
-#[derive(Debug, Deserialize)]
+/// Client message, as JSON text or in the binary encoding of `codec`.
+#[derive(Debug, PartialEq, Deserialize)]
 #[serde(tag = "type")]
 enum ClientMessage {
+    /// Terminal input. JSON carries it as a string, the binary encoding as
+    /// raw bytes.
     #[serde(rename = "data")]
-    Data { data: String },
+    Data {
+        #[serde(deserialize_with = "string_bytes")]
+        data: Vec<u8>,
+    },
+    /// New grid size, with the client's cell size in pixels if known.
     #[serde(rename = "resize")]
-    Resize { cols: u16, rows: u16 },
+    Resize {
+        cols: u16,
+        rows: u16,
+        #[serde(default)]
+        cell_width: Option<u16>,
+        #[serde(default)]
+        cell_height: Option<u16>,
+    },
     #[serde(rename = "scroll")]
     Scroll { delta: i32 },
+    /// Frame interval floor and, when compression was negotiated, whether
+    /// deflate is currently worth it.
     #[serde(rename = "quality")]
-    Quality { min_interval_ms: u32 },
+    Quality {
+        min_interval_ms: u32,
+        #[serde(default)]
+        compress: Option<bool>,
+    },
+    /// Grant additional frame credits (0 = disable flow control).
+    #[serde(rename = "credit")]
+    Credit { frames: u32 },
+    /// Acknowledge rendered frames, returning one credit each.
+    #[serde(rename = "ack")]
+    Ack {
+        #[serde(default = "one_frame")]
+        count: u32,
+    },
+    /// Heartbeat carrying the client's clock (ms), echoed back in a pong.
+    #[serde(rename = "ping")]
+    Ping { ts: f64 },
+    /// Reply to a server ping, echoing its timestamp.
+    #[serde(rename = "pong")]
+    Pong { ts: u64 },
+    /// Reply to a clipboard request; None when the user refused.
+    #[serde(rename = "clipboard")]
+    Clipboard { text: Option<String> },
+    /// Scroll to the shell prompt `delta` prompts away (negative = earlier).
+    /// Clients that scroll their own history pass the line at the top of
+    /// their view and get the target back in `ServerMessage::ScrollTo`.
+    #[serde(rename = "jump_prompt")]
+    JumpPrompt {
+        delta: i32,
+        #[serde(default)]
+        from_line: Option<u64>,
+    },
+    /// Copy the output of the command on a viewport row, or on absolute
+    /// `line` when given; answered with `ServerMessage::Clipboard`.
+    #[serde(rename = "copy_output")]
+    CopyOutput {
+        row: u16,
+        #[serde(default)]
+        line: Option<u64>,
+    },
+    /// Ask for scrollback lines `from_line..from_line + count`; answered
+    /// with `ServerMessage::History`.
+    #[serde(rename = "fetch_history")]
+    FetchHistory { from_line: u64, count: u16 },
+}
+
+fn string_bytes<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    String::deserialize(deserializer).map(String::into_bytes)
+}
+
+/// JSON acks predate the count and cover a single frame.
+fn one_frame() -> u32 {
+    1
 }
 
+/// Fields are never skipped: bincode frames are positional and must match
+/// the client's `Cell` field for field.
 #[derive(Clone, Debug, Serialize)]
 struct ServerCell {
     c: char,
+    /// Zero-width characters (combining marks, ZWJ sequences, variation
+    /// selectors) following `c` in the same grapheme cluster.
+    combining: String,
+    /// First cell of a double-width character.
+    wide: bool,
+    /// Cell covered by the wide character to its left.
+    spacer: bool,
     fg: [u8; 3],
     bg: [u8; 3],
     bold: bool,
     italic: bool,
-    underline: bool,
+    dim: bool,
+    underline: ServerUnderline,
+    /// SGR 58 color; None when the underline follows the foreground.
+    underline_color: Option<[u8; 3]>,
+    strikeout: bool,
+    hidden: bool,
     inverse: bool,
+    /// OSC 8 hyperlink, as an index into the frame's `links`.
+    link: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ServerUnderline {
+    #[default]
+    None,
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
 }
 
 impl Default for ServerCell {
     fn default() -> Self {
         Self {
             c: ' ',
-            fg: DEFAULT_FG,
-            bg: DEFAULT_BG,
+            combining: String::new(),
+            wide: false,
+            spacer: false,
+            fg: palette::DEFAULT_FG,
+            bg: palette::DEFAULT_BG,
             bold: false,
             italic: false,
-            underline: false,
+            dim: false,
+            underline: ServerUnderline::None,
+            underline_color: None,
+            strikeout: false,
+            hidden: false,
             inverse: false,
+            link: None,
         }
     }
 }
@@ -92,37 +211,337 @@ struct ServerFrame {
     cursor_col: u16,
     cursor_row: u16,
     cursor_visible: bool,
+    /// DECSCUSR cursor style.
+    cursor_shape: ServerCursorShape,
+    cursor_blink: bool,
+    modes: ServerModes,
+    /// Default background and cursor colors, including changes the program
+    /// made with OSC 11/12.
+    background: [u8; 3],
+    cursor_color: [u8; 3],
+    /// Absolute line number of the first row, counted from the first line
+    /// the session ever printed.
+    top_line: u64,
+    /// First line still in the scrollback; earlier lines were dropped.
+    history_start: u64,
+    /// URIs of the OSC 8 hyperlinks on screen, one entry per distinct link
+    /// (id and URI), referenced by `ServerCell::link`.
+    links: Vec<String>,
+    /// Shell commands (OSC 133) overlapping the viewport.
+    commands: Vec<ServerCommandMark>,
+    /// Images on this screen, including those scrolled into the scrollback.
+    images: Vec<ServerImage>,
     cells: Vec<ServerCell>,
 }
 
+/// An inline image anchored to the grid. Its pixels are sent once per
+/// connection, as `ServerMessage::Image`, before the first frame showing it.
+#[derive(Clone, Debug, Serialize)]
+struct ServerImage {
+    id: u32,
+    /// Absolute line of the image's top row, as `ServerFrame::top_line`.
+    line: u64,
+    column: u16,
+    /// Offset of the top left corner into that cell, in pixels.
+    offset: (u32, u32),
+    /// Part of the image drawn: x, y, width and height in image pixels.
+    source: [u32; 4],
+    /// Size drawn, in pixels.
+    width: u32,
+    height: u32,
+    /// Negative under the text, otherwise over it.
+    z: i32,
+    #[serde(skip)]
+    image: images::Image,
+}
+
+/// A shell command marked by OSC 133. Rows are relative to the top of the
+/// viewport and may lie outside it.
+#[derive(Clone, Debug, Serialize)]
+struct ServerCommandMark {
+    prompt: i32,
+    /// Output rows, `output_start..output_end`.
+    output_start: i32,
+    output_end: i32,
+    finished: bool,
+    /// Exit code, if the shell reported one.
+    exit: Option<i32>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ServerCursorShape {
+    Block,
+    Underline,
+    Beam,
+}
+
+/// Terminal modes the client needs to encode input the way the running
+/// program expects.
+#[derive(Clone, Copy, Debug, Serialize)]
+struct ServerModes {
+    /// DECCKM: cursor keys send `ESC O x`.
+    app_cursor: bool,
+    /// DECKPAM: keypad keys send `ESC O x`.
+    app_keypad: bool,
+    /// 2004: wrap pastes in `ESC [200~` / `ESC [201~`.
+    bracketed_paste: bool,
+    /// 1004: report focus changes.
+    focus_reporting: bool,
+    /// 1049/47: the alternate screen is active.
+    alt_screen: bool,
+    /// 1000/1002/1003: mouse button, drag and motion reporting.
+    mouse_click: bool,
+    mouse_drag: bool,
+    mouse_motion: bool,
+    /// 1006/1005: SGR and UTF-8 mouse coordinate encodings.
+    sgr_mouse: bool,
+    utf8_mouse: bool,
+    /// 9: X10 tracking, button presses only. Not tracked by alacritty; see
+    /// `modes::ModeScanner`.
+    x10_mouse: bool,
+    /// 1015: urxvt coordinate encoding, likewise.
+    urxvt_mouse: bool,
+    /// 1007: the wheel sends arrow keys on the alternate screen.
+    alternate_scroll: bool,
+}
+
+impl From<TermMode> for ServerModes {
+    fn from(mode: TermMode) -> Self {
+        ServerModes {
+            app_cursor: mode.contains(TermMode::APP_CURSOR),
+            app_keypad: mode.contains(TermMode::APP_KEYPAD),
+            bracketed_paste: mode.contains(TermMode::BRACKETED_PASTE),
+            focus_reporting: mode.contains(TermMode::FOCUS_IN_OUT),
+            alt_screen: mode.contains(TermMode::ALT_SCREEN),
+            mouse_click: mode.contains(TermMode::MOUSE_REPORT_CLICK),
+            mouse_drag: mode.contains(TermMode::MOUSE_DRAG),
+            mouse_motion: mode.contains(TermMode::MOUSE_MOTION),
+            sgr_mouse: mode.contains(TermMode::SGR_MOUSE),
+            utf8_mouse: mode.contains(TermMode::UTF8_MOUSE),
+            x10_mouse: false,
+            urxvt_mouse: false,
+            alternate_scroll: mode.contains(TermMode::ALTERNATE_SCROLL),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize)]
 #[serde(tag = "type")]
 enum ServerMessage {
     #[serde(rename = "frame")]
     Frame(ServerFrame),
+    /// Heartbeat carrying the server's clock (ms since the Unix epoch).
+    #[serde(rename = "ping")]
+    Ping { ts: u64 },
+    /// Reply to a client ping, echoing its timestamp.
+    #[serde(rename = "pong")]
+    Pong { ts: f64 },
+    /// Window title; None when the program reset it.
+    #[serde(rename = "title")]
+    Title { title: Option<String> },
+    /// BEL from the running program.
+    #[serde(rename = "bell")]
+    Bell,
+    /// Text the running program copied (OSC 52).
+    #[serde(rename = "clipboard")]
+    Clipboard { text: String },
+    /// The running program asked to read the clipboard (OSC 52); answered
+    /// with `ClientMessage::Clipboard`.
+    #[serde(rename = "clipboard_request")]
+    ClipboardRequest,
+    /// Desktop notification from the running program (OSC 9 / OSC 777).
+    #[serde(rename = "notify")]
+    Notify { title: Option<String>, body: String },
+    /// Scrollback lines asked for with `ClientMessage::FetchHistory`.
+    #[serde(rename = "history")]
+    History(HistoryPage),
+    /// Line of the prompt to scroll to, answering a `ClientMessage::JumpPrompt`
+    /// that carried the client's view.
+    #[serde(rename = "scroll_to")]
+    ScrollTo { line: u64 },
+    /// Raw stream: the screen to start from, on attach and after falling
+    /// behind. `respond` tells the client whether to answer status reports
+    /// (DSR) itself; `palette` is what it resolves colors in the output
+    /// with.
+    #[serde(rename = "snapshot")]
+    Snapshot { respond: bool, palette: RawPalette, frame: ServerFrame },
+    /// Raw stream: PTY output following the last snapshot.
+    #[serde(rename = "output")]
+    Output {
+        #[serde(serialize_with = "latin1_string")]
+        data: Vec<u8>,
+    },
+    /// Pixels of an image that frames refer to by id, RGBA row by row.
+    #[serde(rename = "image")]
+    Image {
+        id: u32,
+        width: u32,
+        height: u32,
+        #[serde(serialize_with = "base64_bytes")]
+        data: Arc<[u8]>,
+    },
+}
+
+/// Session colors for a raw stream, including changes the program made
+/// with OSC 4/10.
+#[derive(Clone, Debug, Serialize)]
+struct RawPalette {
+    foreground: [u8; 3],
+    /// Colors 0-255.
+    indexed: Vec<[u8; 3]>,
+}
+
+/// Bytes as a string of U+0000-U+00FF in JSON, one char per byte, which
+/// it can carry whatever the bytes are; bincode takes them as they are.
+fn latin1_string<S: serde::Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        let text: String = bytes.iter().map(|&b| b as char).collect();
+        serializer.serialize_str(&text)
+    } else {
+        serializer.serialize_bytes(bytes)
+    }
+}
+
+/// Bytes as base64 in JSON; bincode takes them as they are.
+fn base64_bytes<S: serde::Serializer>(bytes: &Arc<[u8]>, serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(bytes))
+    } else {
+        serializer.serialize_bytes(bytes)
+    }
+}
+
+/// Scrollback lines `from_line..from_line + lines.len()`, with trailing
+/// blank cells dropped.
+#[derive(Clone, Debug, Serialize)]
+struct HistoryPage {
+    from_line: u64,
+    lines: Vec<Vec<ServerCell>>,
+    /// Hyperlinks referenced by the cells, as in `ServerFrame::links`.
+    links: Vec<String>,
+    /// Commands overlapping the page, rows relative to `from_line`.
+    commands: Vec<ServerCommandMark>,
 }
 
+/// Bells closer together than this are merged.
+const BELL_MIN_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+/// Notifications buffered for a connection that is slow to send them.
+const NOTIFY_BACKLOG: usize = 16;
+/// Most scrollback lines sent in one `ServerMessage::History`.
+const MAX_HISTORY_PAGE: u16 = 500;
+/// PTY output chunks buffered for a raw-stream client; one that falls
+/// further behind gets a new snapshot.
+const RAW_BACKLOG: usize = 1024;
+/// Server heartbeat period.
+const HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+/// Drop a connection that has sent nothing (not even a pong) for this long.
+const PEER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(20);
+
 #[derive(Clone)]
 struct AppState {
     sessions: Arc<DashMap<String, Arc<Session>>>,
     auth: auth::AuthState,
     config_path: Arc<std::path::PathBuf>,
     debug_ui: bool,
+    /// QUIC listener, when HTTPS is on and WebTransport is enabled.
+    webtransport: Option<Arc<webtransport::Listener>>,
 }
 
 #[derive(Clone)]
 struct Session {
     id: String,
     pty_tx: mpsc::Sender<PtyCommand>,
-    frame_tx: broadcast::Sender<ServerMessage>,
-    last_frame: Arc<Mutex<Option<ServerMessage>>>,
-    min_interval_ms: Arc<AtomicU64>,
+    /// Latest rendered frame. Senders coalesce to whatever is current here.
+    frame_tx: watch::Sender<Option<ServerMessage>>,
+    /// Window title set by the running program (OSC 0/2), None once reset.
+    title_tx: watch::Sender<Option<String>>,
+    /// Bells rung so far; clients ring once per change.
+    bell_tx: watch::Sender<u64>,
+    /// Latest text copied by the running program.
+    clipboard_tx: watch::Sender<Option<String>>,
+    /// Pending clipboard read, formatting the reply for the PTY.
+    paste_tx: watch::Sender<Option<ClipboardFormatter>>,
+    /// Desktop notifications raised by the running program. Unlike the
+    /// state above these are events, so none may be coalesced away.
+    notify_tx: broadcast::Sender<osc::Notification>,
+    /// Working directory last reported by the shell (OSC 7).
+    cwd_tx: watch::Sender<Option<PathBuf>>,
+    /// A raw-stream client answers status reports, so the server does not.
+    responder_tx: watch::Sender<bool>,
+}
+
+impl Session {
+    /// Current working directory: the shell's last OSC 7 report, else that
+    /// of the foreground process.
+    async fn cwd(&self) -> Option<PathBuf> {
+        if let Some(cwd) = self.cwd_tx.borrow().clone() {
+            return Some(cwd);
+        }
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.pty_tx.send(PtyCommand::Cwd(reply_tx)).await.ok()?;
+        reply_rx.await.ok().flatten()
+    }
 }
 
+/// Turns clipboard text into the OSC 52 reply the program asked for.
+type ClipboardFormatter = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Turns a color into the reply to an OSC 4/10/11/12 query.
+type ColorFormatter = Arc<dyn Fn(Rgb) -> String + Send + Sync>;
+
 #[derive(Debug, Deserialize)]
 struct SessionQuery {
     session: Option<String>,
     format: Option<String>,
+    /// Initial frame credit window (absent or 0 = no flow control).
+    credits: Option<u32>,
+    /// Frame compression requested by the client ("deflate").
+    compress: Option<String>,
+    /// One-time WebTransport ticket from `/api/webtransport`.
+    ticket: Option<String>,
+    /// Session whose working directory a new session starts in.
+    cwd_from: Option<String>,
+    /// "raw" for PTY output instead of rendered frames.
+    stream: Option<String>,
+}
+
+/// How a connection's stream is encoded.
+#[derive(Clone, Copy, Debug)]
+struct StreamOptions {
+    use_binary: bool,
+    compress: bool,
+    /// PTY output instead of rendered frames.
+    raw: bool,
+}
+
+impl SessionQuery {
+    fn stream_options(&self) -> StreamOptions {
+        StreamOptions {
+            use_binary: self.use_binary(),
+            compress: self.compress(),
+            raw: self.raw(),
+        }
+    }
+
+    fn use_binary(&self) -> bool {
+        matches!(
+            self.format.as_deref(),
+            Some("bincode") | Some("bin") | Some("binary")
+        )
+    }
+
+    fn credits(&self) -> Option<u32> {
+        self.credits.filter(|c| *c > 0)
+    }
+
+    fn compress(&self) -> bool {
+        matches!(self.compress.as_deref(), Some("deflate"))
+    }
+
+    fn raw(&self) -> bool {
+        matches!(self.stream.as_deref(), Some("raw"))
+    }
 }
 
 #[tokio::main]
@@ -174,13 +593,42 @@ async fn main() {
         .map(|v| v != "0")
         .unwrap_or(false);
 
+    let addr: SocketAddr = "0.0.0.0:3000".parse().unwrap();
+    if use_https && ring::default_provider().install_default().is_err() {
+        error!("Failed to install rustls ring crypto provider");
+    }
+
+    // WebTransport needs TLS; it listens on the same port over UDP.
+    let webtransport_enabled = std::env::var("NOIRTTY_WEBTRANSPORT")
+        .map(|v| v != "0")
+        .unwrap_or(true);
+    let webtransport = if use_https && webtransport_enabled {
+        match webtransport::Listener::bind(addr, cert_hosts.clone()) {
+            Ok(listener) => {
+                info!("WebTransport listening on udp/{}", listener.port());
+                Some(listener)
+            }
+            Err(e) => {
+                warn!("WebTransport disabled: {:#}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
     let state = AppState {
         sessions: Arc::new(DashMap::new()),
         auth: auth.clone(),
         config_path: Arc::new(static_dir.join("config.json")),
         debug_ui,
+        webtransport,
     };
 
+    if let Some(listener) = state.webtransport.clone() {
+        tokio::spawn(listener.run(state.clone()));
+    }
+
     let static_service = ServiceBuilder::new()
         .layer(SetResponseHeaderLayer::if_not_present(
             header::CACHE_CONTROL,
@@ -206,6 +654,7 @@ async fn main() {
         // Protected routes (auth checked in handler)
         .route("/", get(index_handler))
         .route("/ws", get(ws_handler_with_auth))
+        .route("/api/webtransport", get(webtransport_handler))
         .route("/health", get(|| async { "OK" }))
         .route("/config.json", get(config_handler));
 
@@ -217,11 +666,7 @@ async fn main() {
 
     let app = app.with_state(state.clone()).layer(CorsLayer::permissive());
 
-    let addr: SocketAddr = "0.0.0.0:3000".parse().unwrap();
     if use_https {
-        if ring::default_provider().install_default().is_err() {
-            error!("Failed to install rustls ring crypto provider");
-        }
         let (cert_path, key_path) = ensure_self_signed_cert(&data_dir, &cert_hosts)
             .expect("Failed to generate self-signed certificate");
         info!("TLS certificate: {:?}", cert_path);
@@ -280,14 +725,24 @@ async fn index_handler(
     }
 }
 
-async fn config_handler(State(state): State<AppState>) -> impl IntoResponse {
-    let raw = std::fs::read_to_string(&*state.config_path).unwrap_or_else(|_| {
+/// Raw config.json: the file next to the static assets, else the embedded
+/// copy.
+fn read_config_raw(path: &Path) -> String {
+    std::fs::read_to_string(path).unwrap_or_else(|_| {
         EMBEDDED_STATIC
             .get_file("config.json")
             .and_then(|f| std::str::from_utf8(f.contents()).ok())
             .unwrap_or("{}")
             .to_string()
-    });
+    })
+}
+
+fn read_config(path: &Path) -> serde_json::Value {
+    serde_json::from_str(&read_config_raw(path)).unwrap_or_else(|_| serde_json::json!({}))
+}
+
+async fn config_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let raw = read_config_raw(&state.config_path);
     let mut value: serde_json::Value = serde_json::from_str(&raw).unwrap_or_else(|_| {
         serde_json::json!({})
     });
@@ -392,40 +847,338 @@ async fn ws_handler_with_auth(
         return (axum::http::StatusCode::UNAUTHORIZED, "Authentication required").into_response();
     }
 
+    let options = query.stream_options();
+    let credits = query.credits();
     let session_id = query.session.unwrap_or_else(|| Uuid::new_v4().to_string());
-    let use_binary = matches!(
-        query.format.as_deref(),
-        Some("bincode") | Some("bin") | Some("binary")
-    );
-    let session = get_or_create_session(&state, &session_id);
-    ws.on_upgrade(move |socket| handle_socket(socket, session, use_binary))
+    let session = get_or_create_session(&state, &session_id, query.cwd_from.as_deref()).await;
+    ws.on_upgrade(move |socket| handle_socket(socket, session, options, credits))
+}
+
+/// WebTransport connect details: UDP port, certificate hash and a one-time ticket
+async fn webtransport_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Response {
+    if !auth::check_auth_from_headers(&state.auth, &headers).await {
+        return (StatusCode::UNAUTHORIZED, "Authentication required").into_response();
+    }
+    let Some(listener) = state.webtransport.as_ref() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let cert_hash: String = listener
+        .cert_hash()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let body = serde_json::json!({
+        "port": listener.port(),
+        "certHash": cert_hash,
+        "ticket": state.auth.issue_ticket().await,
+    });
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        body.to_string(),
+    )
+        .into_response()
 }
 
 enum PtyCommand {
     Data(Vec<u8>),
-    Resize(u16, u16),
+    /// Columns, rows and the cell size in pixels.
+    Resize(u16, u16, Option<(u16, u16)>),
     Scroll(i32),
+    /// Ask for the foreground process's working directory.
+    Cwd(oneshot::Sender<Option<PathBuf>>),
+    JumpPrompt(i32, Option<u64>, oneshot::Sender<Option<u64>>),
+    CopyOutput(u16, Option<u64>, oneshot::Sender<Option<String>>),
+    FetchHistory(u64, u16, oneshot::Sender<HistoryPage>),
+    AttachRaw(oneshot::Sender<RawAttach>),
 }
 
+/// Start of a raw stream: the current screen, its colors and the PTY
+/// output after it.
+type RawAttach = (ServerFrame, RawPalette, broadcast::Receiver<Arc<[u8]>>);
+
 enum TermCommand {
     Data(Vec<u8>),
-    Resize(u16, u16),
+    Resize(u16, u16, Option<(u16, u16)>),
     Scroll(i32),
+    /// Prompt jump; with the client's view top the target is replied
+    /// instead of scrolling the display.
+    JumpPrompt(i32, Option<u64>, oneshot::Sender<Option<u64>>),
+    CopyOutput(u16, Option<u64>, oneshot::Sender<Option<String>>),
+    FetchHistory(u64, u16, oneshot::Sender<HistoryPage>),
+    AttachRaw(oneshot::Sender<RawAttach>),
 }
 
+/// The terminal plus the side channels fed from the same PTY output.
+struct Emulator {
+    term: Term<TermEventProxy>,
+    processor: Processor<StdSyncHandler>,
+    osc_scanner: osc::OscScanner,
+    mode_scanner: modes::ModeScanner,
+    image_scanner: images::ImageScanner,
+    events: TermEvents,
+    marks: marks::CommandMarks,
+    images: images::ImageStore,
+    palette: palette::Palette,
+    /// Color queries the terminal raised, answered once it has finished
+    /// the sequence so the replies keep their order with other responses.
+    color_requests: Rc<RefCell<Vec<(usize, ColorFormatter)>>>,
+    pty_tx: mpsc::Sender<PtyCommand>,
+    /// PTY output for raw-stream clients, sent once the terminal has seen it.
+    raw_tx: broadcast::Sender<Arc<[u8]>>,
+}
+
+impl Emulator {
+    /// An 80x24 terminal; replies to the program go to `pty_tx`.
+    fn new(events: TermEvents, pty_tx: mpsc::Sender<PtyCommand>, palette: palette::Palette) -> Self {
+        let color_requests = Rc::new(RefCell::new(Vec::new()));
+        let proxy = TermEventProxy {
+            pty_tx: pty_tx.clone(),
+            events: events.clone(),
+            color_requests: color_requests.clone(),
+            last_bell: std::cell::Cell::new(None),
+        };
+        // Clipboard reads are gated by the client, which asks the user.
+        // Scrollback is trimmed by `CommandMarks`, see there.
+        let config = TermConfig {
+            osc52: Osc52::CopyPaste,
+            scrolling_history: marks::HISTORY_LINES + marks::HISTORY_SLACK,
+            ..TermConfig::default()
+        };
+        let size = TermSize { cols: 80, rows: 24 };
+        Emulator {
+            term: Term::new(config, &size, proxy),
+            processor: Processor::new(),
+            osc_scanner: osc::OscScanner::new(),
+            mode_scanner: modes::ModeScanner::new(),
+            image_scanner: images::ImageScanner::new(),
+            events,
+            marks: marks::CommandMarks::new(),
+            images: images::ImageStore::new(),
+            palette,
+            color_requests,
+            pty_tx,
+            raw_tx: broadcast::channel(RAW_BACKLOG).0,
+        }
+    }
+
+    fn apply(&mut self, cmd: TermCommand) {
+        match cmd {
+            TermCommand::Data(data) => {
+                self.advance(&data);
+                if self.raw_tx.receiver_count() > 0 {
+                    let _ = self.raw_tx.send(data.into());
+                }
+            }
+            TermCommand::Resize(cols, rows, cell) => {
+                if let Some((width, height)) = cell {
+                    self.images.set_cell_size(width, height);
+                }
+                self.term.resize(TermSize {
+                    cols: cols as usize,
+                    rows: rows as usize,
+                });
+                self.marks.resized(&self.term);
+                // Raw clients do not rewrap; closing their stream makes them
+                // start over from a snapshot
+                if self.raw_tx.receiver_count() > 0 {
+                    self.raw_tx = broadcast::channel(RAW_BACKLOG).0;
+                }
+            }
+            TermCommand::Scroll(delta) => {
+                self.term.scroll_display(Scroll::Delta(delta));
+            }
+            TermCommand::JumpPrompt(delta, Some(top), reply) => {
+                let _ = reply.send(self.marks.jump_target(top, delta));
+            }
+            TermCommand::JumpPrompt(delta, None, _) => {
+                let top = self.marks.top_line(&self.term);
+                if let Some(prompt) = self.marks.jump_target(top, delta) {
+                    let delta = top as i64 - prompt as i64;
+                    self.term.scroll_display(Scroll::Delta(delta as i32));
+                }
+            }
+            TermCommand::CopyOutput(row, line, reply) => {
+                let line = line.unwrap_or_else(|| self.marks.top_line(&self.term) + row as u64);
+                let _ = reply.send(self.marks.output_text(&self.term, line));
+            }
+            TermCommand::FetchHistory(from_line, count, reply) => {
+                let _ = reply.send(build_history(self, from_line, count));
+            }
+            TermCommand::AttachRaw(reply) => {
+                // Subscribed between two chunks, so the output picks up
+                // exactly where the snapshot leaves off
+                let colors = self.term.colors();
+                let palette = RawPalette {
+                    foreground: self.palette.resolve_index(NamedColor::Foreground as usize, colors),
+                    indexed: (0..256).map(|index| self.palette.resolve_index(index, colors)).collect(),
+                };
+                let _ = reply.send((build_frame(self), palette, self.raw_tx.subscribe()));
+            }
+        }
+    }
+
+    /// Feed PTY output to the terminal, pausing at every OSC string and
+    /// image so the side channels see the cursor where the program left it.
+    fn advance(&mut self, data: &[u8]) {
+        let Emulator {
+            term,
+            processor,
+            osc_scanner,
+            mode_scanner,
+            image_scanner,
+            events,
+            marks,
+            images,
+            palette,
+            color_requests,
+            pty_tx,
+            raw_tx: _,
+        } = self;
+        mode_scanner.feed(data);
+        let mut image_events = Vec::new();
+        image_scanner.feed(data, |end, event| image_events.push((end, event)));
+        let mut image_events = image_events.into_iter().peekable();
+        let mut done = 0;
+        let answer = |term: &Term<TermEventProxy>| {
+            for (index, format) in color_requests.borrow_mut().drain(..) {
+                let [r, g, b] = palette.resolve_index(index, term.colors());
+                let _ = pty_tx.blocking_send(PtyCommand::Data(format(Rgb { r, g, b }).into_bytes()));
+            }
+        };
+        // Run the terminal up to `end`, placing the images on the way
+        let mut advance_to = |term: &mut Term<TermEventProxy>, marks: &marks::CommandMarks, end: usize| {
+            while let Some((at, event)) = image_events.next_if(|(at, _)| *at <= end) {
+                processor.advance(term, &data[done..at]);
+                done = at;
+                let effect = images.handle(term, marks, event);
+                processor.advance(term, &effect.cursor);
+                if let Some(reply) = effect.reply {
+                    let _ = pty_tx.blocking_send(PtyCommand::Data(reply.into_bytes()));
+                }
+            }
+            processor.advance(term, &data[done..end]);
+            done = end;
+        };
+        osc_scanner.feed(data, |end, params| {
+            advance_to(term, marks, end);
+            answer(term);
+            if !marks.handle_osc(term, params) {
+                events.handle_osc(params);
+            }
+        });
+        advance_to(term, marks, data.len());
+        answer(term);
+        marks.sync(term);
+        images.sync(term, marks.history_start());
+    }
+}
+
+/// Session state the terminal updates from its events.
 #[derive(Clone)]
+struct TermEvents {
+    title_tx: watch::Sender<Option<String>>,
+    bell_tx: watch::Sender<u64>,
+    clipboard_tx: watch::Sender<Option<String>>,
+    paste_tx: watch::Sender<Option<ClipboardFormatter>>,
+    notify_tx: broadcast::Sender<osc::Notification>,
+    cwd_tx: watch::Sender<Option<PathBuf>>,
+    /// See `Session::responder_tx`.
+    responder_rx: watch::Receiver<bool>,
+}
+
+impl TermEvents {
+    /// Act on an OSC string the terminal itself ignores.
+    fn handle_osc(&self, params: &[&[u8]]) {
+        if let Some(notification) = osc::parse_notification(params) {
+            // No receivers just means no client is connected
+            let _ = self.notify_tx.send(notification);
+        } else if let Some(cwd) = osc::parse_cwd(params) {
+            self.cwd_tx.send_replace(Some(cwd));
+        }
+    }
+}
+
 struct TermEventProxy {
     pty_tx: mpsc::Sender<PtyCommand>,
+    events: TermEvents,
+    /// Shared with `Emulator`, which knows the current colors.
+    color_requests: Rc<RefCell<Vec<(usize, ColorFormatter)>>>,
+    last_bell: std::cell::Cell<Option<std::time::Instant>>,
+}
+
+impl TermEventProxy {
+    fn set_title(&self, title: Option<String>) {
+        self.events.title_tx.send_if_modified(|current| {
+            let changed = *current != title;
+            *current = title;
+            changed
+        });
+    }
+
+    fn ring_bell(&self) {
+        let now = std::time::Instant::now();
+        if self.last_bell.get().is_some_and(|last| now - last < BELL_MIN_INTERVAL) {
+            return;
+        }
+        self.last_bell.set(Some(now));
+        self.events.bell_tx.send_modify(|count| *count += 1);
+    }
 }
 
 impl EventListener for TermEventProxy {
     fn send_event(&self, event: Event) {
-        if let Event::PtyWrite(text) = event {
-            let _ = self.pty_tx.blocking_send(PtyCommand::Data(text.into_bytes()));
+        match event {
+            Event::PtyWrite(text) => {
+                if *self.events.responder_rx.borrow() && is_status_report(&text) {
+                    return;
+                }
+                // alacritty answers DA1 as a VT102; tell programs that look
+                // for it that sixel works
+                let text = if text == "\x1b[?6c" { DEVICE_ATTRIBUTES.to_owned() } else { text };
+                let _ = self.pty_tx.blocking_send(PtyCommand::Data(text.into_bytes()));
+            }
+            Event::Title(title) => self.set_title(Some(title)),
+            Event::ResetTitle => self.set_title(None),
+            Event::Bell => self.ring_bell(),
+            // The browser has a single clipboard, so the primary selection
+            // maps onto it too
+            Event::ClipboardStore(_, text) => {
+                self.events.clipboard_tx.send_replace(Some(text));
+            }
+            Event::ClipboardLoad(_, format) => {
+                self.events.paste_tx.send_replace(Some(format));
+            }
+            Event::ColorRequest(index, format) => {
+                self.color_requests.borrow_mut().push((index, format));
+            }
+            _ => {}
         }
     }
 }
 
+/// DA1 reply: VT220 with sixel graphics (4) and ANSI color (22).
+const DEVICE_ATTRIBUTES: &str = "\x1b[?62;4;22c";
+
+/// Replies to DSR 5 and 6 (`CSI 0 n`, `CSI row ; col R`), which a raw-stream
+/// client answers from its own screen.
+fn is_status_report(text: &str) -> bool {
+    let Some(body) = text.strip_prefix("\x1b[") else {
+        return false;
+    };
+    body == "0n"
+        || body
+            .strip_suffix('R')
+            .is_some_and(|pos| pos.contains(';') && pos.bytes().all(|b| b.is_ascii_digit() || b == b';'))
+}
+
 #[derive(Clone, Copy)]
 struct TermSize {
     cols: usize,
@@ -446,121 +1199,140 @@ impl Dimensions for TermSize {
     }
 }
 
-fn get_or_create_session(state: &AppState, session_id: &str) -> Arc<Session> {
+/// Attach to a session, creating it if needed. A new session's shell starts
+/// in the working directory of `cwd_from`, when that session exists.
+async fn get_or_create_session(
+    state: &AppState,
+    session_id: &str,
+    cwd_from: Option<&str>,
+) -> Arc<Session> {
     if let Some(existing) = state.sessions.get(session_id) {
         info!("Reusing session {}", session_id);
         return existing.clone();
     }
 
+    let source = cwd_from.and_then(|id| state.sessions.get(id).map(|s| s.clone()));
+    let cwd = match source {
+        Some(source) => source.cwd().await,
+        None => None,
+    };
+    // Another connection may have created it meanwhile
+    if let Some(existing) = state.sessions.get(session_id) {
+        return existing.clone();
+    }
+
     let (pty_tx, pty_rx) = mpsc::channel::<PtyCommand>(1024);
-    let (frame_tx, _) = broadcast::channel::<ServerMessage>(256);
-    let last_frame = Arc::new(Mutex::new(None));
-    let min_interval_ms = Arc::new(AtomicU64::new(0));
+    let (frame_tx, _) = watch::channel::<Option<ServerMessage>>(None);
+    let (title_tx, _) = watch::channel::<Option<String>>(None);
+    let (bell_tx, _) = watch::channel::<u64>(0);
+    let (clipboard_tx, _) = watch::channel::<Option<String>>(None);
+    let (paste_tx, _) = watch::channel::<Option<ClipboardFormatter>>(None);
+    let (notify_tx, _) = broadcast::channel::<osc::Notification>(NOTIFY_BACKLOG);
+    let (cwd_tx, _) = watch::channel::<Option<PathBuf>>(None);
+    let (responder_tx, responder_rx) = watch::channel(false);
 
     let pty_tx_clone = pty_tx.clone();
     let frame_tx_clone = frame_tx.clone();
-    let last_frame_clone = last_frame.clone();
-    let min_interval_clone = min_interval_ms.clone();
+    let events = TermEvents {
+        title_tx: title_tx.clone(),
+        bell_tx: bell_tx.clone(),
+        clipboard_tx: clipboard_tx.clone(),
+        paste_tx: paste_tx.clone(),
+        notify_tx: notify_tx.clone(),
+        cwd_tx: cwd_tx.clone(),
+        responder_rx,
+    };
+    let palette = palette::Palette::from_config(&read_config(&state.config_path));
     std::thread::spawn(move || {
-        run_pty(frame_tx_clone, pty_rx, pty_tx_clone, last_frame_clone, min_interval_clone);
+        run_pty(frame_tx_clone, events, pty_rx, pty_tx_clone, cwd, palette);
     });
 
     let session = Arc::new(Session {
         id: session_id.to_string(),
         pty_tx,
         frame_tx,
-        last_frame,
-        min_interval_ms,
+        title_tx,
+        bell_tx,
+        clipboard_tx,
+        paste_tx,
+        notify_tx,
+        cwd_tx,
+        responder_tx,
     });
     state.sessions.insert(session_id.to_string(), session.clone());
     info!("Created new session {}", session_id);
     session
 }
 
-async fn handle_socket(socket: WebSocket, session: Arc<Session>, use_binary: bool) {
+async fn handle_socket(
+    socket: WebSocket,
+    session: Arc<Session>,
+    options: StreamOptions,
+    credits: Option<u32>,
+) {
     info!("New WebSocket connection (session={})", session.id);
 
-    let (mut ws_tx, mut ws_rx) = socket.split();
-    let min_interval_ms = session.min_interval_ms.clone();
+    let (ws_tx, mut ws_rx) = socket.split();
+
+    // Frame credits granted by the client (None = flow control disabled).
+    let credit_tx = Arc::new(watch::channel::<Option<u32>>(credits).0);
+    let (control_tx, control_rx) = mpsc::channel::<ServerMessage>(16);
+    let prefs = Arc::new(quality::LinkPreferences::new());
 
     // Task: PTY -> WebSocket
-    let mut frame_rx = session.frame_tx.subscribe();
-    let last = session
-        .last_frame
-        .lock()
-        .ok()
-        .and_then(|guard| guard.clone());
-    if let Some(last) = last {
-        if use_binary {
-            if let Ok(bin) = bincode::serialize(&last) {
-                let _ = ws_tx.send(Message::Binary(bin.into())).await;
-            }
-        } else if let Ok(json) = serde_json::to_string(&last) {
-            let _ = ws_tx.send(Message::Text(json.into())).await;
-        }
-    }
-    let min_interval_ms_send = min_interval_ms.clone();
-    let send_task = tokio::spawn(async move {
-        let mut last_sent = std::time::Instant::now()
-            .checked_sub(std::time::Duration::from_secs(1))
-            .unwrap_or_else(std::time::Instant::now);
-        loop {
-            match frame_rx.recv().await {
-                Ok(msg) => {
-                    let min_ms = min_interval_ms_send.load(Ordering::Relaxed);
-                    if min_ms > 0 {
-                        let now = std::time::Instant::now();
-                        if now.duration_since(last_sent) < std::time::Duration::from_millis(min_ms) {
-                            continue;
-                        }
-                        last_sent = now;
-                    }
-                    if use_binary {
-                        if let Ok(bin) = bincode::serialize(&msg) {
-                            if ws_tx.send(Message::Binary(bin.into())).await.is_err() {
-                                break;
-                            }
-                        }
-                    } else if let Ok(json) = serde_json::to_string(&msg) {
-                        if ws_tx.send(Message::Text(json.into())).await.is_err() {
-                            break;
-                        }
-                    }
-                }
-                Err(broadcast::error::RecvError::Lagged(_)) => continue,
-                Err(_) => break,
-            }
-        }
-    });
+    let sink = WsFrameSink {
+        tx: ws_tx,
+        text: !options.use_binary && !options.compress,
+    };
+    let send_task = tokio::spawn(pump_frames(
+        session.clone(),
+        credit_tx.clone(),
+        control_rx,
+        prefs.clone(),
+        options,
+        sink,
+    ));
 
     // Task: WebSocket -> PTY
-    let pty_tx = session.pty_tx.clone();
-    let min_interval_ms_recv = min_interval_ms.clone();
+    let recv_session = session.clone();
     let recv_task = tokio::spawn(async move {
-        while let Some(Ok(msg)) = ws_rx.next().await {
+        loop {
+            let msg = match tokio::time::timeout(PEER_TIMEOUT, ws_rx.next()).await {
+                Ok(Some(Ok(msg))) => msg,
+                Ok(_) => break,
+                Err(_) => {
+                    warn!("Peer silent for {:?}, closing (session={})", PEER_TIMEOUT, recv_session.id);
+                    break;
+                }
+            };
             match msg {
                 Message::Text(text) => {
                     if let Ok(client_msg) = serde_json::from_str::<ClientMessage>(&text) {
-                        match client_msg {
-                            ClientMessage::Data { data } => {
-                                let _ = pty_tx.send(PtyCommand::Data(data.into_bytes())).await;
-                            }
-                            ClientMessage::Resize { cols, rows } => {
-                                let _ = pty_tx.send(PtyCommand::Resize(cols, rows)).await;
-                            }
-                            ClientMessage::Scroll { delta } => {
-                                let _ = pty_tx.send(PtyCommand::Scroll(delta)).await;
-                            }
-                            ClientMessage::Quality { min_interval_ms } => {
-                                min_interval_ms_recv.store(min_interval_ms as u64, Ordering::Relaxed);
-                            }
-                        }
+                        handle_client_message(
+                            &recv_session,
+                            &credit_tx,
+                            &control_tx,
+                            &prefs,
+                            client_msg,
+                        )
+                        .await;
                     } else {
                         warn!("Failed to parse client message: {}", text);
                     }
                 }
                 Message::Binary(data) => {
-                    let _ = pty_tx.send(PtyCommand::Data(data.to_vec())).await;
+                    if let Some(client_msg) = codec::decode(&data) {
+                        handle_client_message(
+                            &recv_session,
+                            &credit_tx,
+                            &control_tx,
+                            &prefs,
+                            client_msg,
+                        )
+                        .await;
+                    } else {
+                        warn!("Failed to decode binary client message ({} bytes)", data.len());
+                    }
                 }
                 Message::Close(_) => break,
                 _ => {}
@@ -576,12 +1348,398 @@ async fn handle_socket(socket: WebSocket, session: Arc<Session>, use_binary: boo
     info!("Connection closed");
 }
 
+/// Apply one control message from a client connection.
+async fn handle_client_message(
+    session: &Session,
+    credit_tx: &watch::Sender<Option<u32>>,
+    control_tx: &mpsc::Sender<ServerMessage>,
+    prefs: &quality::LinkPreferences,
+    msg: ClientMessage,
+) {
+    match msg {
+        ClientMessage::Data { data } => {
+            let _ = session.pty_tx.send(PtyCommand::Data(data)).await;
+        }
+        ClientMessage::Resize { cols, rows, cell_width, cell_height } => {
+            let cell = cell_width.zip(cell_height);
+            let _ = session.pty_tx.send(PtyCommand::Resize(cols, rows, cell)).await;
+        }
+        ClientMessage::Scroll { delta } => {
+            let _ = session.pty_tx.send(PtyCommand::Scroll(delta)).await;
+        }
+        ClientMessage::Quality { min_interval_ms, compress } => {
+            prefs.set_min_interval_ms(min_interval_ms as u64);
+            if let Some(compress) = compress {
+                prefs.set_compress(compress);
+            }
+        }
+        ClientMessage::Credit { frames } => {
+            credit_tx.send_modify(|c| {
+                *c = if frames == 0 {
+                    None
+                } else {
+                    Some(c.unwrap_or(0).saturating_add(frames))
+                };
+            });
+        }
+        ClientMessage::Ack { count } => grant_credits(credit_tx, count),
+        ClientMessage::Ping { ts } => {
+            let _ = control_tx.send(ServerMessage::Pong { ts }).await;
+        }
+        ClientMessage::Pong { ts } => {
+            let rtt = unix_millis().saturating_sub(ts);
+            debug!("Heartbeat RTT {}ms (session={})", rtt, session.id);
+        }
+        ClientMessage::JumpPrompt { delta, from_line } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            if session.pty_tx.send(PtyCommand::JumpPrompt(delta, from_line, reply_tx)).await.is_err() {
+                return;
+            }
+            if let Ok(Some(line)) = reply_rx.await {
+                let _ = control_tx.send(ServerMessage::ScrollTo { line }).await;
+            }
+        }
+        ClientMessage::CopyOutput { row, line } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            if session.pty_tx.send(PtyCommand::CopyOutput(row, line, reply_tx)).await.is_err() {
+                return;
+            }
+            if let Ok(Some(text)) = reply_rx.await {
+                let _ = control_tx.send(ServerMessage::Clipboard { text }).await;
+            }
+        }
+        ClientMessage::FetchHistory { from_line, count } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let count = count.min(MAX_HISTORY_PAGE);
+            if session.pty_tx.send(PtyCommand::FetchHistory(from_line, count, reply_tx)).await.is_err() {
+                return;
+            }
+            if let Ok(page) = reply_rx.await {
+                let _ = control_tx.send(ServerMessage::History(page)).await;
+            }
+        }
+        ClientMessage::Clipboard { text } => {
+            // The first client to answer wins; a refusal reads as empty
+            let Some(format) = session.paste_tx.send_replace(None) else {
+                return;
+            };
+            let reply = format(text.as_deref().unwrap_or(""));
+            let _ = session.pty_tx.send(PtyCommand::Data(reply.into_bytes())).await;
+        }
+    }
+}
+
+fn unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Return `frames` credits to a connection that has flow control enabled.
+fn grant_credits(credit_tx: &watch::Sender<Option<u32>>, frames: u32) {
+    credit_tx.send_modify(|c| {
+        if let Some(n) = c {
+            *n = n.saturating_add(frames);
+        }
+    });
+}
+
+/// Outgoing half of a client connection, carrying encoded frame payloads.
+trait FrameSink {
+    /// Send one payload; returns false once the connection is gone.
+    async fn send_payload(&mut self, payload: Vec<u8>) -> bool;
+}
+
+struct WsFrameSink {
+    tx: futures::stream::SplitSink<WebSocket, Message>,
+    /// Uncompressed JSON goes out as text messages.
+    text: bool,
+}
+
+impl FrameSink for WsFrameSink {
+    async fn send_payload(&mut self, payload: Vec<u8>) -> bool {
+        let msg = if self.text {
+            match String::from_utf8(payload) {
+                Ok(json) => Message::Text(json.into()),
+                Err(_) => return true,
+            }
+        } else {
+            Message::Binary(payload.into())
+        };
+        self.tx.send(msg).await.is_ok()
+    }
+}
+
+/// Stream the session's frames and control messages to one client.
+///
+/// Frames are never queued: the watch channel always holds the newest state,
+/// so a slow client skips intermediate frames but is guaranteed to receive
+/// the final state of a burst once it has credit again. Control messages
+/// (heartbeats) bypass the credit window and the frame interval.
+///
+/// The frame interval is the client's requested minimum, stretched while
+/// writes to the connection block (see `quality::LinkMonitor`).
+///
+/// A raw stream sends a snapshot and then the PTY output instead of frames.
+/// Output is never skipped, so credits do not apply; a client that falls
+/// behind the backlog starts over from a new snapshot.
+async fn pump_frames<S: FrameSink>(
+    session: Arc<Session>,
+    credit_tx: Arc<watch::Sender<Option<u32>>>,
+    mut control_rx: mpsc::Receiver<ServerMessage>,
+    prefs: Arc<quality::LinkPreferences>,
+    options: StreamOptions,
+    mut sink: S,
+) {
+    let StreamOptions { use_binary, compress, raw } = options;
+    let responder = if raw { Responder::claim(&session) } else { None };
+    let respond = responder.is_some();
+    let mut raw_rx = None;
+    // Images whose pixels this connection has been sent
+    let mut sent_images = HashSet::new();
+    let mut frame_rx = session.frame_tx.subscribe();
+    frame_rx.mark_changed();
+    let mut title_rx = session.title_tx.subscribe();
+    title_rx.mark_changed();
+    // Only bells, copies, clipboard reads and notifications raised while
+    // connected
+    let mut bell_rx = session.bell_tx.subscribe();
+    let mut clipboard_rx = session.clipboard_tx.subscribe();
+    let mut paste_rx = session.paste_tx.subscribe();
+    let mut notify_rx = session.notify_tx.subscribe();
+    let mut credit_rx = credit_tx.subscribe();
+    let mut compressor = compress.then(compression::FrameCompressor::new);
+    let mut monitor = quality::LinkMonitor::new();
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut last_sent = std::time::Instant::now()
+        .checked_sub(std::time::Duration::from_secs(1))
+        .unwrap_or_else(std::time::Instant::now);
+    // A frame was marked seen but not sent yet. The wait for credit and
+    // the interval is dropped whenever another branch wins the select, so
+    // this remembers the frame until it goes out.
+    let mut pending = false;
+    loop {
+        let frame_ready = async {
+            if !pending {
+                frame_rx.changed().await?;
+                pending = true;
+            }
+            credit_rx.wait_for(|c| *c != Some(0)).await?;
+            let interval = monitor.frame_interval(prefs.min_interval_ms());
+            if !interval.is_zero() {
+                tokio::time::sleep_until((last_sent + interval).into()).await;
+            }
+            Ok::<(), watch::error::RecvError>(())
+        };
+        let control = tokio::select! {
+            msg = control_rx.recv() => match msg {
+                Some(msg) => Some(msg),
+                None => break,
+            },
+            _ = heartbeat.tick() => Some(ServerMessage::Ping { ts: unix_millis() }),
+            changed = title_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let title = title_rx.borrow_and_update().clone();
+                Some(ServerMessage::Title { title })
+            }
+            changed = bell_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                bell_rx.borrow_and_update();
+                Some(ServerMessage::Bell)
+            }
+            changed = clipboard_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let Some(text) = clipboard_rx.borrow_and_update().clone() else { continue };
+                Some(ServerMessage::Clipboard { text })
+            }
+            changed = paste_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                // Also changes back to None once answered
+                if paste_rx.borrow_and_update().is_none() {
+                    continue;
+                }
+                Some(ServerMessage::ClipboardRequest)
+            }
+            received = notify_rx.recv() => match received {
+                Ok(osc::Notification { title, body }) => Some(ServerMessage::Notify { title, body }),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Dropped {} notifications (session={})", skipped, session.id);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            attached = attach_raw(&session), if raw && raw_rx.is_none() => match attached {
+                Some((frame, palette, rx)) => {
+                    raw_rx = Some(rx);
+                    Some(ServerMessage::Snapshot { respond, palette, frame })
+                }
+                None => break,
+            },
+            received = recv_raw(&mut raw_rx) => match received {
+                Ok(data) => Some(ServerMessage::Output { data: data.to_vec() }),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("Raw stream {} chunks behind, resending snapshot (session={})", skipped, session.id);
+                    raw_rx = None;
+                    continue;
+                }
+                // Restarted after a resize, or the session ended, which the
+                // next attach finds out
+                Err(broadcast::error::RecvError::Closed) => {
+                    raw_rx = None;
+                    continue;
+                }
+            },
+            ready = frame_ready, if !raw => {
+                if ready.is_err() {
+                    break;
+                }
+                None
+            }
+        };
+
+        // Bincode needs the deflate stream to be told apart from JSON, so
+        // only JSON frames may skip compression.
+        let deflate = use_binary || prefs.compress();
+        let mut compressor = compressor.as_mut().filter(|_| deflate);
+        let is_frame = control.is_none();
+        // Encoded in the order they are sent, as the deflate stream needs
+        let mut image_payloads = Vec::new();
+        let payload = match control {
+            Some(msg) => {
+                for image in unsent_images(&msg, &mut sent_images) {
+                    image_payloads.extend(encode_payload(&image, use_binary, compressor.as_deref_mut()));
+                }
+                encode_payload(&msg, use_binary, compressor)
+            }
+            None => {
+                pending = false;
+                let payload = {
+                    let frame = frame_rx.borrow_and_update();
+                    let Some(msg) = frame.as_ref() else { continue };
+                    for image in unsent_images(msg, &mut sent_images) {
+                        image_payloads.extend(encode_payload(&image, use_binary, compressor.as_deref_mut()));
+                    }
+                    encode_payload(msg, use_binary, compressor)
+                };
+                if payload.is_some() {
+                    credit_tx.send_modify(|c| {
+                        if let Some(n) = c {
+                            *n = n.saturating_sub(1);
+                        }
+                    });
+                    last_sent = std::time::Instant::now();
+                }
+                payload
+            }
+        };
+        let Some(payload) = payload else { continue };
+        for image in image_payloads {
+            if !sink.send_payload(image).await {
+                return;
+            }
+        }
+        let started = std::time::Instant::now();
+        if !sink.send_payload(payload).await {
+            break;
+        }
+        if is_frame {
+            monitor.record_send(started.elapsed());
+        }
+    }
+}
+
+/// `ServerMessage::Image`s for the images in a frame or snapshot that were
+/// not sent yet. Images no longer shown are forgotten, and sent again if
+/// they return.
+fn unsent_images(msg: &ServerMessage, sent: &mut HashSet<u32>) -> Vec<ServerMessage> {
+    let (ServerMessage::Frame(frame) | ServerMessage::Snapshot { frame, .. }) = msg else {
+        return Vec::new();
+    };
+    sent.retain(|id| frame.images.iter().any(|image| image.id == *id));
+    frame
+        .images
+        .iter()
+        .filter(|placement| sent.insert(placement.id))
+        .map(|placement| ServerMessage::Image {
+            id: placement.id,
+            width: placement.image.width,
+            height: placement.image.height,
+            data: placement.image.rgba.clone(),
+        })
+        .collect()
+}
+
+/// Snapshot and output subscription for a raw stream.
+async fn attach_raw(session: &Session) -> Option<RawAttach> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    session.pty_tx.send(PtyCommand::AttachRaw(reply_tx)).await.ok()?;
+    reply_rx.await.ok()
+}
+
+/// Next raw output chunk; pending while not attached.
+async fn recv_raw(rx: &mut Option<broadcast::Receiver<Arc<[u8]>>>) -> Result<Arc<[u8]>, broadcast::error::RecvError> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The role of answering status reports, held by the first raw-stream
+/// connection of a session and given up when it ends.
+struct Responder(Arc<Session>);
+
+impl Responder {
+    fn claim(session: &Arc<Session>) -> Option<Responder> {
+        let claimed = session.responder_tx.send_if_modified(|taken| !std::mem::replace(taken, true));
+        claimed.then(|| Responder(session.clone()))
+    }
+}
+
+impl Drop for Responder {
+    fn drop(&mut self) {
+        self.0.responder_tx.send_replace(false);
+    }
+}
+
+/// Serialize a message as bincode or JSON, deflating it when a compressor is
+/// given.
+///
+/// Compressed payloads may carry either encoding; the client inflates and then
+/// tells JSON from bincode by the leading byte. Uncompressed JSON can share a
+/// compressed connection because a sync-flushed deflate block never starts
+/// with `{` (its BFINAL bit is clear).
+fn encode_payload(
+    msg: &ServerMessage,
+    use_binary: bool,
+    compressor: Option<&mut compression::FrameCompressor>,
+) -> Option<Vec<u8>> {
+    let payload = if use_binary {
+        bincode::serialize(msg).ok()?
+    } else {
+        serde_json::to_vec(msg).ok()?
+    };
+    Some(match compressor {
+        Some(compressor) => compressor.compress(&payload),
+        None => payload,
+    })
+}
+
 fn run_pty(
-    output_tx: broadcast::Sender<ServerMessage>,
+    output_tx: watch::Sender<Option<ServerMessage>>,
+    events: TermEvents,
     mut input_rx: mpsc::Receiver<PtyCommand>,
     pty_tx: mpsc::Sender<PtyCommand>,
-    last_frame: Arc<Mutex<Option<ServerMessage>>>,
-    min_interval_ms: Arc<AtomicU64>,
+    cwd: Option<PathBuf>,
+    palette: palette::Palette,
 ) {
     let pty_system = native_pty_system();
 
@@ -603,6 +1761,10 @@ fn run_pty(
     let shell = resolve_shell();
     let mut cmd = CommandBuilder::new(&shell);
     configure_shell_command(&mut cmd, &shell);
+    if let Some(dir) = cwd.filter(|dir| dir.is_dir()) {
+        info!("Starting shell in {}", dir.display());
+        cmd.cwd(dir);
+    }
     cmd.env("TERM", "xterm-256color");
     cmd.env("COLORTERM", "truecolor");
     cmd.env("LANG", "en_US.UTF-8");
@@ -627,71 +1789,19 @@ fn run_pty(
 
     // Terminal emulation thread (alacritty_terminal)
     let term_output_tx = output_tx.clone();
-    let term_last_frame = last_frame.clone();
     let term_pty_tx = pty_tx.clone();
     std::thread::spawn(move || {
-        let proxy = TermEventProxy { pty_tx: term_pty_tx };
-        let mut processor = Processor::<StdSyncHandler>::new();
-        let config = TermConfig::default();
-        let size = TermSize { cols: 80, rows: 24 };
-        let mut term = Term::new(config, &size, proxy);
-
-        let mut last_sent = std::time::Instant::now()
-            .checked_sub(std::time::Duration::from_secs(1))
-            .unwrap_or_else(std::time::Instant::now);
+        let mut emulator = Emulator::new(events, term_pty_tx, palette);
         while let Some(cmd) = term_cmd_rx.blocking_recv() {
-            match cmd {
-                TermCommand::Data(data) => {
-                    processor.advance(&mut term, &data);
-                }
-                TermCommand::Resize(cols, rows) => {
-                    term.resize(TermSize {
-                        cols: cols as usize,
-                        rows: rows as usize,
-                    });
-                }
-                TermCommand::Scroll(delta) => {
-                    term.scroll_display(Scroll::Delta(delta));
-                }
-            }
+            emulator.apply(cmd);
 
             // Drain any additional queued commands to avoid rebuilding multiple frames.
             while let Ok(cmd) = term_cmd_rx.try_recv() {
-                match cmd {
-                    TermCommand::Data(data) => {
-                        processor.advance(&mut term, &data);
-                    }
-                    TermCommand::Resize(cols, rows) => {
-                        term.resize(TermSize {
-                            cols: cols as usize,
-                            rows: rows as usize,
-                        });
-                    }
-                    TermCommand::Scroll(delta) => {
-                        term.scroll_display(Scroll::Delta(delta));
-                    }
-                }
+                emulator.apply(cmd);
             }
 
-            let min_ms = min_interval_ms.load(Ordering::Relaxed);
-            if min_ms > 0 {
-                let now = std::time::Instant::now();
-                let elapsed = now.duration_since(last_sent);
-                let min_dur = std::time::Duration::from_millis(min_ms);
-                if elapsed < min_dur {
-                    std::thread::sleep(min_dur - elapsed);
-                }
-                last_sent = std::time::Instant::now();
-            } else {
-                last_sent = std::time::Instant::now();
-            }
-
-            let frame = build_frame(&term);
-            let msg = ServerMessage::Frame(frame);
-            if let Ok(mut guard) = term_last_frame.lock() {
-                *guard = Some(msg.clone());
-            }
-            let _ = term_output_tx.send(msg);
+            let frame = build_frame(&emulator);
+            term_output_tx.send_replace(Some(ServerMessage::Frame(frame)));
         }
     });
 
@@ -724,24 +1834,52 @@ fn run_pty(
                     break;
                 }
             }
-            PtyCommand::Resize(cols, rows) => {
+            PtyCommand::Resize(cols, rows, cell) => {
                 debug!("Resize to {}x{}", cols, rows);
+                let (cell_width, cell_height) = cell.unwrap_or_default();
                 let _ = master.resize(PtySize {
                     rows,
                     cols,
-                    pixel_width: 0,
-                    pixel_height: 0,
+                    pixel_width: cell_width.saturating_mul(cols),
+                    pixel_height: cell_height.saturating_mul(rows),
                 });
-                let _ = term_cmd_tx.blocking_send(TermCommand::Resize(cols, rows));
+                let _ = term_cmd_tx.blocking_send(TermCommand::Resize(cols, rows, cell));
             }
             PtyCommand::Scroll(delta) => {
                 let _ = term_cmd_tx.blocking_send(TermCommand::Scroll(delta));
             }
+            PtyCommand::Cwd(reply) => {
+                let _ = reply.send(foreground_cwd(&*master));
+            }
+            PtyCommand::JumpPrompt(delta, from_line, reply) => {
+                let _ = term_cmd_tx.blocking_send(TermCommand::JumpPrompt(delta, from_line, reply));
+            }
+            PtyCommand::CopyOutput(row, line, reply) => {
+                let _ = term_cmd_tx.blocking_send(TermCommand::CopyOutput(row, line, reply));
+            }
+            PtyCommand::FetchHistory(from_line, count, reply) => {
+                let _ = term_cmd_tx.blocking_send(TermCommand::FetchHistory(from_line, count, reply));
+            }
+            PtyCommand::AttachRaw(reply) => {
+                let _ = term_cmd_tx.blocking_send(TermCommand::AttachRaw(reply));
+            }
         }
     }
     info!("PTY handler exited");
 }
 
+/// Working directory of the process in the foreground of the PTY.
+#[cfg(unix)]
+fn foreground_cwd(master: &dyn MasterPty) -> Option<PathBuf> {
+    let pid = master.process_group_leader()?;
+    std::fs::read_link(format!("/proc/{pid}/cwd")).ok()
+}
+
+#[cfg(not(unix))]
+fn foreground_cwd(_master: &dyn MasterPty) -> Option<PathBuf> {
+    None
+}
+
 fn init_logging() {
     let verbose = std::env::args().any(|arg| arg == "-v" || arg == "--verbose");
     let default_level = if verbose { "debug" } else { "info" };
@@ -874,35 +2012,15 @@ fn ensure_self_signed_cert(cert_dir: &Path, hosts: &[String]) -> anyhow::Result<
     Ok((cert_pem, key_pem))
 }
 
-const DEFAULT_FG: [u8; 3] = [229, 229, 229];
-const DEFAULT_BG: [u8; 3] = [30, 30, 30];
-
-const ANSI_16: [[u8; 3]; 16] = [
-    [0, 0, 0],
-    [205, 49, 49],
-    [13, 188, 121],
-    [229, 229, 16],
-    [36, 114, 200],
-    [188, 63, 188],
-    [17, 168, 205],
-    [229, 229, 229],
-    [102, 102, 102],
-    [241, 76, 76],
-    [35, 209, 139],
-    [245, 245, 67],
-    [59, 142, 234],
-    [214, 112, 214],
-    [41, 184, 219],
-    [255, 255, 255],
-];
-
-fn build_frame<T: EventListener>(term: &Term<T>) -> ServerFrame {
+fn build_frame(emulator: &Emulator) -> ServerFrame {
+    let Emulator { term, marks, images, palette, mode_scanner, .. } = emulator;
     let content = term.renderable_content();
     let cols = term.columns() as u16;
     let rows = term.screen_lines() as u16;
     let display_offset = content.display_offset as i32;
 
     let mut cells = vec![ServerCell::default(); (cols as usize) * (rows as usize)];
+    let mut links = LinkTable::default();
 
     for indexed in content.display_iter {
         let row = indexed.point.line.0 + display_offset;
@@ -914,7 +2032,8 @@ fn build_frame<T: EventListener>(term: &Term<T>) -> ServerFrame {
             continue;
         }
         let idx = row as usize * cols as usize + col;
-        cells[idx] = convert_cell(indexed.cell, content.colors);
+        cells[idx] = convert_cell(indexed.cell, content.colors, palette);
+        cells[idx].link = links.intern(indexed.cell);
     }
 
     let mut cursor_col = 0u16;
@@ -931,127 +2050,178 @@ fn build_frame<T: EventListener>(term: &Term<T>) -> ServerFrame {
         }
     }
 
+    // The hollow block is alacritty's unfocused cursor; focus is the
+    // client's business, so send the shape the application asked for.
+    let style = term.cursor_style();
+    let cursor_shape = match style.shape {
+        CursorShape::Underline => ServerCursorShape::Underline,
+        CursorShape::Beam => ServerCursorShape::Beam,
+        _ => ServerCursorShape::Block,
+    };
+
+    let top_line = marks.top_line(term);
+
     ServerFrame {
         cols,
         rows,
         cursor_col,
         cursor_row,
         cursor_visible,
+        cursor_shape,
+        cursor_blink: style.blinking,
+        modes: ServerModes {
+            x10_mouse: mode_scanner.x10_mouse,
+            urxvt_mouse: mode_scanner.urxvt_mouse,
+            ..ServerModes::from(*term.mode())
+        },
+        background: palette.resolve_index(NamedColor::Background as usize, term.colors()),
+        cursor_color: palette.resolve_index(NamedColor::Cursor as usize, term.colors()),
+        top_line,
+        history_start: marks.history_start(),
+        links: links.uris,
+        commands: command_marks(term, marks, top_line..top_line + rows as u64),
+        images: images
+            .visible(term)
+            .map(|placement| ServerImage {
+                id: placement.image.id,
+                line: placement.line,
+                column: placement.column,
+                offset: placement.offset,
+                source: placement.source,
+                width: placement.size.0,
+                height: placement.size.1,
+                z: placement.z,
+                image: placement.image.clone(),
+            })
+            .collect(),
         cells,
     }
 }
 
-fn convert_cell(cell: &alacritty_terminal::term::cell::Cell, colors: &alacritty_terminal::term::color::Colors) -> ServerCell {
+/// Scrollback lines `from_line..from_line + count` that are still kept,
+/// stopping at the top of the screen.
+fn build_history(emulator: &Emulator, from_line: u64, count: u16) -> HistoryPage {
+    let Emulator { term, marks, palette, .. } = emulator;
+    let start = from_line.max(marks.history_start());
+    let end = (from_line + count as u64).min(marks.screen_top(term)).max(start);
+    let grid = term.grid();
+    let mut links = LinkTable::default();
+    let lines = (start..end)
+        .filter_map(|line| marks.grid_line(term, line))
+        .map(|line| {
+            let row = &grid[line];
+            let len = (0..grid.columns())
+                .rposition(|col| !row[Column(col)].is_empty())
+                .map_or(0, |col| col + 1);
+            (0..len)
+                .map(|col| {
+                    let cell = &row[Column(col)];
+                    let mut converted = convert_cell(cell, term.colors(), palette);
+                    converted.link = links.intern(cell);
+                    converted
+                })
+                .collect()
+        })
+        .collect();
+    HistoryPage {
+        from_line: start,
+        lines,
+        links: links.uris,
+        commands: command_marks(term, marks, start..end),
+    }
+}
+
+/// Commands overlapping `lines`, with rows relative to `lines.start`.
+fn command_marks<T>(term: &Term<T>, marks: &marks::CommandMarks, lines: Range<u64>) -> Vec<ServerCommandMark> {
+    let top = lines.start;
+    let relative = |line: u64| (line as i64 - top as i64) as i32;
+    marks
+        .overlapping(term, lines)
+        .into_iter()
+        .map(|(command, output)| ServerCommandMark {
+            prompt: relative(command.prompt),
+            output_start: relative(output.start),
+            output_end: relative(output.end),
+            finished: command.end.is_some(),
+            exit: command.exit,
+        })
+        .collect()
+}
+
+/// OSC 8 hyperlinks of a frame or history page, one entry per distinct link.
+#[derive(Default)]
+struct LinkTable {
+    uris: Vec<String>,
+    ids: HashMap<Hyperlink, u32>,
+}
+
+impl LinkTable {
+    /// Index of the cell's hyperlink, added on first sight.
+    fn intern(&mut self, cell: &alacritty_terminal::term::cell::Cell) -> Option<u32> {
+        let link = cell.hyperlink()?;
+        let next = self.uris.len() as u32;
+        Some(*self.ids.entry(link).or_insert_with_key(|link| {
+            self.uris.push(link.uri().to_string());
+            next
+        }))
+    }
+}
+
+fn convert_cell(
+    cell: &alacritty_terminal::term::cell::Cell,
+    colors: &alacritty_terminal::term::color::Colors,
+    palette: &palette::Palette,
+) -> ServerCell {
     let flags = cell.flags;
-    let mut fg = resolve_color(cell.fg, colors);
-    let mut bg = resolve_color(cell.bg, colors);
+    let mut fg = palette.resolve(cell.fg, colors);
+    let mut bg = palette.resolve(cell.bg, colors);
 
     if flags.contains(TermFlags::INVERSE) {
         std::mem::swap(&mut fg, &mut bg);
     }
 
-    let mut c = cell.c;
-    if flags.contains(TermFlags::WIDE_CHAR_SPACER) {
-        c = ' ';
-    }
+    let spacer = flags.contains(TermFlags::WIDE_CHAR_SPACER);
+    let (c, combining) = if spacer || flags.contains(TermFlags::LEADING_WIDE_CHAR_SPACER) {
+        (' ', String::new())
+    } else {
+        (cell.c, cell.zerowidth().map(|chars| chars.iter().collect()).unwrap_or_default())
+    };
+
+    let underline = if flags.contains(TermFlags::UNDERCURL) {
+        ServerUnderline::Curly
+    } else if flags.contains(TermFlags::DOUBLE_UNDERLINE) {
+        ServerUnderline::Double
+    } else if flags.contains(TermFlags::DOTTED_UNDERLINE) {
+        ServerUnderline::Dotted
+    } else if flags.contains(TermFlags::DASHED_UNDERLINE) {
+        ServerUnderline::Dashed
+    } else if flags.contains(TermFlags::UNDERLINE) {
+        ServerUnderline::Single
+    } else {
+        ServerUnderline::None
+    };
 
+    // SGR 5/6 (blink) is dropped by alacritty_terminal, so there is no flag for it.
     ServerCell {
         c,
+        combining,
+        wide: flags.contains(TermFlags::WIDE_CHAR),
+        spacer,
         fg,
         bg,
         bold: flags.contains(TermFlags::BOLD),
         italic: flags.contains(TermFlags::ITALIC),
-        underline: flags.intersects(TermFlags::ALL_UNDERLINES),
+        dim: flags.contains(TermFlags::DIM),
+        underline,
+        underline_color: cell
+            .underline_color()
+            .filter(|_| underline != ServerUnderline::None)
+            .map(|color| palette.resolve(color, colors)),
+        strikeout: flags.contains(TermFlags::STRIKEOUT),
+        hidden: flags.contains(TermFlags::HIDDEN),
         inverse: flags.contains(TermFlags::INVERSE),
-    }
-}
-
-fn resolve_color(color: Color, colors: &alacritty_terminal::term::color::Colors) -> [u8; 3] {
-    match color {
-        Color::Spec(rgb) => [rgb.r, rgb.g, rgb.b],
-        Color::Indexed(idx) => color_256(idx),
-        Color::Named(named) => {
-            if let Some(rgb) = colors[named] {
-                [rgb.r, rgb.g, rgb.b]
-            } else {
-                resolve_named_color(named)
-            }
-        }
-    }
-}
-
-fn resolve_named_color(named: NamedColor) -> [u8; 3] {
-    match named {
-        NamedColor::Foreground => DEFAULT_FG,
-        NamedColor::Background => DEFAULT_BG,
-        NamedColor::Cursor => DEFAULT_FG,
-        NamedColor::BrightForeground => ANSI_16[15],
-        NamedColor::DimForeground => dim_color(DEFAULT_FG),
-        NamedColor::Black => ANSI_16[0],
-        NamedColor::Red => ANSI_16[1],
-        NamedColor::Green => ANSI_16[2],
-        NamedColor::Yellow => ANSI_16[3],
-        NamedColor::Blue => ANSI_16[4],
-        NamedColor::Magenta => ANSI_16[5],
-        NamedColor::Cyan => ANSI_16[6],
-        NamedColor::White => ANSI_16[7],
-        NamedColor::BrightBlack => ANSI_16[8],
-        NamedColor::BrightRed => ANSI_16[9],
-        NamedColor::BrightGreen => ANSI_16[10],
-        NamedColor::BrightYellow => ANSI_16[11],
-        NamedColor::BrightBlue => ANSI_16[12],
-        NamedColor::BrightMagenta => ANSI_16[13],
-        NamedColor::BrightCyan => ANSI_16[14],
-        NamedColor::BrightWhite => ANSI_16[15],
-        NamedColor::DimBlack => dim_color(ANSI_16[0]),
-        NamedColor::DimRed => dim_color(ANSI_16[1]),
-        NamedColor::DimGreen => dim_color(ANSI_16[2]),
-        NamedColor::DimYellow => dim_color(ANSI_16[3]),
-        NamedColor::DimBlue => dim_color(ANSI_16[4]),
-        NamedColor::DimMagenta => dim_color(ANSI_16[5]),
-        NamedColor::DimCyan => dim_color(ANSI_16[6]),
-        NamedColor::DimWhite => dim_color(ANSI_16[7]),
-    }
-}
-
-fn dim_color(color: [u8; 3]) -> [u8; 3] {
-    let scale = 2u16;
-    [
-        ((color[0] as u16 * scale) / 3) as u8,
-        ((color[1] as u16 * scale) / 3) as u8,
-        ((color[2] as u16 * scale) / 3) as u8,
-    ]
-}
-
-fn color_256(idx: u8) -> [u8; 3] {
-    match idx {
-        0 => [0, 0, 0],
-        1 => [205, 49, 49],
-        2 => [13, 188, 121],
-        3 => [229, 229, 16],
-        4 => [36, 114, 200],
-        5 => [188, 63, 188],
-        6 => [17, 168, 205],
-        7 => [229, 229, 229],
-        8 => [102, 102, 102],
-        9 => [241, 76, 76],
-        10 => [35, 209, 139],
-        11 => [245, 245, 67],
-        12 => [59, 142, 234],
-        13 => [214, 112, 214],
-        14 => [41, 184, 219],
-        15 => [255, 255, 255],
-        16..=231 => {
-            let idx = idx - 16;
-            let r = (idx / 36) * 51;
-            let g = ((idx / 6) % 6) * 51;
-            let b = (idx % 6) * 51;
-            [r, g, b]
-        }
-        232..=255 => {
-            let gray = (idx - 232) * 10 + 8;
-            [gray, gray, gray]
-        }
+        // Filled in by `build_frame`, which owns the link table
+        link: None,
     }
 }
 
@@ -0,0 +1,372 @@
+//! Shell integration marks (OSC 133, also known as FTCS).
+//!
+//! A shell that emits `OSC 133 ; A` (prompt start), `B` (command input
+//! start), `C` (output start) and `D [; exit code]` (command finished) tells
+//! the terminal where each command and its output sit in the scrollback.
+//!
+//! Marks are stored as absolute line numbers: line 0 is the first line the
+//! session ever had, and a line keeps its number while it scrolls. alacritty
+//! does not report lines leaving a full scrollback, so `CommandMarks` does
+//! the evicting itself: the terminal's own limit sits `HISTORY_SLACK` above
+//! the real one and `sync` trims the difference after every chunk, counting
+//! what it drops.
+
+use alacritty_terminal::grid::Dimensions;
+use alacritty_terminal::index::{Column, Line};
+use alacritty_terminal::term::cell::Flags;
+use alacritty_terminal::term::{Term, TermMode};
+use std::collections::VecDeque;
+use std::ops::Range;
+
+/// Scrollback lines kept for the user.
+pub const HISTORY_LINES: usize = 10_000;
+/// Headroom above `HISTORY_LINES` for the terminal's own limit. A chunk of
+/// PTY output scrolls at most one line per byte, so this must exceed the
+/// PTY read size.
+pub const HISTORY_SLACK: usize = 8192;
+/// Commands remembered; older ones are forgotten even if still on screen.
+const MAX_COMMANDS: usize = 1000;
+
+/// One command: its prompt, input and output lines, and how it ended.
+#[derive(Clone, Debug)]
+pub struct Command {
+    pub prompt: u64,
+    pub input: Option<u64>,
+    pub output: Option<u64>,
+    /// Line the cursor was on when the command finished.
+    pub end: Option<u64>,
+    pub exit: Option<i32>,
+}
+
+impl Command {
+    /// Lines the command's output occupies (empty while it has none yet).
+    fn output_lines(&self, next_prompt: Option<u64>, cursor: u64) -> Range<u64> {
+        let start = self
+            .output
+            .or(self.input.map(|input| input + 1))
+            .unwrap_or(self.prompt + 1);
+        let end = self.end.or(next_prompt).unwrap_or(cursor + 1);
+        start..end.max(start)
+    }
+}
+
+pub struct CommandMarks {
+    commands: VecDeque<Command>,
+    /// Lines dropped off the top of the scrollback so far.
+    evicted: u64,
+    /// Scrollback length after the last `sync`.
+    history: usize,
+}
+
+impl CommandMarks {
+    pub fn new() -> Self {
+        CommandMarks {
+            commands: VecDeque::new(),
+            evicted: 0,
+            history: 0,
+        }
+    }
+
+    /// Absolute number of the first line in the viewport.
+    pub fn top_line<T>(&self, term: &Term<T>) -> u64 {
+        let grid = term.grid();
+        self.evicted + (grid.history_size() - grid.display_offset()) as u64
+    }
+
+    /// Absolute number of the first scrollback line still kept.
+    pub fn history_start(&self) -> u64 {
+        self.evicted
+    }
+
+    /// Absolute number of the first line of the screen (not scrolled).
+    pub fn screen_top<T>(&self, term: &Term<T>) -> u64 {
+        self.evicted + term.grid().history_size() as u64
+    }
+
+    /// Grid line of an absolute line; None if it is gone or below the screen.
+    pub fn grid_line<T>(&self, term: &Term<T>, line: u64) -> Option<Line> {
+        // Relative to the top of the screen; the scrollback is negative
+        let line = line.checked_sub(self.evicted)? as i64 - term.grid().history_size() as i64;
+        (line < term.grid().screen_lines() as i64).then_some(Line(line as i32))
+    }
+
+    /// Absolute number of the cursor's line.
+    pub fn cursor_line<T>(&self, term: &Term<T>) -> u64 {
+        let grid = term.grid();
+        self.evicted + grid.history_size() as u64 + grid.cursor.point.line.0.max(0) as u64
+    }
+
+    /// Record an OSC 133 mark at the cursor. Returns false if `params` are
+    /// not OSC 133.
+    pub fn handle_osc<T>(&mut self, term: &Term<T>, params: &[&[u8]]) -> bool {
+        let [b"133", kind, rest @ ..] = params else {
+            return false;
+        };
+        // The alternate screen has no scrollback to mark
+        if term.mode().contains(TermMode::ALT_SCREEN) {
+            return true;
+        }
+        let line = self.cursor_line(term);
+        match kind.first() {
+            Some(b'A') => {
+                // A redrawn prompt (e.g. after a resize) replaces the mark
+                if self.commands.back().is_some_and(|last| last.prompt == line && last.end.is_none()) {
+                    self.commands.pop_back();
+                }
+                self.commands.push_back(Command {
+                    prompt: line,
+                    input: None,
+                    output: None,
+                    end: None,
+                    exit: None,
+                });
+                if self.commands.len() > MAX_COMMANDS {
+                    self.commands.pop_front();
+                }
+            }
+            Some(b'B') => {
+                if let Some(last) = self.commands.back_mut() {
+                    last.input = Some(line);
+                }
+            }
+            Some(b'C') => {
+                if let Some(last) = self.commands.back_mut() {
+                    last.output = Some(line);
+                }
+            }
+            Some(b'D') => {
+                if let Some(last) = self.commands.back_mut().filter(|last| last.end.is_none()) {
+                    last.end = Some(line);
+                    last.exit = rest
+                        .first()
+                        .and_then(|code| std::str::from_utf8(code).ok())
+                        .and_then(|code| code.parse().ok());
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// Account for scrollback changes after the terminal processed output:
+    /// lines it cleared (CSI 3 J, reset) and lines over `HISTORY_LINES`,
+    /// which are evicted here.
+    pub fn sync<T>(&mut self, term: &mut Term<T>) {
+        if term.mode().contains(TermMode::ALT_SCREEN) {
+            return;
+        }
+        let grid = term.grid_mut();
+        let history = grid.history_size();
+        if history < self.history {
+            // Cleared: the marks point at lines that are gone or blank
+            self.evicted += (self.history - history) as u64;
+            self.commands.clear();
+        }
+        if history > HISTORY_LINES {
+            self.evicted += (history - HISTORY_LINES) as u64;
+            grid.update_history(HISTORY_LINES);
+            grid.update_history(HISTORY_LINES + HISTORY_SLACK);
+        }
+        self.history = grid.history_size();
+        while self.commands.front().is_some_and(|first| first.prompt < self.evicted) {
+            self.commands.pop_front();
+        }
+    }
+
+    /// Resizing moves lines between the screen and the scrollback without
+    /// changing their numbers; note the new scrollback length. (Rewrapping
+    /// on a width change can still move marks by a few lines.)
+    pub fn resized<T>(&mut self, term: &Term<T>) {
+        if !term.mode().contains(TermMode::ALT_SCREEN) {
+            self.history = term.grid().history_size();
+        }
+    }
+
+    /// Commands overlapping `lines`, with the lines of their output.
+    pub fn overlapping<T>(&self, term: &Term<T>, lines: Range<u64>) -> Vec<(&Command, Range<u64>)> {
+        let cursor = self.cursor_line(term);
+        let mut found = Vec::new();
+        for (i, command) in self.commands.iter().enumerate() {
+            let next_prompt = self.commands.get(i + 1).map(|next| next.prompt);
+            let output = command.output_lines(next_prompt, cursor);
+            if command.prompt < lines.end && output.end.max(command.prompt + 1) > lines.start {
+                found.push((command, output));
+            }
+        }
+        found
+    }
+
+    /// Prompt line `delta` prompts away from the top of the viewport
+    /// (negative = earlier).
+    pub fn jump_target(&self, top: u64, delta: i32) -> Option<u64> {
+        if delta < 0 {
+            let earlier: Vec<u64> = self.commands.iter().map(|c| c.prompt).filter(|&p| p < top).collect();
+            earlier.iter().rev().nth(delta.unsigned_abs() as usize - 1).copied()
+        } else if delta > 0 {
+            self.commands
+                .iter()
+                .map(|c| c.prompt)
+                .filter(|&p| p > top)
+                .nth(delta as usize - 1)
+        } else {
+            None
+        }
+    }
+
+    /// Text of the output of the command that `line` belongs to; None if
+    /// there is no command there or it printed nothing.
+    pub fn output_text<T>(&self, term: &Term<T>, line: u64) -> Option<String> {
+        let cursor = self.cursor_line(term);
+        let i = self.commands.iter().rposition(|c| c.prompt <= line)?;
+        let next_prompt = self.commands.get(i + 1).map(|next| next.prompt);
+        let lines = self.commands[i].output_lines(next_prompt, cursor);
+
+        let grid = term.grid();
+        let mut text = String::new();
+        for abs in lines.start.max(self.evicted)..lines.end {
+            let Some(line) = self.grid_line(term, abs) else {
+                break;
+            };
+            let row = &grid[line];
+            let mut row_text = String::new();
+            for col in 0..grid.columns() {
+                let cell = &row[Column(col)];
+                if cell.flags.contains(Flags::WIDE_CHAR_SPACER) {
+                    continue;
+                }
+                row_text.push(cell.c);
+                if let Some(zerowidth) = cell.zerowidth() {
+                    row_text.extend(zerowidth);
+                }
+            }
+            if row[Column(grid.columns() - 1)].flags.contains(Flags::WRAPLINE) {
+                text.push_str(&row_text);
+            } else {
+                text.push_str(row_text.trim_end());
+                text.push('\n');
+            }
+        }
+        let text = text.trim_end_matches('\n');
+        (!text.is_empty()).then(|| text.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alacritty_terminal::event::VoidListener;
+    use alacritty_terminal::term::Config;
+    use alacritty_terminal::vte::ansi::{Processor, StdSyncHandler};
+
+    /// A terminal with marks, fed the way the session feeds them.
+    struct Shell {
+        term: Term<VoidListener>,
+        processor: Processor<StdSyncHandler>,
+        marks: CommandMarks,
+    }
+
+    impl Shell {
+        fn new() -> Self {
+            let config = Config { scrolling_history: HISTORY_LINES + HISTORY_SLACK, ..Config::default() };
+            Shell {
+                term: Term::new(config, &crate::TermSize { cols: 80, rows: 24 }, VoidListener),
+                processor: Processor::new(),
+                marks: CommandMarks::new(),
+            }
+        }
+
+        fn print(&mut self, bytes: &[u8]) {
+            self.processor.advance(&mut self.term, bytes);
+            self.marks.sync(&mut self.term);
+        }
+
+        fn mark(&mut self, params: &[&[u8]]) {
+            assert!(self.marks.handle_osc(&self.term, params));
+        }
+
+        fn prompts(&self) -> Vec<u64> {
+            self.marks.commands.iter().map(|command| command.prompt).collect()
+        }
+    }
+
+    #[test]
+    fn sync_evicts_past_the_history_limit() {
+        let mut shell = Shell::new();
+        shell.mark(&[b"133", b"A"]);
+        shell.print(b"\n\n");
+        shell.mark(&[b"133", b"A"]);
+        // The cursor walks down the screen, then every line scrolls one off
+        let lines = HISTORY_LINES + 23 + 100;
+        for chunk in b"\n".repeat(lines - 2).chunks(4096) {
+            shell.print(chunk);
+        }
+        assert_eq!(shell.term.grid().history_size(), HISTORY_LINES);
+        assert_eq!(shell.marks.history_start(), 100);
+        assert_eq!(shell.marks.screen_top(&shell.term), 100 + HISTORY_LINES as u64);
+        assert_eq!(shell.marks.cursor_line(&shell.term), lines as u64);
+        // Both prompts scrolled away
+        assert!(shell.prompts().is_empty());
+
+        shell.mark(&[b"133", b"A"]);
+        shell.print(&b"\n".repeat(10));
+        assert_eq!(shell.marks.history_start(), 110);
+        assert_eq!(shell.prompts(), [lines as u64]);
+    }
+
+    #[test]
+    fn sync_counts_cleared_history() {
+        let mut shell = Shell::new();
+        shell.print(&b"\n".repeat(50));
+        shell.mark(&[b"133", b"A"]);
+        assert_eq!(shell.marks.history_start(), 0);
+        shell.print(b"\x1b[3J");
+        assert_eq!(shell.marks.history_start(), 27);
+        assert_eq!(shell.marks.cursor_line(&shell.term), 50);
+        assert!(shell.prompts().is_empty());
+    }
+
+    #[test]
+    fn jump_targets() {
+        let mut shell = Shell::new();
+        for _ in 0..4 {
+            shell.mark(&[b"133", b"A"]);
+            shell.print(&b"\n".repeat(5));
+        }
+        assert_eq!(shell.prompts(), [0, 5, 10, 15]);
+        assert_eq!(shell.marks.jump_target(10, -1), Some(5));
+        assert_eq!(shell.marks.jump_target(10, -2), Some(0));
+        assert_eq!(shell.marks.jump_target(10, -3), None);
+        assert_eq!(shell.marks.jump_target(10, 1), Some(15));
+        assert_eq!(shell.marks.jump_target(10, 2), None);
+        assert_eq!(shell.marks.jump_target(7, 1), Some(10));
+        assert_eq!(shell.marks.jump_target(7, -1), Some(5));
+        assert_eq!(shell.marks.jump_target(10, 0), None);
+    }
+
+    #[test]
+    fn output_text_joins_wrapped_lines() {
+        let mut shell = Shell::new();
+        shell.mark(&[b"133", b"A"]);
+        shell.print(b"$ ");
+        shell.mark(&[b"133", b"B"]);
+        shell.print(b"make\r\n");
+        shell.mark(&[b"133", b"C"]);
+        shell.print(&[b"x".repeat(100), b"\r\n\xe4\xb8\xad done   \r\n".to_vec()].concat());
+        shell.mark(&[b"133", b"D", b"2"]);
+        shell.mark(&[b"133", b"A"]);
+        shell.print(b"$ ");
+        shell.mark(&[b"133", b"B"]);
+        shell.print(b"true\r\n");
+        shell.mark(&[b"133", b"D", b"0"]);
+
+        let expected = format!("{}\n\u{4e2d} done", "x".repeat(100));
+        assert_eq!(shell.marks.output_text(&shell.term, 0).as_deref(), Some(expected.as_str()));
+        assert_eq!(shell.marks.output_text(&shell.term, 2).as_deref(), Some(expected.as_str()));
+        // The second command printed nothing
+        assert_eq!(shell.marks.output_text(&shell.term, 4), None);
+
+        let commands = shell.marks.overlapping(&shell.term, 0..10);
+        let summary: Vec<_> = commands.iter().map(|(command, output)| (command.exit, output.clone())).collect();
+        assert_eq!(summary, [(Some(2), 1..4), (Some(0), 5..5)]);
+    }
+}
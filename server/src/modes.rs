@@ -0,0 +1,153 @@
+//! DEC private modes alacritty_terminal does not track.
+//!
+//! alacritty implements mouse tracking modes 1000/1002/1003 and the SGR and
+//! UTF-8 encodings, but ignores X10 tracking (`CSI ? 9 h`) and the urxvt
+//! encoding (`CSI ? 1015 h`). `ModeScanner` follows those two from the PTY
+//! output, next to the terminal, so the client can honour them as well.
+
+const ESC: u8 = 0x1b;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+
+const X10_MOUSE: u16 = 9;
+const URXVT_MOUSE: u16 = 1015;
+/// Tracking modes that replace X10 tracking when set.
+const MOUSE_TRACKING: [u16; 3] = [1000, 1002, 1003];
+
+/// Longest parameter string kept for a CSI sequence.
+const MAX_PARAMS_LEN: usize = 64;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    /// After `CSI`, before any parameter byte.
+    CsiEntry,
+    /// Inside `CSI ?` parameters.
+    PrivateParams,
+    /// Inside a CSI sequence that is not of interest.
+    CsiIgnore,
+}
+
+/// Incremental tracker of the X10 and urxvt mouse modes; sequences may span
+/// reads.
+pub struct ModeScanner {
+    state: State,
+    params: Vec<u8>,
+    pub x10_mouse: bool,
+    pub urxvt_mouse: bool,
+}
+
+impl ModeScanner {
+    pub fn new() -> Self {
+        ModeScanner {
+            state: State::Ground,
+            params: Vec::new(),
+            x10_mouse: false,
+            urxvt_mouse: false,
+        }
+    }
+
+    /// Scan a chunk of PTY output.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state = match (self.state, byte) {
+                (_, CAN | SUB) => State::Ground,
+                (_, ESC) => State::Escape,
+                (State::Ground, _) => State::Ground,
+                (State::Escape, b'[') => State::CsiEntry,
+                (State::Escape, b'c') => {
+                    // RIS resets every mode
+                    self.x10_mouse = false;
+                    self.urxvt_mouse = false;
+                    State::Ground
+                }
+                (State::Escape, _) => State::Ground,
+                (State::CsiEntry, b'?') => {
+                    self.params.clear();
+                    State::PrivateParams
+                }
+                (State::PrivateParams, b'0'..=b'9' | b';') => {
+                    if self.params.len() < MAX_PARAMS_LEN {
+                        self.params.push(byte);
+                    }
+                    State::PrivateParams
+                }
+                (State::PrivateParams, b'h' | b'l') => {
+                    self.set_modes(byte == b'h');
+                    State::Ground
+                }
+                // Final byte of a sequence of no interest
+                (State::CsiEntry | State::PrivateParams | State::CsiIgnore, 0x40..=0x7e) => {
+                    State::Ground
+                }
+                (State::CsiEntry | State::PrivateParams | State::CsiIgnore, _) => State::CsiIgnore,
+            };
+        }
+    }
+
+    fn set_modes(&mut self, set: bool) {
+        for param in self.params.split(|&b| b == b';') {
+            let Some(mode) = std::str::from_utf8(param).ok().and_then(|p| p.parse::<u16>().ok()) else {
+                continue;
+            };
+            match mode {
+                X10_MOUSE => self.x10_mouse = set,
+                URXVT_MOUSE => self.urxvt_mouse = set,
+                mode if set && MOUSE_TRACKING.contains(&mode) => self.x10_mouse = false,
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modes(chunks: &[&[u8]]) -> (bool, bool) {
+        let mut scanner = ModeScanner::new();
+        for chunk in chunks {
+            scanner.feed(chunk);
+        }
+        (scanner.x10_mouse, scanner.urxvt_mouse)
+    }
+
+    #[test]
+    fn set_and_reset() {
+        assert_eq!(modes(&[b"\x1b[?9h"]), (true, false));
+        assert_eq!(modes(&[b"\x1b[?1015h"]), (false, true));
+        assert_eq!(modes(&[b"\x1b[?9h\x1b[?1015h\x1b[?9l"]), (false, true));
+        assert_eq!(modes(&[b"\x1b[?9h\x1b[?1015h\x1b[?1015l"]), (true, false));
+    }
+
+    #[test]
+    fn among_other_parameters() {
+        assert_eq!(modes(&[b"\x1b[?1049;9;25;1015h"]), (true, true));
+        assert_eq!(modes(&[b"\x1b[?9;1015h", b"\x1b[?25;1015;1049l"]), (true, false));
+        // Empty and unparsable parameters are skipped
+        assert_eq!(modes(&[b"\x1b[?;99999999;9h"]), (true, false));
+    }
+
+    #[test]
+    fn split_across_chunks() {
+        assert_eq!(modes(&[b"\x1b", b"[?10", b"15", b"h"]), (false, true));
+    }
+
+    #[test]
+    fn other_tracking_modes_replace_x10() {
+        assert_eq!(modes(&[b"\x1b[?9h\x1b[?1000h"]), (false, false));
+        assert_eq!(modes(&[b"\x1b[?9;1002h"]), (false, false));
+        assert_eq!(modes(&[b"\x1b[?1003;9h"]), (true, false));
+        assert_eq!(modes(&[b"\x1b[?9h\x1b[?1000l"]), (true, false));
+    }
+
+    #[test]
+    fn ignored_sequences() {
+        // Not private, cancelled, or reset by RIS
+        assert_eq!(modes(&[b"\x1b[9h\x1b[1015h"]), (false, false));
+        assert_eq!(modes(&[b"\x1b[?9\x18h\x1b[?1015\x1b[h"]), (false, false));
+        assert_eq!(modes(&[b"\x1b[?9;1015h\x1bc"]), (false, false));
+        assert_eq!(modes(&[b"\x1b[>9h\x1b[?9$p"]), (false, false));
+    }
+}
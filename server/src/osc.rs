@@ -0,0 +1,247 @@
+//! Side channel for OSC sequences alacritty_terminal does not handle.
+//!
+//! The PTY output is fed through `OscScanner` as well as the terminal, so
+//! sequences alacritty ignores (desktop notifications, working directory
+//! reports and the like) can still be acted on. The scanner only tracks
+//! enough state to find OSC strings; the terminal remains the single source
+//! of truth for everything it renders.
+
+use std::path::PathBuf;
+
+/// Longest OSC string kept; longer ones are dropped whole.
+const MAX_OSC_LEN: usize = 4096;
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    Osc,
+    /// ESC inside an OSC string, possibly starting the ST terminator.
+    OscEscape,
+}
+
+/// Incremental OSC extractor; sequences may span reads.
+pub struct OscScanner {
+    state: State,
+    buf: Vec<u8>,
+    overflow: bool,
+}
+
+impl OscScanner {
+    pub fn new() -> Self {
+        OscScanner {
+            state: State::Ground,
+            buf: Vec::new(),
+            overflow: false,
+        }
+    }
+
+    /// Scan a chunk of PTY output, calling `on_osc` with the offset just past
+    /// every complete OSC string and its parameters (split on `;`).
+    pub fn feed(&mut self, bytes: &[u8], mut on_osc: impl FnMut(usize, &[&[u8]])) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.state = match (self.state, byte) {
+                (State::Ground, ESC) => State::Escape,
+                (State::Ground, _) => State::Ground,
+                (State::Escape, b']') => {
+                    self.buf.clear();
+                    self.overflow = false;
+                    State::Osc
+                }
+                (State::Escape, ESC) => State::Escape,
+                (State::Escape, _) => State::Ground,
+                (State::Osc, BEL) | (State::OscEscape, b'\\') => {
+                    self.dispatch(|params| on_osc(i + 1, params));
+                    State::Ground
+                }
+                (State::Osc, ESC) => State::OscEscape,
+                (State::Osc, CAN | SUB) => State::Ground,
+                (State::Osc, _) => {
+                    self.push(byte);
+                    State::Osc
+                }
+                // Any other escape aborts the string and may start a new
+                // sequence, as in the terminal itself
+                (State::OscEscape, b']') => {
+                    self.buf.clear();
+                    self.overflow = false;
+                    State::Osc
+                }
+                (State::OscEscape, ESC) => State::Escape,
+                (State::OscEscape, _) => State::Ground,
+            };
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.buf.len() < MAX_OSC_LEN {
+            self.buf.push(byte);
+        } else {
+            self.overflow = true;
+        }
+    }
+
+    fn dispatch(&mut self, on_osc: impl FnOnce(&[&[u8]])) {
+        if !self.overflow {
+            let params: Vec<&[u8]> = self.buf.split(|&b| b == b';').collect();
+            on_osc(&params);
+        }
+        self.buf.clear();
+        self.overflow = false;
+    }
+}
+
+/// Desktop notification requested by the running program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub title: Option<String>,
+    pub body: String,
+}
+
+/// Parse OSC 9 (iTerm2 `9;body`) and OSC 777 (`777;notify;title;body`)
+/// notifications. ConEmu reuses OSC 9 with a numeric subcommand
+/// (`9;4;...` progress and friends); those are not notifications.
+pub fn parse_notification(params: &[&[u8]]) -> Option<Notification> {
+    match params {
+        [b"9", rest @ ..] if !rest.is_empty() => {
+            if rest[0].iter().all(u8::is_ascii_digit) {
+                return None;
+            }
+            // The body may itself contain ';'
+            Some(Notification {
+                title: None,
+                body: String::from_utf8_lossy(&rest.join(&b';')).into_owned(),
+            })
+        }
+        [b"777", b"notify", title, body @ ..] => Some(Notification {
+            title: Some(String::from_utf8_lossy(title).into_owned()).filter(|t| !t.is_empty()),
+            body: String::from_utf8_lossy(&body.join(&b';')).into_owned(),
+        }),
+        _ => None,
+    }
+}
+
+/// Parse an OSC 7 working directory report (`7;file://host/path`). Reports
+/// from another host (e.g. a shell inside ssh) are ignored, as their paths
+/// mean nothing here.
+pub fn parse_cwd(params: &[&[u8]]) -> Option<PathBuf> {
+    let [b"7", uri @ ..] = params else {
+        return None;
+    };
+    let url = url::Url::parse(&String::from_utf8_lossy(&uri.join(&b';'))).ok()?;
+    if url.scheme() != "file" {
+        return None;
+    }
+    if url.host_str().is_some_and(|host| !is_local_host(host)) {
+        return None;
+    }
+    // `to_file_path` only accepts an empty host or localhost, so drop it
+    let local = url::Url::parse(&format!("file://{}", url.path())).ok()?;
+    local.to_file_path().ok()
+}
+
+fn is_local_host(host: &str) -> bool {
+    if host.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+    let name = gethostname::gethostname();
+    let name = name.to_string_lossy();
+    let short = name.split('.').next().unwrap_or(&name);
+    host.eq_ignore_ascii_case(&name) || host.eq_ignore_ascii_case(short)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// OSC strings found in `chunks` fed one after the other, with the
+    /// offset into their chunk.
+    fn scan(chunks: &[&[u8]]) -> Vec<(usize, usize, Vec<Vec<u8>>)> {
+        let mut scanner = OscScanner::new();
+        let mut found = Vec::new();
+        for (chunk, bytes) in chunks.iter().enumerate() {
+            scanner.feed(bytes, |at, params| {
+                found.push((chunk, at, params.iter().map(|param| param.to_vec()).collect()));
+            });
+        }
+        found
+    }
+
+    fn params(params: &[&str]) -> Vec<Vec<u8>> {
+        params.iter().map(|param| param.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn bel_and_st_terminators() {
+        let found = scan(&[b"\x1b]9;hi\x07x\x1b]9;there\x1b\\"]);
+        assert_eq!(found, [(0, 7, params(&["9", "hi"])), (0, 19, params(&["9", "there"]))]);
+    }
+
+    #[test]
+    fn sequence_split_across_chunks() {
+        let found = scan(&[b"ab\x1b", b"]777;no", b"tify;T;b\x1b", b"\\c"]);
+        assert_eq!(found, [(3, 1, params(&["777", "notify", "T", "b"]))]);
+    }
+
+    #[test]
+    fn aborted_and_overlong_strings() {
+        let found = scan(&[b"\x1b]9;a\x1b[m\x1b]9;b\x18\x1b]9;c\x1b\x1b]9;d\x07"]);
+        assert_eq!(found, [(0, 26, params(&["9", "d"]))]);
+
+        let mut long = b"\x1b]9;".to_vec();
+        long.resize(long.len() + MAX_OSC_LEN, b'x');
+        long.extend_from_slice(b"\x07\x1b]9;ok\x07");
+        let found = scan(&[&long]);
+        assert_eq!(found, [(0, long.len(), params(&["9", "ok"]))]);
+    }
+
+    #[test]
+    fn notifications() {
+        assert_eq!(
+            parse_notification(&[b"9", b"build done; 3 warnings"]),
+            Some(Notification { title: None, body: "build done; 3 warnings".into() })
+        );
+        // Split on ';' by the scanner
+        assert_eq!(
+            parse_notification(&[b"9", b"a", b"b"]),
+            Some(Notification { title: None, body: "a;b".into() })
+        );
+        assert_eq!(
+            parse_notification(&[b"777", b"notify", b"Make", b"done", b"ok"]),
+            Some(Notification { title: Some("Make".into()), body: "done;ok".into() })
+        );
+        assert_eq!(
+            parse_notification(&[b"777", b"notify", b"", b"done"]),
+            Some(Notification { title: None, body: "done".into() })
+        );
+        // ConEmu progress and the like
+        assert_eq!(parse_notification(&[b"9", b"4", b"1", b"50"]), None);
+        assert_eq!(parse_notification(&[b"9"]), None);
+        assert_eq!(parse_notification(&[b"777", b"other", b"x"]), None);
+        assert_eq!(parse_notification(&[b"2", b"title"]), None);
+    }
+
+    #[test]
+    fn cwd_is_percent_decoded() {
+        assert_eq!(parse_cwd(&[b"7", b"file:///tmp/a%20b/%C3%A9"]), Some(PathBuf::from("/tmp/a b/\u{e9}")));
+        assert_eq!(parse_cwd(&[b"7", b"file://localhost/tmp/x%3By"]), Some(PathBuf::from("/tmp/x;y")));
+        // Split on ';' by the scanner
+        assert_eq!(parse_cwd(&[b"7", b"file:///tmp/x", b"y"]), Some(PathBuf::from("/tmp/x;y")));
+    }
+
+    #[test]
+    fn cwd_from_this_host_only() {
+        let name = gethostname::gethostname().to_string_lossy().into_owned();
+        let here = format!("file://{name}/srv/data");
+        assert_eq!(parse_cwd(&[b"7", here.as_bytes()]), Some(PathBuf::from("/srv/data")));
+        assert_eq!(parse_cwd(&[b"7", b"file://elsewhere.invalid/srv/data"]), None);
+        assert_eq!(parse_cwd(&[b"7", b"https://example.com/srv/data"]), None);
+        assert_eq!(parse_cwd(&[b"7", b"not a uri"]), None);
+        assert_eq!(parse_cwd(&[b"9", b"file:///srv/data"]), None);
+    }
+}
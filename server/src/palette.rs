@@ -0,0 +1,298 @@
+//! Color palette: the configured defaults (`colors` in config.json) plus the
+//! overrides programs set at runtime with OSC 4/10/11/12, which alacritty
+//! keeps in `Term::colors` and drops again on OSC 104/110/111/112.
+
+use alacritty_terminal::term::color::Colors;
+use alacritty_terminal::vte::ansi::{Color, NamedColor, Rgb};
+use tracing::warn;
+
+const FOREGROUND: usize = NamedColor::Foreground as usize;
+const BACKGROUND: usize = NamedColor::Background as usize;
+const CURSOR: usize = NamedColor::Cursor as usize;
+const DIM_BLACK: usize = NamedColor::DimBlack as usize;
+const DIM_WHITE: usize = NamedColor::DimWhite as usize;
+const BRIGHT_FOREGROUND: usize = NamedColor::BrightForeground as usize;
+const DIM_FOREGROUND: usize = NamedColor::DimForeground as usize;
+
+pub const DEFAULT_FG: [u8; 3] = [229, 229, 229];
+pub const DEFAULT_BG: [u8; 3] = [30, 30, 30];
+
+const ANSI_16: [[u8; 3]; 16] = [
+    [0, 0, 0],
+    [205, 49, 49],
+    [13, 188, 121],
+    [229, 229, 16],
+    [36, 114, 200],
+    [188, 63, 188],
+    [17, 168, 205],
+    [229, 229, 229],
+    [102, 102, 102],
+    [241, 76, 76],
+    [35, 209, 139],
+    [245, 245, 67],
+    [59, 142, 234],
+    [214, 112, 214],
+    [41, 184, 219],
+    [255, 255, 255],
+];
+
+/// Default colors of a session.
+#[derive(Clone, Debug)]
+pub struct Palette {
+    pub foreground: [u8; 3],
+    pub background: [u8; 3],
+    pub cursor: [u8; 3],
+    /// The 16 ANSI colors; 16-255 are the standard cube and gray ramp.
+    pub ansi: [[u8; 3]; 16],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            foreground: DEFAULT_FG,
+            background: DEFAULT_BG,
+            cursor: DEFAULT_FG,
+            ansi: ANSI_16,
+        }
+    }
+}
+
+impl Palette {
+    /// Read the `colors` block of config.json (`#rrggbb` strings); missing or
+    /// malformed entries keep their defaults.
+    pub fn from_config(config: &serde_json::Value) -> Self {
+        let mut palette = Palette::default();
+        let colors = &config["colors"];
+        let parse = |key: &str, value: &serde_json::Value| -> Option<[u8; 3]> {
+            let text = value.as_str()?;
+            match text.parse::<Rgb>() {
+                Ok(rgb) => Some([rgb.r, rgb.g, rgb.b]),
+                Err(()) => {
+                    warn!("Ignoring colors.{}: {:?} is not #rrggbb", key, text);
+                    None
+                }
+            }
+        };
+        if let Some(color) = parse("foreground", &colors["foreground"]) {
+            palette.foreground = color;
+            palette.cursor = color;
+        }
+        if let Some(color) = parse("background", &colors["background"]) {
+            palette.background = color;
+        }
+        if let Some(color) = parse("cursor", &colors["cursor"]) {
+            palette.cursor = color;
+        }
+        if let Some(ansi) = colors["ansi"].as_array() {
+            for (slot, value) in palette.ansi.iter_mut().zip(ansi) {
+                if let Some(color) = parse("ansi", value) {
+                    *slot = color;
+                }
+            }
+        }
+        palette
+    }
+
+    /// Resolve a cell color.
+    pub fn resolve(&self, color: Color, colors: &Colors) -> [u8; 3] {
+        match color {
+            Color::Spec(rgb) => [rgb.r, rgb.g, rgb.b],
+            Color::Indexed(idx) => self.resolve_index(idx as usize, colors),
+            Color::Named(named) => self.resolve_index(named as usize, colors),
+        }
+    }
+
+    /// Current color of an alacritty color index (0-255, then the named
+    /// colors from `NamedColor::Foreground` on): the runtime override if
+    /// there is one, else the default.
+    pub fn resolve_index(&self, index: usize, colors: &Colors) -> [u8; 3] {
+        if let Some(rgb) = colors[index] {
+            return [rgb.r, rgb.g, rgb.b];
+        }
+        match index {
+            0..=15 => self.ansi[index],
+            16..=255 => color_256(index as u8),
+            FOREGROUND => self.foreground,
+            BACKGROUND => self.background,
+            CURSOR => self.cursor,
+            DIM_BLACK..=DIM_WHITE => dim_color(self.resolve_index(index - DIM_BLACK, colors)),
+            BRIGHT_FOREGROUND => self.resolve_index(NamedColor::BrightWhite as usize, colors),
+            DIM_FOREGROUND => dim_color(self.resolve_index(FOREGROUND, colors)),
+            _ => self.foreground,
+        }
+    }
+}
+
+fn dim_color(color: [u8; 3]) -> [u8; 3] {
+    let scale = 2u16;
+    [
+        ((color[0] as u16 * scale) / 3) as u8,
+        ((color[1] as u16 * scale) / 3) as u8,
+        ((color[2] as u16 * scale) / 3) as u8,
+    ]
+}
+
+/// Colors 16-255: the 6x6x6 cube and the gray ramp.
+fn color_256(idx: u8) -> [u8; 3] {
+    match idx {
+        16..=231 => {
+            let idx = idx - 16;
+            let r = (idx / 36) * 51;
+            let g = ((idx / 6) % 6) * 51;
+            let b = (idx % 6) * 51;
+            [r, g, b]
+        }
+        232..=255 => {
+            let gray = (idx - 232) * 10 + 8;
+            [gray, gray, gray]
+        }
+        _ => ANSI_16[idx as usize],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alacritty_terminal::event::VoidListener;
+    use alacritty_terminal::term::{Config, Term};
+    use alacritty_terminal::vte::ansi::{Processor, StdSyncHandler};
+    use serde_json::json;
+
+    fn term() -> Term<VoidListener> {
+        Term::new(
+            Config::default(),
+            &crate::TermSize { cols: 80, rows: 24 },
+            VoidListener,
+        )
+    }
+
+    /// Runs `bytes` through the terminal, which keeps the OSC color overrides.
+    fn feed(term: &mut Term<VoidListener>, bytes: &[u8]) {
+        let mut processor: Processor<StdSyncHandler> = Processor::new();
+        processor.advance(term, bytes);
+    }
+
+    #[test]
+    fn defaults_without_colors() {
+        let palette = Palette::from_config(&json!({}));
+        assert_eq!(palette.foreground, DEFAULT_FG);
+        assert_eq!(palette.background, DEFAULT_BG);
+        assert_eq!(palette.cursor, DEFAULT_FG);
+        assert_eq!(palette.ansi, ANSI_16);
+    }
+
+    #[test]
+    fn parses_colors() {
+        let palette = Palette::from_config(&json!({
+            "colors": {
+                "foreground": "#c0c0c0",
+                "background": "0x102030",
+                "ansi": ["#000001", "#ff0000"],
+            }
+        }));
+        assert_eq!(palette.foreground, [0xc0, 0xc0, 0xc0]);
+        assert_eq!(palette.background, [0x10, 0x20, 0x30]);
+        // The cursor follows the foreground unless set
+        assert_eq!(palette.cursor, [0xc0, 0xc0, 0xc0]);
+        assert_eq!(palette.ansi[0], [0, 0, 1]);
+        assert_eq!(palette.ansi[1], [0xff, 0, 0]);
+        assert_eq!(palette.ansi[2..], ANSI_16[2..]);
+
+        let palette = Palette::from_config(&json!({
+            "colors": { "foreground": "#c0c0c0", "cursor": "#00ff00" }
+        }));
+        assert_eq!(palette.cursor, [0, 0xff, 0]);
+    }
+
+    #[test]
+    fn invalid_colors_keep_defaults() {
+        let palette = Palette::from_config(&json!({
+            "colors": {
+                "foreground": "white",
+                "background": "#12345",
+                "cursor": 7,
+                "ansi": [null, "#zzzzzz", "#010203"],
+            }
+        }));
+        assert_eq!(palette.foreground, DEFAULT_FG);
+        assert_eq!(palette.background, DEFAULT_BG);
+        assert_eq!(palette.cursor, DEFAULT_FG);
+        assert_eq!(palette.ansi[..2], ANSI_16[..2]);
+        assert_eq!(palette.ansi[2], [1, 2, 3]);
+
+        let palette = Palette::from_config(&json!({ "colors": { "ansi": "#010203" } }));
+        assert_eq!(palette.ansi, ANSI_16);
+    }
+
+    #[test]
+    fn resolves_derived_colors() {
+        let palette = Palette::from_config(&json!({
+            "colors": { "foreground": "#999999" }
+        }));
+        let term = term();
+        let colors = term.colors();
+        assert_eq!(palette.resolve_index(16, colors), [0, 0, 0]);
+        assert_eq!(palette.resolve_index(21, colors), [0, 0, 255]);
+        assert_eq!(palette.resolve_index(231, colors), [255, 255, 255]);
+        assert_eq!(palette.resolve_index(232, colors), [8, 8, 8]);
+        assert_eq!(
+            palette.resolve_index(DIM_FOREGROUND, colors),
+            [0x66, 0x66, 0x66]
+        );
+        assert_eq!(
+            palette.resolve_index(DIM_BLACK + 1, colors),
+            dim_color(ANSI_16[1])
+        );
+        assert_eq!(
+            palette.resolve_index(BRIGHT_FOREGROUND, colors),
+            ANSI_16[15]
+        );
+        assert_eq!(
+            palette.resolve(Color::Spec(Rgb { r: 1, g: 2, b: 3 }), colors),
+            [1, 2, 3]
+        );
+        assert_eq!(
+            palette.resolve(Color::Named(NamedColor::Foreground), colors),
+            [0x99; 3]
+        );
+    }
+
+    #[test]
+    fn runtime_overrides_win_until_reset() {
+        let palette = Palette::from_config(&json!({
+            "colors": {
+                "foreground": "#aaaaaa",
+                "background": "#111111",
+                "ansi": ["#000000", "#aa0000"],
+            }
+        }));
+        let mut term = term();
+        feed(
+            &mut term,
+            b"\x1b]4;1;#00ff00\x07\x1b]10;#ffffff\x07\x1b]11;rgb:22/22/22\x07",
+        );
+        let colors = term.colors();
+        assert_eq!(palette.resolve_index(1, colors), [0, 0xff, 0]);
+        assert_eq!(palette.resolve_index(FOREGROUND, colors), [0xff; 3]);
+        assert_eq!(palette.resolve_index(BACKGROUND, colors), [0x22; 3]);
+        // Colors derived from an overridden one follow it
+        assert_eq!(
+            palette.resolve_index(DIM_BLACK + 1, colors),
+            dim_color([0, 0xff, 0])
+        );
+        assert_eq!(
+            palette.resolve_index(DIM_FOREGROUND, colors),
+            dim_color([0xff; 3])
+        );
+        // Untouched entries still come from the config
+        assert_eq!(palette.resolve_index(0, colors), [0, 0, 0]);
+        assert_eq!(palette.resolve_index(CURSOR, colors), [0xaa; 3]);
+
+        feed(&mut term, b"\x1b]104;1\x07\x1b]110\x07\x1b]111\x07");
+        let colors = term.colors();
+        assert_eq!(colors[1], None);
+        assert_eq!(palette.resolve_index(1, colors), [0xaa, 0, 0]);
+        assert_eq!(palette.resolve_index(FOREGROUND, colors), [0xaa; 3]);
+        assert_eq!(palette.resolve_index(BACKGROUND, colors), [0x11; 3]);
+    }
+}
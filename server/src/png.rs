@@ -0,0 +1,445 @@
+//! PNG decoding, for images sent with the kitty graphics protocol.
+//!
+//! Covers what programs send in practice: every color type and bit depth,
+//! palettes and `tRNS` transparency, Adam7 interlacing. Ancillary chunks
+//! (gamma, color profiles, text) are ignored and checksums are not
+//! verified; a PNG that is damaged anywhere else is rejected.
+
+use crate::images::{Pixels, MAX_PIXELS};
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Adam7 passes: first column and row, then column and row step.
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+struct Header {
+    width: usize,
+    height: usize,
+    depth: usize,
+    color: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let [w0, w1, w2, w3, h0, h1, h2, h3, depth, color, 0, 0, interlace] = *data else {
+            return None;
+        };
+        let header = Header {
+            width: u32::from_be_bytes([w0, w1, w2, w3]) as usize,
+            height: u32::from_be_bytes([h0, h1, h2, h3]) as usize,
+            depth: depth as usize,
+            color,
+            interlaced: match interlace {
+                0 => false,
+                1 => true,
+                _ => return None,
+            },
+        };
+        let valid_depth = match color {
+            0 => matches!(depth, 1 | 2 | 4 | 8 | 16),
+            3 => matches!(depth, 1 | 2 | 4 | 8),
+            2 | 4 | 6 => matches!(depth, 8 | 16),
+            _ => false,
+        };
+        valid_depth.then_some(header)
+    }
+
+    fn channels(&self) -> usize {
+        match self.color {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    /// Bytes in a scanline of `width` pixels, without the filter byte.
+    fn row_bytes(&self, width: usize) -> usize {
+        (width * self.channels() * self.depth).div_ceil(8)
+    }
+
+    /// Bytes per complete pixel, at least 1, for the filters.
+    fn pixel_bytes(&self) -> usize {
+        (self.channels() * self.depth / 8).max(1)
+    }
+
+    /// Passes as (first column, first row, column step, row step).
+    fn passes(&self) -> &'static [(usize, usize, usize, usize)] {
+        if self.interlaced {
+            &ADAM7
+        } else {
+            &[(0, 0, 1, 1)]
+        }
+    }
+
+    /// Size of a pass in pixels.
+    fn pass_size(&self, (x0, y0, dx, dy): (usize, usize, usize, usize)) -> (usize, usize) {
+        (self.width.saturating_sub(x0).div_ceil(dx), self.height.saturating_sub(y0).div_ceil(dy))
+    }
+}
+
+/// Transparency from `tRNS`.
+enum Transparency {
+    None,
+    /// Alpha of the first palette entries.
+    Palette(Vec<u8>),
+    /// The one gray or RGB sample value that is transparent.
+    Key([u16; 3]),
+}
+
+/// Decode a PNG to RGBA; None if it is not one we can read or is larger
+/// than `MAX_PIXELS`.
+pub fn decode(data: &[u8]) -> Option<Pixels> {
+    let mut rest = data.strip_prefix(SIGNATURE)?;
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency = Transparency::None;
+    let mut compressed = Vec::new();
+    loop {
+        let (length, tail) = rest.split_first_chunk::<4>()?;
+        let length = u32::from_be_bytes(*length) as usize;
+        let (kind, tail) = tail.split_first_chunk::<4>()?;
+        if tail.len() < length + 4 {
+            return None;
+        }
+        let (chunk, tail) = tail.split_at(length);
+        rest = &tail[4..];
+        match kind {
+            b"IHDR" => header = Some(Header::parse(chunk)?),
+            b"PLTE" => palette = chunk,
+            b"tRNS" => {
+                let key = |i: usize| chunk.get(i..i + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+                transparency = match header.as_ref()?.color {
+                    3 => Transparency::Palette(chunk.to_vec()),
+                    0 => Transparency::Key([key(0)?; 3]),
+                    2 => Transparency::Key([key(0)?, key(2)?, key(4)?]),
+                    _ => Transparency::None,
+                };
+            }
+            b"IDAT" => compressed.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+    let header = header?;
+    if header.width == 0 || header.height == 0 || header.width * header.height > MAX_PIXELS {
+        return None;
+    }
+    if header.color == 3 && palette.is_empty() {
+        return None;
+    }
+
+    let expected: usize = header
+        .passes()
+        .iter()
+        .map(|&pass| match header.pass_size(pass) {
+            (0, _) | (_, 0) => 0,
+            (width, height) => (header.row_bytes(width) + 1) * height,
+        })
+        .sum();
+    let mut raw = Vec::with_capacity(expected);
+    ZlibDecoder::new(&compressed[..]).take(expected as u64).read_to_end(&mut raw).ok()?;
+    if raw.len() < expected {
+        return None;
+    }
+
+    let mut rgba = vec![0; header.width * header.height * 4];
+    let mut raw = &mut raw[..];
+    for &pass in header.passes() {
+        let (x0, y0, dx, dy) = pass;
+        let (width, height) = header.pass_size(pass);
+        if width == 0 || height == 0 {
+            continue;
+        }
+        let stride = header.row_bytes(width) + 1;
+        let (data, tail) = raw.split_at_mut(stride * height);
+        raw = tail;
+        unfilter(data, stride, header.pixel_bytes())?;
+        for (row, line) in data.chunks_exact(stride).enumerate() {
+            let line = &line[1..];
+            for col in 0..width {
+                let at = ((y0 + row * dy) * header.width + x0 + col * dx) * 4;
+                rgba[at..at + 4].copy_from_slice(&pixel(&header, line, col, palette, &transparency));
+            }
+        }
+    }
+    Some(Pixels { width: header.width, height: header.height, rgba })
+}
+
+/// Undo the per-scanline filters in place. Each scanline of `stride`
+/// bytes starts with its filter type.
+fn unfilter(data: &mut [u8], stride: usize, bpp: usize) -> Option<()> {
+    let mut previous = vec![0; stride - 1];
+    for line in data.chunks_exact_mut(stride) {
+        let (filter, line) = line.split_first_mut()?;
+        for i in 0..line.len() {
+            let left = if i >= bpp { line[i - bpp] } else { 0 };
+            let up = previous[i];
+            let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+            let predicted = match *filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return None,
+            };
+            line[i] = line[i].wrapping_add(predicted);
+        }
+        previous.copy_from_slice(line);
+    }
+    Some(())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// RGBA of pixel `col` of an unfiltered scanline.
+fn pixel(header: &Header, line: &[u8], col: usize, palette: &[u8], transparency: &Transparency) -> [u8; 4] {
+    let channels = header.channels();
+    // Sample `i` of the pixel, as stored
+    let sample = |i: usize| -> u16 {
+        match header.depth {
+            16 => {
+                let at = (col * channels + i) * 2;
+                u16::from_be_bytes([line[at], line[at + 1]])
+            }
+            8 => line[col * channels + i] as u16,
+            depth => {
+                let bit = col * depth;
+                let shift = 8 - depth - bit % 8;
+                ((line[bit / 8] >> shift) & ((1 << depth) - 1) as u8) as u16
+            }
+        }
+    };
+    // Sample scaled to 8 bits
+    let scaled = |i: usize| -> u8 {
+        match header.depth {
+            16 => (sample(i) >> 8) as u8,
+            8 => sample(i) as u8,
+            depth => (sample(i) * 255 / ((1 << depth) - 1)) as u8,
+        }
+    };
+    let opaque = |key: [u16; 3]| match transparency {
+        Transparency::Key(transparent) if *transparent == key => 0,
+        _ => 255,
+    };
+    match header.color {
+        0 => {
+            let gray = scaled(0);
+            [gray, gray, gray, opaque([sample(0); 3])]
+        }
+        2 => [scaled(0), scaled(1), scaled(2), opaque([sample(0), sample(1), sample(2)])],
+        3 => {
+            let index = sample(0) as usize;
+            let [r, g, b] = palette.get(index * 3..index * 3 + 3).map_or([0; 3], |rgb| [rgb[0], rgb[1], rgb[2]]);
+            let alpha = match transparency {
+                Transparency::Palette(alpha) => alpha.get(index).copied().unwrap_or(255),
+                _ => 255,
+            };
+            [r, g, b, alpha]
+        }
+        4 => {
+            let gray = scaled(0);
+            [gray, gray, gray, scaled(1)]
+        }
+        _ => [scaled(0), scaled(1), scaled(2), scaled(3)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A PNG of the given chunks; checksums are left zero, as they are not
+    /// verified.
+    fn png(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut out = SIGNATURE.to_vec();
+        for (kind, data) in chunks {
+            out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            out.extend_from_slice(*kind);
+            out.extend_from_slice(data);
+            out.extend_from_slice(&[0; 4]);
+        }
+        out.extend_from_slice(&[0, 0, 0, 0]);
+        out.extend_from_slice(b"IEND");
+        out.extend_from_slice(&[0; 4]);
+        out
+    }
+
+    fn ihdr(width: u32, height: u32, depth: u8, color: u8, interlaced: bool) -> Vec<u8> {
+        let mut data = width.to_be_bytes().to_vec();
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[depth, color, 0, 0, interlaced as u8]);
+        data
+    }
+
+    /// Scanlines of `stride` bytes, each stored with `filter`.
+    fn filtered(rows: &[u8], stride: usize, bpp: usize, filter: impl Fn(usize) -> u8) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut previous = vec![0; stride];
+        for (y, line) in rows.chunks_exact(stride).enumerate() {
+            let filter = filter(y);
+            out.push(filter);
+            for i in 0..stride {
+                let left = if i >= bpp { line[i - bpp] } else { 0 };
+                let up = previous[i];
+                let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+                let predicted = match filter {
+                    0 => 0,
+                    1 => left,
+                    2 => up,
+                    3 => ((left as u16 + up as u16) / 2) as u8,
+                    _ => paeth(left, up, up_left),
+                };
+                out.push(line[i].wrapping_sub(predicted));
+            }
+            previous.copy_from_slice(line);
+        }
+        out
+    }
+
+    /// A 5x5 RGBA gradient, noisy enough for every filter to matter.
+    fn gradient() -> Vec<u8> {
+        (0..5 * 5 * 4).map(|i| (i * 37 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn every_filter_type() {
+        let rgba = gradient();
+        for filter in 0..5 {
+            let raw = filtered(&rgba, 5 * 4, 4, |_| filter);
+            let image = decode(&png(&[(b"IHDR", &ihdr(5, 5, 8, 6, false)), (b"IDAT", &zlib(&raw))])).unwrap();
+            assert_eq!((image.width, image.height), (5, 5));
+            assert_eq!(image.rgba, rgba, "filter {filter}");
+        }
+        let raw = filtered(&rgba, 5 * 4, 4, |y| y as u8);
+        let image = decode(&png(&[(b"IHDR", &ihdr(5, 5, 8, 6, false)), (b"IDAT", &zlib(&raw))])).unwrap();
+        assert_eq!(image.rgba, rgba);
+    }
+
+    #[test]
+    fn unknown_filter_type_is_rejected() {
+        let raw = filtered(&gradient(), 5 * 4, 4, |_| 5);
+        assert!(decode(&png(&[(b"IHDR", &ihdr(5, 5, 8, 6, false)), (b"IDAT", &zlib(&raw))])).is_none());
+    }
+
+    #[test]
+    fn palette_with_transparency() {
+        // 2-bit indices 0, 1, 2, 1; only the first two entries have alpha
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+        let image = decode(&png(&[
+            (b"IHDR", &ihdr(4, 1, 2, 3, false)),
+            (b"PLTE", &palette),
+            (b"tRNS", &[0, 128]),
+            (b"IDAT", &zlib(&[0, 0b00_01_10_01])),
+        ]))
+        .unwrap();
+        assert_eq!(
+            image.rgba,
+            [[255, 0, 0, 0], [0, 255, 0, 128], [0, 0, 255, 255], [0, 255, 0, 128]].concat()
+        );
+    }
+
+    #[test]
+    fn palette_image_without_palette_is_rejected() {
+        let png = png(&[(b"IHDR", &ihdr(1, 1, 8, 3, false)), (b"IDAT", &zlib(&[0, 0]))]);
+        assert!(decode(&png).is_none());
+    }
+
+    #[test]
+    fn gray_and_rgb_color_keys() {
+        let image = decode(&png(&[
+            (b"IHDR", &ihdr(2, 1, 16, 0, false)),
+            (b"tRNS", &[0x12, 0x34]),
+            (b"IDAT", &zlib(&[0, 0x12, 0x34, 0xff, 0x00])),
+        ]))
+        .unwrap();
+        assert_eq!(image.rgba, [0x12, 0x12, 0x12, 0, 0xff, 0xff, 0xff, 255]);
+
+        let image = decode(&png(&[
+            (b"IHDR", &ihdr(2, 1, 8, 2, false)),
+            (b"tRNS", &[0, 1, 0, 2, 0, 3]),
+            (b"IDAT", &zlib(&[0, 1, 2, 3, 1, 2, 4])),
+        ]))
+        .unwrap();
+        assert_eq!(image.rgba, [1, 2, 3, 0, 1, 2, 4, 255]);
+    }
+
+    #[test]
+    fn adam7_interlacing() {
+        // 10x9 gray, so some passes have a partial last column or row
+        let (width, height) = (10, 9);
+        let gray: Vec<u8> = (0..width * height).map(|i| i as u8).collect();
+        let mut raw = Vec::new();
+        for (x0, y0, dx, dy) in ADAM7 {
+            let rows: Vec<u8> = (y0..height)
+                .step_by(dy)
+                .flat_map(|y| (x0..width).step_by(dx).map(move |x| (x, y)))
+                .map(|(x, y)| gray[y * width + x])
+                .collect();
+            let stride = width.saturating_sub(x0).div_ceil(dx);
+            if stride > 0 && !rows.is_empty() {
+                raw.extend(filtered(&rows, stride, 1, |y| (y % 5) as u8));
+            }
+        }
+        let image = decode(&png(&[
+            (b"IHDR", &ihdr(width as u32, height as u32, 8, 0, true)),
+            (b"IDAT", &zlib(&raw)),
+        ]))
+        .unwrap();
+        let expected: Vec<u8> = gray.iter().flat_map(|&g| [g, g, g, 255]).collect();
+        assert_eq!(image.rgba, expected);
+    }
+
+    #[test]
+    fn truncated_images_are_rejected() {
+        let raw = filtered(&gradient(), 5 * 4, 4, |_| 4);
+        let whole = png(&[(b"IHDR", &ihdr(5, 5, 8, 6, false)), (b"IDAT", &zlib(&raw))]);
+        assert!(decode(&whole).is_some());
+        for len in 0..whole.len() {
+            assert!(decode(&whole[..len]).is_none(), "{len} bytes");
+        }
+        // Complete chunks, but too little image data in them
+        let short = png(&[(b"IHDR", &ihdr(5, 5, 8, 6, false)), (b"IDAT", &zlib(&raw[..raw.len() - 1]))]);
+        assert!(decode(&short).is_none());
+    }
+
+    #[test]
+    fn oversized_and_empty_images_are_rejected() {
+        let idat = zlib(&[0; 64]);
+        for (width, height) in [(4097, 4096), (1 << 20, 1 << 20), (0, 1), (1, 0)] {
+            let png = png(&[(b"IHDR", &ihdr(width, height, 8, 0, false)), (b"IDAT", &idat)]);
+            assert!(decode(&png).is_none(), "{width}x{height}");
+        }
+    }
+}
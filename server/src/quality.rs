@@ -0,0 +1,76 @@
+//! Per-connection link adaptation.
+//!
+//! Clients pick their frame interval and encoding from what they can measure
+//! (RTT, render backlog). The server adds what only it can see: how long
+//! writes to the connection take. Writes that block mean the socket buffer is
+//! full, so frames are spaced further apart until the link drains.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Average write time above which the link counts as saturated.
+const SATURATED_SEND_MS: f64 = 8.0;
+/// Upper bound on the interval the server imposes on its own.
+const MAX_BACKOFF_MS: f64 = 500.0;
+
+/// Frame interval and encoding preference the client sends in `quality`
+/// messages.
+pub struct LinkPreferences {
+    min_interval_ms: AtomicU64,
+    compress: AtomicBool,
+}
+
+impl LinkPreferences {
+    pub fn new() -> Self {
+        LinkPreferences {
+            min_interval_ms: AtomicU64::new(0),
+            compress: AtomicBool::new(true),
+        }
+    }
+
+    pub fn set_min_interval_ms(&self, min_interval_ms: u64) {
+        self.min_interval_ms.store(min_interval_ms, Ordering::Relaxed);
+    }
+
+    /// Shortest interval between frames the client asked for, in ms.
+    pub fn min_interval_ms(&self) -> u64 {
+        self.min_interval_ms.load(Ordering::Relaxed)
+    }
+
+    pub fn set_compress(&self, compress: bool) {
+        self.compress.store(compress, Ordering::Relaxed);
+    }
+
+    /// Whether negotiated compression should currently be applied.
+    pub fn compress(&self) -> bool {
+        self.compress.load(Ordering::Relaxed)
+    }
+}
+
+/// Write timing for one connection.
+pub struct LinkMonitor {
+    /// Smoothed time per frame write, in ms.
+    send_ms: f64,
+}
+
+impl LinkMonitor {
+    pub fn new() -> Self {
+        LinkMonitor { send_ms: 0.0 }
+    }
+
+    pub fn record_send(&mut self, elapsed: Duration) {
+        let sample = elapsed.as_secs_f64() * 1000.0;
+        self.send_ms = 0.75 * self.send_ms + 0.25 * sample;
+    }
+
+    /// Interval between frames: the client's request, stretched to twice the
+    /// write time while writes are blocking.
+    pub fn frame_interval(&self, requested_ms: u64) -> Duration {
+        let backoff_ms = if self.send_ms > SATURATED_SEND_MS {
+            (self.send_ms * 2.0).min(MAX_BACKOFF_MS) as u64
+        } else {
+            0
+        };
+        Duration::from_millis(requested_ms.max(backoff_ms))
+    }
+}
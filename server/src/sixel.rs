@@ -0,0 +1,305 @@
+//! Sixel image decoding.
+//!
+//! A sixel image arrives as `DCS P1 ; P2 ; P3 q <data> ST`. The data is a
+//! stream of sixel characters (`?` to `~`, each six pixels stacked
+//! vertically) interleaved with control functions:
+//!
+//! - `" Pan ; Pad ; Ph ; Pv` raster attributes (aspect ratio and size),
+//! - `# Pc` selects color register `Pc`; `# Pc ; Pu ; Px ; Py ; Pz` also
+//!   defines it, in HLS (`Pu` = 1) or RGB (`Pu` = 2) with 0-100 components,
+//! - `! Pn <char>` repeats a sixel character `Pn` times,
+//! - `$` returns to the start of the band and `-` moves to the next band.
+//!
+//! `P2` = 1 leaves pixels that are never drawn transparent; otherwise they
+//! take the color of register 0. Aspect ratios are ignored: every sixel
+//! pixel is one square image pixel.
+
+use crate::images::Pixels;
+
+/// Longest side of a decoded image; anything beyond it is cropped.
+pub const MAX_SIDE: usize = 2048;
+
+const REGISTERS: usize = 256;
+
+/// VT340 default color registers, in percent.
+const DEFAULT_PALETTE: [[u8; 3]; 16] = [
+    [0, 0, 0],
+    [20, 20, 80],
+    [80, 13, 13],
+    [20, 80, 20],
+    [80, 20, 80],
+    [20, 80, 80],
+    [80, 80, 20],
+    [53, 53, 53],
+    [26, 26, 26],
+    [33, 33, 60],
+    [60, 26, 26],
+    [33, 60, 33],
+    [60, 33, 60],
+    [33, 60, 60],
+    [60, 60, 33],
+    [80, 80, 80],
+];
+
+/// Decode a sixel image from the DCS parameters (before `q`) and data.
+/// None if it draws nothing.
+pub fn decode(params: &[u8], data: &[u8]) -> Option<Pixels> {
+    let transparent = params.split(|&b| b == b';').nth(1) == Some(b"1");
+    let mut decoder = Decoder::new();
+    let mut bytes = data.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'?'..=b'~' => decoder.sixel(byte - b'?', 1),
+            b'!' => {
+                let count = number(&mut bytes).unwrap_or(1).max(1);
+                if let Some(byte @ b'?'..=b'~') = bytes.next() {
+                    decoder.sixel(byte - b'?', count);
+                }
+            }
+            b'#' => {
+                let args = numbers(&mut bytes);
+                decoder.color(&args);
+            }
+            b'"' => {
+                let args = numbers(&mut bytes);
+                if let [_, _, width, height, ..] = args[..] {
+                    decoder.canvas.grow(width.min(MAX_SIDE), height.min(MAX_SIDE));
+                    decoder.raster = (width.min(MAX_SIDE), height.min(MAX_SIDE));
+                }
+            }
+            b'$' => decoder.x = 0,
+            b'-' => {
+                decoder.x = 0;
+                decoder.y += 6;
+            }
+            _ => {}
+        }
+    }
+    decoder.finish(transparent)
+}
+
+struct Decoder {
+    canvas: Canvas,
+    palette: [[u8; 3]; REGISTERS],
+    color: usize,
+    x: usize,
+    y: usize,
+    /// Size from the raster attributes, if given.
+    raster: (usize, usize),
+    /// Extent of the pixels drawn so far.
+    drawn: (usize, usize),
+}
+
+impl Decoder {
+    fn new() -> Self {
+        let mut palette = [[0; 3]; REGISTERS];
+        for (register, color) in palette.iter_mut().zip(DEFAULT_PALETTE) {
+            *register = color.map(percent);
+        }
+        Decoder {
+            canvas: Canvas::default(),
+            palette,
+            color: 0,
+            x: 0,
+            y: 0,
+            raster: (0, 0),
+            drawn: (0, 0),
+        }
+    }
+
+    /// Draw sixel `bits` `count` times at the current position.
+    fn sixel(&mut self, bits: u8, count: usize) {
+        let end = self.x.saturating_add(count).min(MAX_SIDE);
+        if bits != 0 && self.x < end && self.y < MAX_SIDE {
+            let bottom = (self.y + (u8::BITS - bits.leading_zeros()) as usize).min(MAX_SIDE);
+            self.canvas.grow(end, bottom);
+            let [r, g, b] = self.palette[self.color];
+            for row in 0..6 {
+                if bits & (1 << row) == 0 || self.y + row >= MAX_SIDE {
+                    continue;
+                }
+                let start = ((self.y + row) * self.canvas.width + self.x) * 4;
+                for pixel in self.canvas.rgba[start..start + (end - self.x) * 4].chunks_exact_mut(4) {
+                    pixel.copy_from_slice(&[r, g, b, 255]);
+                }
+            }
+            self.drawn = (self.drawn.0.max(end), self.drawn.1.max(bottom));
+        }
+        self.x = self.x.saturating_add(count);
+    }
+
+    /// `#Pc` or `#Pc;Pu;Px;Py;Pz`.
+    fn color(&mut self, args: &[usize]) {
+        let Some(&register) = args.first() else { return };
+        let register = register % REGISTERS;
+        self.color = register;
+        match args[1..] {
+            [1, hue, lightness, saturation, ..] => {
+                self.palette[register] = hls(hue, lightness.min(100), saturation.min(100));
+            }
+            [2, r, g, b, ..] => {
+                self.palette[register] = [r, g, b].map(|c| percent(c.min(100) as u8));
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(mut self, transparent: bool) -> Option<Pixels> {
+        let width = if self.raster.0 > 0 { self.raster.0 } else { self.drawn.0 };
+        let height = if self.raster.1 > 0 { self.raster.1 } else { self.drawn.1 };
+        if width == 0 || height == 0 {
+            return None;
+        }
+        self.canvas.grow(width, height);
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        for row in self.canvas.rgba.chunks_exact(self.canvas.width * 4).take(height) {
+            rgba.extend_from_slice(&row[..width * 4]);
+        }
+        if !transparent {
+            let [r, g, b] = self.palette[0];
+            for pixel in rgba.chunks_exact_mut(4).filter(|pixel| pixel[3] == 0) {
+                pixel.copy_from_slice(&[r, g, b, 255]);
+            }
+        }
+        Some(Pixels { width, height, rgba })
+    }
+}
+
+/// Pixels drawn so far; grows as sixels land outside it.
+#[derive(Default)]
+struct Canvas {
+    width: usize,
+    height: usize,
+    rgba: Vec<u8>,
+}
+
+impl Canvas {
+    /// Make room for at least `width` x `height` pixels, never shrinking.
+    fn grow(&mut self, width: usize, height: usize) {
+        if width <= self.width && height <= self.height {
+            return;
+        }
+        // Grow ahead of need so long images are not copied per band
+        let new_width = if width > self.width { width.max(self.width * 2).min(MAX_SIDE) } else { self.width };
+        let new_height = if height > self.height { height.max(self.height * 2).min(MAX_SIDE) } else { self.height };
+        let mut rgba = vec![0; new_width * new_height * 4];
+        if self.width > 0 {
+            for (old, new) in self.rgba.chunks_exact(self.width * 4).zip(rgba.chunks_exact_mut(new_width * 4)) {
+                new[..old.len()].copy_from_slice(old);
+            }
+        }
+        self.width = new_width;
+        self.height = new_height;
+        self.rgba = rgba;
+    }
+}
+
+/// Parse a decimal number, if one follows.
+fn number(bytes: &mut std::iter::Peekable<impl Iterator<Item = u8>>) -> Option<usize> {
+    let mut value: Option<usize> = None;
+    while let Some(digit @ b'0'..=b'9') = bytes.peek().copied() {
+        bytes.next();
+        value = Some(value.unwrap_or(0).saturating_mul(10).saturating_add((digit - b'0') as usize));
+    }
+    value
+}
+
+/// Parse `;`-separated numbers; empty ones count as 0.
+fn numbers(bytes: &mut std::iter::Peekable<impl Iterator<Item = u8>>) -> Vec<usize> {
+    let mut values = vec![number(bytes).unwrap_or(0)];
+    while bytes.next_if_eq(&b';').is_some() {
+        values.push(number(bytes).unwrap_or(0));
+    }
+    values
+}
+
+fn percent(value: u8) -> u8 {
+    ((value as u32 * 255 + 50) / 100) as u8
+}
+
+/// Sixel HLS (hue 0 = blue, 120 = red, 240 = green) to RGB.
+fn hls(hue: usize, lightness: usize, saturation: usize) -> [u8; 3] {
+    let lightness = lightness as f32 / 100.0;
+    let saturation = saturation as f32 / 100.0;
+    let hue = ((hue + 240) % 360) as f32;
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let x = chroma * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 / 60 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    [r, g, b].map(|c| ((c + m) * 255.0).round().clamp(0.0, 255.0) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(image: &Pixels, x: usize, y: usize) -> [u8; 4] {
+        let at = (y * image.width + x) * 4;
+        image.rgba[at..at + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn raster_attributes_set_the_size() {
+        let image = decode(b"0;1", b"\"1;1;4;3#1;2;100;0;0@").unwrap();
+        assert_eq!((image.width, image.height), (4, 3));
+        assert_eq!(pixel(&image, 0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 1, 0), [0, 0, 0, 0]);
+        assert_eq!(pixel(&image, 0, 2), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn size_follows_the_pixels_drawn_without_raster_attributes() {
+        let image = decode(b"0;1", b"#1;2;100;100;100@-@").unwrap();
+        assert_eq!((image.width, image.height), (1, 7));
+        assert_eq!(pixel(&image, 0, 6), [255, 255, 255, 255]);
+        assert_eq!(pixel(&image, 0, 3), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn repeat_draws_a_run() {
+        let image = decode(b"0;1", b"#1;2;0;100;0!5~$!2B").unwrap();
+        assert_eq!((image.width, image.height), (5, 6));
+        assert!(image.rgba.chunks_exact(4).all(|pixel| pixel == [0, 255, 0, 255]));
+    }
+
+    #[test]
+    fn color_registers() {
+        // Defined in RGB and HLS, selected again later, and the defaults
+        let image = decode(b"0;1", b"#1;2;0;0;100~#2;1;120;50;100~#1~#7~").unwrap();
+        assert_eq!(pixel(&image, 0, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(&image, 1, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 2, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(&image, 3, 0), [135, 135, 135, 255]);
+    }
+
+    #[test]
+    fn undrawn_pixels_take_register_zero_unless_transparent() {
+        let data = b"#0;2;0;100;0#1;2;100;0;0?@";
+        let image = decode(b"0;0", data).unwrap();
+        assert_eq!(pixel(&image, 0, 0), [0, 255, 0, 255]);
+        assert_eq!(pixel(&image, 1, 0), [255, 0, 0, 255]);
+        let image = decode(b"0;1", data).unwrap();
+        assert_eq!(pixel(&image, 0, 0), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn sides_are_clamped_to_max_side() {
+        let image = decode(b"0;1", b"!3000~").unwrap();
+        assert_eq!((image.width, image.height), (MAX_SIDE, 6));
+        let image = decode(b"0;1", b"\"1;1;5000;9000~").unwrap();
+        assert_eq!((image.width, image.height), (MAX_SIDE, MAX_SIDE));
+        assert_eq!(image.rgba.len(), MAX_SIDE * MAX_SIDE * 4);
+    }
+
+    #[test]
+    fn nothing_drawn_is_no_image() {
+        assert!(decode(b"", b"#1;2;0;0;0??$-").is_none());
+    }
+}
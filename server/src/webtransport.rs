@@ -0,0 +1,351 @@
+//! WebTransport (HTTP/3) listener serving the same session protocol as `/ws`.
+//!
+//! A client opens one bidirectional stream after the session is accepted:
+//! - server -> client: frame payloads, each prefixed with a `u32` LE length
+//!   (same JSON/bincode/deflate encoding as WebSocket messages)
+//! - client -> server: records prefixed with a `u32` LE length and a kind byte
+//!   (the client opens with an empty record, as streams are only announced to
+//!   the peer once written to)
+//!
+//! Keystrokes and frame acks travel as datagrams so a lost packet never stalls
+//! the frame stream. Both are cumulative and confirmed by the server, and the
+//! client resends whatever is unconfirmed:
+//! - input: `[KIND_INPUT][offset: u64 LE][bytes]`, confirmed with
+//!   `[KIND_INPUT][received: u64 LE]`
+//! - acks: `[KIND_ACK][rendered frames: u32 LE]`, confirmed with
+//!   `[KIND_ACK][counted: u32 LE]`
+//!
+//! Input that does not fit one datagram is sent as a `KIND_INPUT` record on the
+//! stream instead; offsets let the server drop bytes it has already seen.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use axum::extract::Query;
+use bytes::Bytes;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+use web_transport_quinn::http::StatusCode;
+
+use crate::quality::LinkPreferences;
+use crate::{
+    codec, get_or_create_session, grant_credits, handle_client_message, pump_frames, AppState,
+    FrameSink, PtyCommand, ServerMessage, Session, SessionQuery, StreamOptions, PEER_TIMEOUT,
+};
+
+/// `ClientMessage` in the binary encoding of `codec` (stream only).
+const KIND_MESSAGE: u8 = 0;
+/// Terminal input at a byte offset.
+const KIND_INPUT: u8 = 1;
+/// Cumulative count of rendered frames.
+const KIND_ACK: u8 = 2;
+
+/// Upper bound for a single control record.
+const MAX_RECORD_LEN: usize = 1 << 20;
+/// Browsers only pin certificates valid for at most 14 days.
+const CERT_VALIDITY: Duration = Duration::from_secs(13 * 24 * 60 * 60);
+/// Rotate well before the pinned certificate expires.
+const CERT_ROTATE_INTERVAL: Duration = Duration::from_secs(12 * 24 * 60 * 60);
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// QUIC endpoint with a short-lived self-signed certificate.
+pub struct Listener {
+    endpoint: quinn::Endpoint,
+    hosts: Vec<String>,
+    /// SHA-256 of the current certificate, for `serverCertificateHashes`.
+    cert_hash: RwLock<Vec<u8>>,
+}
+
+impl Listener {
+    pub fn bind(addr: SocketAddr, hosts: Vec<String>) -> anyhow::Result<Arc<Self>> {
+        let (config, cert_hash) = server_config(&hosts)?;
+        let endpoint = quinn::Endpoint::server(config, addr)?;
+        Ok(Arc::new(Self {
+            endpoint,
+            hosts,
+            cert_hash: RwLock::new(cert_hash),
+        }))
+    }
+
+    pub fn port(&self) -> u16 {
+        self.endpoint.local_addr().map(|a| a.port()).unwrap_or(0)
+    }
+
+    pub fn cert_hash(&self) -> Vec<u8> {
+        self.cert_hash.read().unwrap().clone()
+    }
+
+    /// Accept sessions until the endpoint is closed.
+    pub async fn run(self: Arc<Self>, state: AppState) {
+        let rotate = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(CERT_ROTATE_INTERVAL).await;
+                rotate.rotate_certificate();
+            }
+        });
+
+        let mut server = web_transport_quinn::Server::new(self.endpoint.clone());
+        while let Some(request) = server.accept().await {
+            tokio::spawn(handle_request(state.clone(), request));
+        }
+    }
+
+    fn rotate_certificate(&self) {
+        match server_config(&self.hosts) {
+            Ok((config, cert_hash)) => {
+                self.endpoint.set_server_config(Some(config));
+                *self.cert_hash.write().unwrap() = cert_hash;
+                info!("WebTransport certificate rotated");
+            }
+            Err(e) => warn!("WebTransport certificate rotation failed: {:#}", e),
+        }
+    }
+}
+
+fn server_config(hosts: &[String]) -> anyhow::Result<(quinn::ServerConfig, Vec<u8>)> {
+    let (cert, key) = generate_certificate(hosts)?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let cert_hash = web_transport_quinn::crypto::sha256(&provider, &cert)
+        .as_ref()
+        .to_vec();
+
+    let mut tls = rustls::ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key)?;
+    tls.alpn_protocols = vec![web_transport_quinn::ALPN.as_bytes().to_vec()];
+
+    let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(tls)?;
+    let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    let mut transport = quinn::TransportConfig::default();
+    transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+    config.transport_config(Arc::new(transport));
+    Ok((config, cert_hash))
+}
+
+fn generate_certificate(
+    hosts: &[String],
+) -> anyhow::Result<(CertificateDer<'static>, PrivateKeyDer<'static>)> {
+    let mut params = rcgen::CertificateParams::new(hosts.to_vec())?;
+    let now = time::OffsetDateTime::now_utc();
+    params.not_before = now - time::Duration::hours(1);
+    params.not_after = now + CERT_VALIDITY;
+    // ECDSA P-256, as required for certificate hash pinning.
+    let key_pair = rcgen::KeyPair::generate()?;
+    let cert = params.self_signed(&key_pair)?;
+    let key = PrivatePkcs8KeyDer::from(key_pair.serialize_der());
+    Ok((cert.der().clone(), key.into()))
+}
+
+async fn handle_request(state: AppState, request: web_transport_quinn::Request) {
+    let query = request
+        .url
+        .as_str()
+        .parse::<axum::http::Uri>()
+        .ok()
+        .filter(|uri| uri.path() == "/wt")
+        .and_then(|uri| Query::<SessionQuery>::try_from_uri(&uri).ok())
+        .map(|Query(query)| query);
+    let Some(query) = query else {
+        let _ = request.reject(StatusCode::NOT_FOUND).await;
+        return;
+    };
+
+    // Browsers do not attach cookies to WebTransport sessions, so the page
+    // fetches a one-time ticket over authenticated HTTP first.
+    let authorized = match query.ticket.as_deref() {
+        Some(ticket) => state.auth.redeem_ticket(ticket).await,
+        None => false,
+    };
+    if !authorized {
+        let _ = request.reject(StatusCode::UNAUTHORIZED).await;
+        return;
+    }
+
+    let conn = match request.respond(StatusCode::OK).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            warn!("WebTransport handshake failed: {}", e);
+            return;
+        }
+    };
+
+    let options = query.stream_options();
+    let credits = query.credits();
+    let session_id = query.session.unwrap_or_else(|| Uuid::new_v4().to_string());
+    let session = get_or_create_session(&state, &session_id, query.cwd_from.as_deref()).await;
+    handle_session(conn, session, options, credits).await;
+}
+
+async fn handle_session(
+    conn: web_transport_quinn::Session,
+    session: Arc<Session>,
+    options: StreamOptions,
+    credits: Option<u32>,
+) {
+    info!("New WebTransport connection (session={})", session.id);
+
+    let (send, mut recv) = match conn.accept_bi().await {
+        Ok(streams) => streams,
+        Err(e) => {
+            warn!("WebTransport client never opened a stream: {}", e);
+            return;
+        }
+    };
+
+    // Frame credits granted by the client (None = flow control disabled).
+    let credit_tx = Arc::new(watch::channel::<Option<u32>>(credits).0);
+    let (control_tx, control_rx) = mpsc::channel::<ServerMessage>(16);
+    let prefs = Arc::new(LinkPreferences::new());
+
+    // Task: PTY -> stream
+    let mut send_task = tokio::spawn(pump_frames(
+        session.clone(),
+        credit_tx.clone(),
+        control_rx,
+        prefs.clone(),
+        options,
+        StreamFrameSink(send),
+    ));
+
+    // Control records are read on their own task so a partial read is never
+    // cancelled by an incoming datagram.
+    let (record_tx, mut record_rx) = mpsc::channel::<Vec<u8>>(64);
+    let read_task = tokio::spawn(async move {
+        while let Some(record) = read_record(&mut recv).await {
+            if record_tx.send(record).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut peer = PeerState::default();
+    let link = Link {
+        conn: &conn,
+        session: &session,
+        credit_tx: &credit_tx,
+        control_tx: &control_tx,
+        prefs: &prefs,
+    };
+    let mut deadline = tokio::time::Instant::now() + PEER_TIMEOUT;
+    loop {
+        tokio::select! {
+            record = record_rx.recv() => {
+                let Some(record) = record else { break };
+                handle_packet(&link, &mut peer, &record).await;
+            }
+            datagram = conn.read_datagram() => {
+                let Ok(datagram) = datagram else { break };
+                handle_packet(&link, &mut peer, &datagram).await;
+            }
+            _ = tokio::time::sleep_until(deadline) => {
+                warn!("Peer silent for {:?}, closing (session={})", PEER_TIMEOUT, session.id);
+                conn.close(0, b"timeout");
+                break;
+            }
+            _ = &mut send_task => break,
+        }
+        deadline = tokio::time::Instant::now() + PEER_TIMEOUT;
+    }
+
+    send_task.abort();
+    read_task.abort();
+    info!("WebTransport connection closed");
+}
+
+/// Cumulative positions confirmed back to the client.
+#[derive(Default)]
+struct PeerState {
+    /// Input bytes forwarded to the PTY.
+    received: u64,
+    /// Rendered frames counted towards credit.
+    acked: u32,
+}
+
+/// Per-connection handles used while processing client packets.
+struct Link<'a> {
+    conn: &'a web_transport_quinn::Session,
+    session: &'a Session,
+    credit_tx: &'a watch::Sender<Option<u32>>,
+    control_tx: &'a mpsc::Sender<ServerMessage>,
+    prefs: &'a LinkPreferences,
+}
+
+async fn handle_packet(link: &Link<'_>, peer: &mut PeerState, packet: &[u8]) {
+    let Some((&kind, body)) = packet.split_first() else {
+        return;
+    };
+    match kind {
+        KIND_MESSAGE => match codec::decode(body) {
+            Some(msg) => {
+                handle_client_message(link.session, link.credit_tx, link.control_tx, link.prefs, msg)
+                    .await
+            }
+            None => warn!("Failed to decode client message ({} bytes)", body.len()),
+        },
+        KIND_INPUT => {
+            let Some((offset, data)) = body.split_first_chunk::<8>() else {
+                return;
+            };
+            let offset = u64::from_le_bytes(*offset);
+            // Only the part past what we already have is new; a gap means an
+            // earlier datagram was lost and the client will resend from there.
+            let end = offset + data.len() as u64;
+            if offset <= peer.received && end > peer.received {
+                let fresh = &data[(peer.received - offset) as usize..];
+                let _ = link.session.pty_tx.send(PtyCommand::Data(fresh.to_vec())).await;
+                peer.received = end;
+            }
+            confirm(link.conn, KIND_INPUT, &peer.received.to_le_bytes());
+        }
+        KIND_ACK => {
+            let Some(total) = body.first_chunk::<4>() else {
+                return;
+            };
+            let total = u32::from_le_bytes(*total);
+            let delta = total.wrapping_sub(peer.acked);
+            // Ignore stale (reordered) acks.
+            if delta != 0 && delta <= u32::MAX / 2 {
+                peer.acked = total;
+                grant_credits(link.credit_tx, delta);
+            }
+            confirm(link.conn, KIND_ACK, &peer.acked.to_le_bytes());
+        }
+        _ => debug!("Unknown WebTransport packet kind {}", kind),
+    }
+}
+
+fn confirm(conn: &web_transport_quinn::Session, kind: u8, value: &[u8]) {
+    let mut datagram = Vec::with_capacity(1 + value.len());
+    datagram.push(kind);
+    datagram.extend_from_slice(value);
+    let _ = conn.send_datagram(Bytes::from(datagram));
+}
+
+async fn read_record(recv: &mut web_transport_quinn::RecvStream) -> Option<Vec<u8>> {
+    let mut len = [0_u8; 4];
+    recv.read_exact(&mut len).await.ok()?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_RECORD_LEN {
+        warn!("WebTransport record too large ({} bytes)", len);
+        return None;
+    }
+    let mut record = vec![0_u8; len];
+    recv.read_exact(&mut record).await.ok()?;
+    Some(record)
+}
+
+struct StreamFrameSink(web_transport_quinn::SendStream);
+
+impl FrameSink for StreamFrameSink {
+    async fn send_payload(&mut self, payload: Vec<u8>) -> bool {
+        let mut record = Vec::with_capacity(4 + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&payload);
+        self.0.write_all(&record).await.is_ok()
+    }
+}
@@ -0,0 +1,31 @@
+# NoirTTY shell integration for bash (4.4+).
+#
+# Marks prompts, commands and their exit codes (OSC 133) and reports the
+# working directory (OSC 7). Source it at the end of ~/.bashrc, after the
+# prompt is set up:
+#
+#     source /path/to/noirtty.bash
+
+if [[ $- == *i* && -z ${__noirtty_loaded-} ]]; then
+    __noirtty_loaded=1
+    __noirtty_ran=
+
+    __noirtty_precmd() {
+        local status=$?
+        if [[ -n $__noirtty_ran ]]; then
+            printf '\e]133;D;%s\a' "$status"
+            __noirtty_ran=
+        fi
+        printf '\e]7;file://%s%s\a' "$HOSTNAME" "${PWD// /%20}"
+        # Wrap the prompt again if something reassigned it
+        if [[ $PS1 != *'133;B'* ]]; then
+            PS1='\[\e]133;A\a\]'"$PS1"'\[\e]133;B\a\]'
+        fi
+        return $status
+    }
+
+    # PS0 is printed once a command line is read; the arithmetic sets the
+    # flag in this shell and expands to nothing
+    PS0+='\e]133;C\a${__noirtty_ran:0:$((__noirtty_ran=1,0))}'
+    PROMPT_COMMAND="__noirtty_precmd${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+fi
@@ -0,0 +1,24 @@
+# NoirTTY shell integration for fish.
+#
+# Marks prompts, commands and their exit codes (OSC 133) and reports the
+# working directory (OSC 7). fish 4 emits OSC 133 itself; this adds it to
+# older versions. Source it from ~/.config/fish/config.fish:
+#
+#     source /path/to/noirtty.fish
+
+if status is-interactive; and not set -q __noirtty_loaded
+    set -g __noirtty_loaded 1
+
+    function __noirtty_prompt --on-event fish_prompt
+        printf '\e]7;file://%s%s\a' $hostname (string replace -a ' ' '%20' -- $PWD)
+        printf '\e]133;A\a'
+    end
+
+    function __noirtty_preexec --on-event fish_preexec
+        printf '\e]133;C\a'
+    end
+
+    function __noirtty_postexec --on-event fish_postexec
+        printf '\e]133;D;%s\a' $status
+    end
+end